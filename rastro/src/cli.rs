@@ -0,0 +1,483 @@
+use std::error::Error;
+use std::time::Duration;
+use clap::{Args, Parser, Subcommand};
+use serde_json::json;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::time::Instant;
+
+use rastro::config_file::ConnectionSpec;
+use rastro::hub::Hub;
+use rastro::indi::common::IndiState;
+use rastro::indi::condition::Condition;
+use rastro::indi::number_format::parse_number;
+use rastro::indi::pattern::{glob_match, PropertyPattern};
+use rastro::indi::registry::{PropertyElement, PropertyKind, PropertyValue, PropertyVector};
+use rastro::indi::replay::Replay;
+use rastro::indi::session::{Session, SessionEvent};
+use rastro::indi::switch::IndiSwitch;
+use rastro::indi::IncomingMsg;
+use rastro::sequence::journal::{Journal, ResumeMode};
+use rastro::sequence::{Plan, Sequencer, Step};
+
+pub type CliResult<T> = Result<T, Box<dyn Error + Send + Sync>>;
+
+/// `get` stops once nothing new arrived for this long.
+const QUIET: Duration = Duration::from_millis(500);
+
+#[derive(Parser, Debug)]
+#[command(name = "rastro", about = "INDI client for observatory automation")]
+pub struct Cli {
+    /// Config file, instead of $RASTRO_CONFIG or the XDG config path.
+    #[arg(long, global = true)]
+    pub config: Option<std::path::PathBuf>,
+
+    /// Profile of the config file to use.
+    #[arg(long, global = true)]
+    pub profile: Option<String>,
+
+    /// Talk to this INDI server (host, host:port or [IPv6]:port) instead of the configured connections.
+    #[arg(long, short = 's', global = true)]
+    pub server: Option<String>,
+
+    /// Append every message of the connection to this session file, for `rastro replay`.
+    #[arg(long, global = true)]
+    pub record: Option<std::path::PathBuf>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Show the devices of every configured server in a dashboard and edit their properties (the default).
+    Run,
+    /// Print properties, like indi_getprop.
+    Get(GetArgs),
+    /// Set properties and wait for the driver to accept them, like indi_setprop.
+    Set(SetArgs),
+    /// Print properties as they change.
+    Watch(WatchArgs),
+    /// Block until a condition over property values holds, like indi_eval -w.
+    Wait(WaitArgs),
+    /// Take the frames of an exposure plan and save them.
+    Sequence(SequenceArgs),
+    /// Serve a session recorded with --record to INDI clients, like the server that was recorded.
+    Replay(ReplayArgs),
+    /// Serve the devices of every configured server to INDI clients, as one server.
+    Hub(HubArgs),
+}
+
+#[derive(Args, Debug)]
+pub struct GetArgs {
+    /// device.property.element, each part may use * and ? wildcards.
+    #[arg(default_value = "*.*.*")]
+    pub patterns: Vec<PropertyPattern>,
+    /// Seconds to wait for the properties to be defined.
+    #[arg(long, short = 't', default_value_t = 2.0)]
+    pub timeout: f64,
+    #[arg(long)]
+    pub json: bool,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct SetArgs {
+    /// device.property.element=value, elements of the same property are sent together.
+    #[arg(required = true)]
+    pub assignments: Vec<String>,
+    /// Seconds to wait for the property to be defined.
+    #[arg(long, short = 't', default_value_t = 5.0)]
+    pub timeout: f64,
+}
+
+#[derive(Args, Debug)]
+pub struct WatchArgs {
+    /// device.property.element, each part may use * and ? wildcards.
+    #[arg(default_value = "*.*.*")]
+    pub patterns: Vec<PropertyPattern>,
+    /// One JSON object per changed element and line.
+    #[arg(long)]
+    pub json: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct WaitArgs {
+    /// For example "CCD Simulator.CCD_TEMPERATURE.CCD_TEMPERATURE_VALUE < -9.5", `_STATE` is the vector state.
+    pub condition: Condition,
+    /// Seconds to wait before failing.
+    #[arg(long, short = 't', default_value_t = 60.0)]
+    pub timeout: f64,
+}
+
+#[derive(Args, Debug)]
+pub struct SequenceArgs {
+    /// TOML plan, see `rastro::sequence::Plan`.
+    #[arg(required_unless_present = "steps")]
+    pub plan: Option<std::path::PathBuf>,
+    /// Camera device, for plans given with --step.
+    #[arg(long, default_value = "CCD Simulator")]
+    pub camera: String,
+    #[arg(long)]
+    pub filter_wheel: Option<String>,
+    /// A step like "20x300s, filter Ha, bin 1, gain 100", instead of a plan file.
+    #[arg(long = "step", conflicts_with = "plan")]
+    pub steps: Vec<Step>,
+    #[arg(long)]
+    pub target: Option<String>,
+    /// Directory template, overrides the one of the plan.
+    #[arg(long)]
+    pub directory: Option<String>,
+    /// Journal to record progress in and resume from, defaults to the plan file with a `.journal` extension.
+    #[arg(long)]
+    pub journal: Option<std::path::PathBuf>,
+    /// Ignore what the journal recorded and take every frame.
+    #[arg(long)]
+    pub fresh: bool,
+    /// Do not take an exposure again that an earlier run was interrupted in.
+    #[arg(long)]
+    pub skip_partial: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct ReplayArgs {
+    /// Session file written with --record.
+    pub session: std::path::PathBuf,
+    #[arg(long, short = 'p', default_value_t = 7624)]
+    pub port: u16,
+    /// Play this many times faster than recorded, 0 for no pauses at all.
+    #[arg(long, default_value_t = 1.0)]
+    pub speed: f64,
+    /// Fail a client that does not send what the recorded client sent.
+    #[arg(long)]
+    pub check: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct HubArgs {
+    #[arg(long, short = 'p', default_value_t = 7624)]
+    pub port: u16,
+}
+
+/// `--server host[:port]` as a connection that does not reconnect, IPv6 addresses with a port go in brackets.
+pub fn server_spec(server: &str) -> CliResult<ConnectionSpec> {
+    let parse_port = |port: &str| port.parse().map_err(|_| format!("{} is not a port", port));
+    let (host, port) = if let Some(bracketed) = server.strip_prefix('[') {
+        match bracketed.split_once(']') {
+            Some((host, "")) => (host, 7624),
+            Some((host, rest)) => match rest.strip_prefix(':') {
+                Some(port) => (host, parse_port(port)?),
+                None => return Err(format!("{} is not host[:port]", server).into()),
+            },
+            None => return Err(format!("{} lacks a closing ]", server).into()),
+        }
+    } else {
+        match server.split_once(':') {
+            //a bare IPv6 address
+            Some((_, rest)) if rest.contains(':') => (server, 7624),
+            Some((host, port)) => (host, parse_port(port)?),
+            None => (server, 7624),
+        }
+    };
+    let mut spec = ConnectionSpec::new(host, host, port);
+    spec.reconnect.enabled = Some(false);
+    spec.validate()?;
+    Ok(spec)
+}
+
+pub fn json_value(value: &PropertyValue) -> serde_json::Value {
+    match value {
+        PropertyValue::Number { value, .. } => json!(value),
+        PropertyValue::Switch(value) => json!(value),
+        PropertyValue::Text(value) => json!(value),
+        PropertyValue::Light(value) => json!(value),
+        PropertyValue::Blob { format, size, stored, .. } => json!({
+            "format": format,
+            "size": size,
+            "path": stored.as_ref().and_then(|s| s.path.as_ref()),
+        }),
+    }
+}
+
+fn json_element(connection: &str, vector: &PropertyVector, element: &PropertyElement) -> serde_json::Value {
+    json!({
+        "connection": connection,
+        "device": vector.device,
+        "property": vector.name,
+        "element": element.name,
+        "value": json_value(&element.value),
+        "state": vector.state,
+        "timestamp": vector.timestamp,
+    })
+}
+
+/// Matching elements of every connection, tagged with the connection name.
+fn find(session: &Session, pattern: &PropertyPattern) -> Vec<(String, PropertyVector, PropertyElement)> {
+    let mut found = Vec::new();
+    for connection in session.connections() {
+        if let Some(client) = session.client(connection) {
+            let registry = client.registry();
+            for (vector, element) in pattern.find(&registry) {
+                found.push((connection.to_string(), vector.clone(), element.clone()));
+            }
+        }
+    }
+    found
+}
+
+pub async fn get(session: &Session, args: &GetArgs) -> CliResult<()> {
+    let mut events = session.subscribe();
+    session.get_properties(None, None).await?;
+
+    let deadline = Instant::now() + Duration::from_secs_f64(args.timeout);
+    let exact = args.patterns.iter().all(|p| !p.has_wildcards());
+    let mut heard = false;
+    loop {
+        if exact && args.patterns.iter().all(|p| !find(session, p).is_empty()) {
+            break;
+        }
+        let wait = if heard { deadline.min(Instant::now() + QUIET) } else { deadline };
+        match tokio::time::timeout_at(wait, events.recv()).await {
+            Err(_) | Ok(Err(RecvError::Closed)) => break,
+            Ok(_) => heard = true,
+        }
+    }
+
+    let mut any = false;
+    for pattern in &args.patterns {
+        for (connection, vector, element) in find(session, pattern) {
+            any = true;
+            if args.json {
+                println!("{}", json_element(&connection, &vector, &element));
+            } else {
+                println!("{}.{}.{}={}", vector.device, vector.name, element.name, element.value);
+            }
+        }
+    }
+    if !any {
+        return Err("no property matches".into());
+    }
+    Ok(())
+}
+
+/// `(element, value)` pairs of one property.
+type ElementValues = Vec<(String, String)>;
+
+/// Splits `device.property.element=value`.
+pub fn parse_assignment(assignment: &str) -> CliResult<(PropertyPattern, String)> {
+    let (target, value) = assignment
+        .split_once('=')
+        .ok_or_else(|| format!("{} is not device.property.element=value", assignment))?;
+    let target: PropertyPattern = target.parse()?;
+    if target.has_wildcards() {
+        return Err(format!("{} has wildcards, set needs exact names", target).into());
+    }
+    Ok((target, value.to_string()))
+}
+
+pub async fn set(session: &Session, args: &SetArgs) -> CliResult<()> {
+    //elements of one property go out in one new*Vector
+    let mut groups: Vec<((String, String), ElementValues)> = Vec::new();
+    for assignment in &args.assignments {
+        let (target, value) = parse_assignment(assignment)?;
+        let key = (target.device, target.property);
+        match groups.iter_mut().find(|(k, _)| *k == key) {
+            Some((_, values)) => values.push((target.element, value)),
+            None => groups.push((key, vec![(target.element, value)])),
+        }
+    }
+
+    session.get_properties(None, None).await?;
+    let timeout = Duration::from_secs_f64(args.timeout);
+    for ((device, property), values) in groups {
+        session.wait_for_property(&device, &property, timeout).await?;
+        let kind = session.property(&device, &property).map(|p| p.kind);
+        let state = match kind {
+            Some(PropertyKind::Number) => {
+                let numbers = values
+                    .iter()
+                    .map(|(e, v)| parse_number(v).map(|n| (e.as_str(), n)).ok_or_else(|| format!("{} is not a number", v)))
+                    .collect::<Result<Vec<_>, _>>()?;
+                session.set_number(&device, &property, &numbers).await?
+            },
+            Some(PropertyKind::Switch) => {
+                let switches = values
+                    .iter()
+                    .map(|(e, v)| match v.to_ascii_lowercase().as_str() {
+                        "on" => Ok((e.as_str(), IndiSwitch::On)),
+                        "off" => Ok((e.as_str(), IndiSwitch::Off)),
+                        _ => Err(format!("{} is not On or Off", v)),
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                session.set_switch(&device, &property, &switches).await?
+            },
+            Some(PropertyKind::Text) => {
+                let texts: Vec<_> = values.iter().map(|(e, v)| (e.as_str(), v.as_str())).collect();
+                session.set_text(&device, &property, &texts).await?
+            },
+            kind => return Err(format!("{}.{} is a {:?} property and cannot be set", device, property, kind).into()),
+        };
+        if state != IndiState::Ok {
+            return Err(format!("{}.{} ended {:?}", device, property, state).into());
+        }
+    }
+    Ok(())
+}
+
+fn watched(patterns: &[PropertyPattern], msg: &IncomingMsg) -> bool {
+    match msg.target() {
+        Some((device, Some(property))) => patterns.iter().any(|p| p.matches_property(device, property)),
+        Some((device, None)) => patterns.iter().any(|p| glob_match(&p.device, device)),
+        None => false,
+    }
+}
+
+pub async fn watch(session: &Session, args: &WatchArgs) -> CliResult<()> {
+    let mut events = session.subscribe();
+    session.get_properties(None, None).await?;
+    let tagged = session.connections().count() > 1;
+
+    loop {
+        let event = tokio::select! {
+            event = events.recv() => event,
+            _ = tokio::signal::ctrl_c() => break,
+        };
+        let (connection, msg) = match event {
+            Ok(SessionEvent::Message { connection, msg }) => (connection, msg),
+            Ok(SessionEvent::State { connection, state }) => {
+                log::info!("[{}] {:?}", connection, state);
+                continue;
+            },
+            Err(RecvError::Lagged(n)) => {
+                log::warn!("dropped {} updates", n);
+                continue;
+            },
+            Err(RecvError::Closed) => break,
+        };
+        if !watched(&args.patterns, &msg) {
+            continue;
+        }
+
+        if !args.json {
+            let text = msg.to_string();
+            if tagged {
+                println!("[{}] {}", connection, text.trim_end());
+            } else {
+                println!("{}", text.trim_end());
+            }
+            continue;
+        }
+
+        match &*msg {
+            IncomingMsg::Message(m) => println!("{}", json!({
+                "connection": connection, "device": m.device, "message": m.message, "timestamp": m.timestamp,
+            })),
+            IncomingMsg::DelProperty(d) => println!("{}", json!({
+                "connection": connection, "device": d.device, "property": d.name, "deleted": true,
+            })),
+            msg => if let Some((device, Some(property))) = msg.target() {
+                let vector = session.client(&connection).and_then(|c| c.registry().property(device, property).cloned());
+                if let Some(vector) = vector {
+                    for element in &vector.elements {
+                        if args.patterns.iter().any(|p| p.matches(device, property, &element.name)) {
+                            println!("{}", json_element(&connection, &vector, element));
+                        }
+                    }
+                }
+            },
+        }
+    }
+    Ok(())
+}
+
+pub async fn wait(session: &Session, args: &WaitArgs) -> CliResult<()> {
+    session.get_properties(None, None).await?;
+    session.wait_until(&args.condition, Duration::from_secs_f64(args.timeout)).await?;
+    Ok(())
+}
+
+pub fn plan(args: &SequenceArgs) -> CliResult<Plan> {
+    let mut plan = match &args.plan {
+        Some(path) => Plan::load(path)?,
+        None => {
+            let mut plan = Plan::new(&args.camera, args.steps.clone());
+            plan.filter_wheel = args.filter_wheel.clone();
+            plan
+        },
+    };
+    if let Some(target) = &args.target {
+        plan.target = target.clone();
+    }
+    if let Some(directory) = &args.directory {
+        plan.directory = directory.clone();
+    }
+    plan.validate()?;
+    Ok(plan)
+}
+
+pub async fn sequence(session: &Session, plan: Plan, args: &SequenceArgs, start_timeout: Duration) -> CliResult<()> {
+    let journal = args.journal.clone().or_else(|| args.plan.as_ref().map(|p| p.with_extension("journal")));
+    let mut sequencer = Sequencer::new(session, plan);
+    if let Some(path) = journal {
+        let journal = if args.fresh { Journal::create(&path, sequencer.plan())? } else { Journal::open(&path, sequencer.plan())? };
+        if journal.done() > 0 {
+            eprintln!("resuming from {}, {} frames are done", path.display(), journal.done());
+        }
+        let mode = if args.skip_partial { ResumeMode::Skip } else { ResumeMode::Retake };
+        sequencer = sequencer.with_journal(journal, mode);
+    }
+    if sequencer.remaining().is_empty() {
+        eprintln!("all frames are done");
+        return Ok(());
+    }
+
+    session.start(start_timeout).await?;
+    session.wait_for_property(&sequencer.plan().camera, "CCD_EXPOSURE", start_timeout).await?;
+    let total = sequencer.plan().frames().len();
+    sequencer.run(|captured| println!("[{}/{}] {}", captured.frame.index + 1, total, captured.path.display())).await?;
+    Ok(())
+}
+
+pub async fn replay(args: &ReplayArgs) -> CliResult<()> {
+    let speed = if args.speed > 0.0 { args.speed } else { f64::INFINITY };
+    let replay = Replay::load(&args.session)?.with_speed(speed).with_check(args.check);
+    let listener = tokio::net::TcpListener::bind(("0.0.0.0", args.port)).await?;
+    eprintln!("replaying {} records of {} on port {}", replay.records().len(), args.session.display(), args.port);
+    replay.serve_tcp(listener).await?;
+    Ok(())
+}
+
+pub async fn hub(connections: &[ConnectionSpec], args: &HubArgs) -> CliResult<()> {
+    let hub = Hub::connect(connections).await;
+    let listener = tokio::net::TcpListener::bind(("0.0.0.0", args.port)).await?;
+    let names: Vec<&str> = connections.iter().map(|c| c.name.as_str()).collect();
+    eprintln!("serving {} on port {}", names.join(", "), args.port);
+    hub.serve_tcp(listener).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cli::{json_value, parse_assignment, server_spec};
+    use rastro::indi::registry::PropertyValue;
+
+    #[test]
+    fn it_parses_arguments() {
+        let (target, value) = parse_assignment("CCD Simulator.CCD_EXPOSURE.CCD_EXPOSURE_VALUE=1.5").unwrap();
+        assert_eq!(target.to_string(), "CCD Simulator.CCD_EXPOSURE.CCD_EXPOSURE_VALUE");
+        assert_eq!(value, "1.5");
+        assert!(parse_assignment("CCD Simulator.CCD_EXPOSURE.*=1").is_err());
+        assert!(parse_assignment("CCD Simulator.CCD_EXPOSURE").is_err());
+
+        let spec = server_spec("mobile-mini.local:7625").unwrap();
+        assert_eq!((spec.host.as_str(), spec.port), ("mobile-mini.local", 7625));
+        assert_eq!(server_spec("localhost").unwrap().port, 7624);
+        let spec = server_spec("[::1]:7625").unwrap();
+        assert_eq!((spec.host.as_str(), spec.port, spec.address().as_str()), ("::1", 7625, "[::1]:7625"));
+        let spec = server_spec("fe80::1").unwrap();
+        assert_eq!((spec.host.as_str(), spec.port), ("fe80::1", 7624));
+        assert_eq!(server_spec("[::1]").unwrap().port, 7624);
+        assert!(server_spec("[::1").is_err() && server_spec("[::1]7625").is_err());
+
+        let number = PropertyValue::Number { value: -10.5, format: "%g".to_string(), min: 0.0, max: 0.0, step: 0.0 };
+        assert_eq!(json_value(&number).to_string(), "-10.5");
+    }
+}
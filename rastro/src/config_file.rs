@@ -1,50 +1,389 @@
-use std::error::Error;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, Debug)]
+use crate::indi::blob_stream::BlobStorage;
+use crate::indi::enable_blob::EnableBLOBValue;
+use crate::indi::reconnect::ReconnectPolicy;
+
+/// Names the config file explicitly, like `--config`.
+pub const CONFIG_ENV: &str = "RASTRO_CONFIG";
+/// Selects a profile, like `--profile`.
+pub const PROFILE_ENV: &str = "RASTRO_PROFILE";
+/// Prefix of per-connection overrides, e.g. `RASTRO_MOBILE_MINI_HOST` for the connection `mobile-mini`.
+pub const ENV_PREFIX: &str = "RASTRO_";
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub enum ConnectionProtocol {
     #[serde(rename = "indi")]
     InstrumentNeutralDistributedInterface
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+/// Reconnect settings as written in the config file, unset fields keep the `ReconnectPolicy` defaults.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ReconnectSpec {
+    pub enabled: Option<bool>,
+    pub initial_delay_ms: Option<u64>,
+    pub max_delay_ms: Option<u64>,
+    pub multiplier: Option<f64>,
+    pub max_attempts: Option<usize>,
+    pub resync_quiet_ms: Option<u64>,
+}
+
+impl ReconnectSpec {
+    pub fn policy(&self) -> ReconnectPolicy {
+        let default = ReconnectPolicy::default();
+        ReconnectPolicy {
+            enabled: self.enabled.unwrap_or(default.enabled),
+            initial_delay: self.initial_delay_ms.map(Duration::from_millis).unwrap_or(default.initial_delay),
+            max_delay: self.max_delay_ms.map(Duration::from_millis).unwrap_or(default.max_delay),
+            multiplier: self.multiplier.unwrap_or(default.multiplier),
+            max_attempts: self.max_attempts.or(default.max_attempts),
+            resync_quiet: self.resync_quiet_ms.map(Duration::from_millis).unwrap_or(default.resync_quiet),
+        }
+    }
+}
+
+fn default_blob_mode() -> EnableBLOBValue {
+    EnableBLOBValue::Also
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
 pub struct ConnectionSpec {
     pub name: String,
     pub protocol: ConnectionProtocol,
     pub host: String,
-    pub port: usize
+    pub port: usize,
+
+    /// Sent as `enableBLOB` for every device once connected.
+    #[serde(default = "default_blob_mode")]
+    pub blob_mode: EnableBLOBValue,
+    /// BLOBs are streamed into this directory instead of being kept in memory.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blob_dir: Option<PathBuf>,
+    /// Every message sent and received is appended to this session file without BLOB payloads, see `rastro replay`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub record: Option<PathBuf>,
+    /// Devices whose `CONNECTION` switch is turned on once they are defined.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub auto_connect: Vec<String>,
+    #[serde(default)]
+    pub reconnect: ReconnectSpec,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct ConfigFile {
+impl ConnectionSpec {
+    /// An INDI connection with default options.
+    pub fn new(name: &str, host: &str, port: usize) -> ConnectionSpec {
+        ConnectionSpec {
+            name: name.to_string(),
+            protocol: ConnectionProtocol::InstrumentNeutralDistributedInterface,
+            host: host.to_string(),
+            port,
+            blob_mode: default_blob_mode(),
+            blob_dir: None,
+            record: None,
+            auto_connect: Vec::new(),
+            reconnect: ReconnectSpec::default(),
+        }
+    }
+
+    /// `host:port` to connect to, with IPv6 addresses in brackets.
+    pub fn address(&self) -> String {
+        if self.host.contains(':') {
+            format!("[{}]:{}", self.host, self.port)
+        } else {
+            format!("{}:{}", self.host, self.port)
+        }
+    }
+
+    pub fn reconnect_policy(&self) -> ReconnectPolicy {
+        self.reconnect.policy()
+    }
+
+    pub fn blob_storage(&self) -> BlobStorage {
+        match &self.blob_dir {
+            Some(dir) => BlobStorage::Directory(dir.clone()),
+            None => BlobStorage::InMemory,
+        }
+    }
+
+    fn invalid(&self, what: String) -> ConfigError {
+        ConfigError::Invalid(format!("connection '{}': {}", self.name, what))
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.name.trim().is_empty() {
+            return Err(ConfigError::Invalid("connection without a name".to_string()));
+        }
+        if self.host.trim().is_empty() || self.host.contains(char::is_whitespace) {
+            return Err(self.invalid(format!("host {:?} is not a host name or address", self.host)));
+        }
+        if !(1..=65535).contains(&self.port) {
+            return Err(self.invalid(format!("port {} is not in 1..=65535", self.port)));
+        }
+        if let Some(device) = self.auto_connect.iter().find(|d| d.trim().is_empty()) {
+            return Err(self.invalid(format!("auto_connect has an empty device name {:?}", device)));
+        }
+        let policy = self.reconnect_policy();
+        if !policy.multiplier.is_finite() || policy.multiplier < 1.0 {
+            return Err(self.invalid(format!("reconnect multiplier {} is less than 1", policy.multiplier)));
+        }
+        if policy.initial_delay > policy.max_delay {
+            return Err(self.invalid("reconnect initial_delay_ms is larger than max_delay_ms".to_string()));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    #[serde(default)]
     pub connections: Vec<ConnectionSpec>
 }
 
-impl ConfigFile {
-    pub fn load_default() -> Result<ConfigFile, Box<dyn Error>> {
-        toml::from_str(r###"
-            #[[connections]]
-            #name = "mobile-mini"
-            #protocol = "indi"
-            #host = "mobile-mini.local"
-            #port = 7624
+/**
+The rastro config file.
+
+The top level `connections` are used unless a profile is selected, by name or through
+`default_profile`.
+
+```toml
+default_profile = "field"
+
+[[connections]]
+name = "local"
+protocol = "indi"
+host = "localhost"
+port = 7624
+
+[[profiles.field.connections]]
+name = "mount"
+protocol = "indi"
+host = "mobile-mini.local"
+port = 7624
+auto_connect = ["Telescope Simulator"]
+reconnect = { initial_delay_ms = 1000, max_attempts = 10 }
+```
+*/
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigFile {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_profile: Option<String>,
+    #[serde(default)]
+    pub connections: Vec<ConnectionSpec>,
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io { path: PathBuf, source: std::io::Error },
+    Parse { path: PathBuf, source: toml::de::Error },
+    UnknownProfile(String),
+    Invalid(String),
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::Io { path, source } => write!(f, "could not read {}: {}", path.display(), source),
+            ConfigError::Parse { path, source } => write!(f, "could not parse {}: {}", path.display(), source),
+            ConfigError::UnknownProfile(name) => write!(f, "there is no profile named '{}'", name),
+            ConfigError::Invalid(what) => write!(f, "invalid config: {}", what),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ConfigError::Io { source, .. } => Some(source),
+            ConfigError::Parse { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
 
+/// `mobile-mini` becomes `MOBILE_MINI`.
+fn env_name(connection: &str) -> String {
+    connection
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+        .collect()
+}
+
+impl ConfigFile {
+    /// Built in config used when there is no config file.
+    pub fn load_default() -> Result<ConfigFile, ConfigError> {
+        ConfigFile::parse(Path::new("<default>"), r###"
             [[connections]]
             name = "local"
             protocol = "indi"
             host = "localhost"
             port = 7624
-        "###).map_err(Into::into)
+        "###)
+    }
+
+    /// `$XDG_CONFIG_HOME/rastro/config.toml` or the platform equivalent.
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("rastro").join("config.toml"))
+    }
+
+    /**
+    Loads `path`, or the file named by `RASTRO_CONFIG`, or the one at `default_path`.
+
+    Only a missing file at the default path falls back to `load_default`.
+    */
+    pub fn load(path: Option<&Path>) -> Result<ConfigFile, ConfigError> {
+        let explicit = path.map(Path::to_path_buf).or_else(|| std::env::var_os(CONFIG_ENV).map(PathBuf::from));
+        let path = match explicit {
+            Some(path) => path,
+            None => match ConfigFile::default_path() {
+                Some(path) if path.exists() => path,
+                _ => return ConfigFile::load_default(),
+            },
+        };
+
+        let text = std::fs::read_to_string(&path).map_err(|source| ConfigError::Io { path: path.clone(), source })?;
+        ConfigFile::parse(&path, &text)
+    }
+
+    pub fn parse(path: &Path, text: &str) -> Result<ConfigFile, ConfigError> {
+        let config: ConfigFile = toml::from_str(text).map_err(|source| ConfigError::Parse { path: path.to_path_buf(), source })?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if let Some(name) = &self.default_profile {
+            if !self.profiles.contains_key(name) {
+                return Err(ConfigError::UnknownProfile(name.clone()));
+            }
+        }
+        let lists = std::iter::once(("top level", &self.connections))
+            .chain(self.profiles.iter().map(|(name, p)| (name.as_str(), &p.connections)));
+        for (list, connections) in lists {
+            let mut names = HashSet::new();
+            for spec in connections {
+                spec.validate()?;
+                if !names.insert(&spec.name) {
+                    return Err(ConfigError::Invalid(format!("connection '{}' appears twice in {} connections", spec.name, list)));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /**
+    The connections of `profile`, or of `RASTRO_PROFILE`, `default_profile` or the top level,
+    in that order, with the environment overrides applied.
+    */
+    pub fn connections(&self, profile: Option<&str>) -> Result<Vec<ConnectionSpec>, ConfigError> {
+        let env_profile = std::env::var(PROFILE_ENV).ok();
+        let profile = profile.or(env_profile.as_deref()).or(self.default_profile.as_deref());
+        let mut connections = match profile {
+            Some(name) => self.profiles
+                .get(name)
+                .ok_or_else(|| ConfigError::UnknownProfile(name.to_string()))?
+                .connections
+                .clone(),
+            None => self.connections.clone(),
+        };
+        apply_env(&mut connections, std::env::vars())?;
+        Ok(connections)
     }
 }
 
+/// Applies `RASTRO_<NAME>_HOST`, `_PORT` and `_BLOB_MODE` overrides to the connection `<NAME>`.
+pub fn apply_env(connections: &mut [ConnectionSpec], vars: impl Iterator<Item = (String, String)>) -> Result<(), ConfigError> {
+    let vars: BTreeMap<String, String> = vars.filter(|(k, _)| k.starts_with(ENV_PREFIX)).collect();
+    for spec in connections.iter_mut() {
+        let prefix = format!("{}{}_", ENV_PREFIX, env_name(&spec.name));
+        if let Some(host) = vars.get(&format!("{}HOST", prefix)) {
+            spec.host = host.clone();
+        }
+        if let Some(port) = vars.get(&format!("{}PORT", prefix)) {
+            spec.port = port.trim().parse()
+                .map_err(|_| spec.invalid(format!("{}PORT={:?} is not a port", prefix, port)))?;
+        }
+        if let Some(mode) = vars.get(&format!("{}BLOB_MODE", prefix)) {
+            spec.blob_mode = match mode.to_ascii_lowercase().as_str() {
+                "never" | "none" => EnableBLOBValue::Never,
+                "only" => EnableBLOBValue::Only,
+                "also" => EnableBLOBValue::Also,
+                _ => return Err(spec.invalid(format!("{}BLOB_MODE={:?} is not Never, Only or Also", prefix, mode))),
+            };
+        }
+        spec.validate()?;
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use std::error::Error;
-    use crate::config_file::ConfigFile;
+    use std::path::Path;
+    use std::time::Duration;
+    use crate::config_file::{apply_env, ConfigError, ConfigFile};
+    use crate::indi::enable_blob::EnableBLOBValue;
 
     #[test]
     fn it_loads_default_config_file() -> Result<(), Box<dyn Error>> {
-        ConfigFile::load_default().map(|_|())
+        ConfigFile::load_default()?;
+        Ok(())
+    }
+
+    #[test]
+    fn it_selects_profiles_and_applies_overrides() -> Result<(), Box<dyn Error>> {
+        let config = ConfigFile::parse(Path::new("test.toml"), r#"
+            [[connections]]
+            name = "local"
+            protocol = "indi"
+            host = "localhost"
+            port = 7624
+
+            [[profiles.field.connections]]
+            name = "mobile-mini"
+            protocol = "indi"
+            host = "mobile-mini.local"
+            port = 7624
+            blob_mode = "Never"
+            auto_connect = ["Telescope Simulator"]
+            reconnect = { initial_delay_ms = 1000, max_attempts = 3 }
+        "#)?;
+
+        assert_eq!(config.connections(None)?[0].name, "local");
+        let mut field = config.connections(Some("field"))?;
+        assert_eq!(field[0].blob_mode, EnableBLOBValue::Never);
+        assert_eq!(field[0].auto_connect, vec!["Telescope Simulator"]);
+        assert_eq!(field[0].reconnect_policy().initial_delay, Duration::from_secs(1));
+        assert_eq!(field[0].reconnect_policy().max_attempts, Some(3));
+        assert!(matches!(config.connections(Some("home")), Err(ConfigError::UnknownProfile(_))));
+
+        let vars = [("RASTRO_MOBILE_MINI_HOST", "10.0.0.2"), ("RASTRO_MOBILE_MINI_BLOB_MODE", "also"), ("HOME", "/root")];
+        apply_env(&mut field, vars.iter().map(|(k, v)| (k.to_string(), v.to_string())))?;
+        assert_eq!(field[0].host, "10.0.0.2");
+        assert_eq!(field[0].blob_mode, EnableBLOBValue::Also);
+
+        let vars = [("RASTRO_MOBILE_MINI_PORT", "seventy")];
+        assert!(apply_env(&mut field, vars.iter().map(|(k, v)| (k.to_string(), v.to_string()))).is_err());
+        Ok(())
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn it_rejects_invalid_connections() {
+        let parse = |connection: &str| ConfigFile::parse(Path::new("test.toml"), &format!("[[connections]]\n{}", connection));
+
+        let err = parse("name = \"local\"\nprotocol = \"indi\"\nhost = \"localhost\"\nport = 70000").unwrap_err();
+        assert_eq!(err.to_string(), "invalid config: connection 'local': port 70000 is not in 1..=65535");
+
+        assert!(parse("name = \"local\"\nprotocol = \"indi\"\nhost = \"\"\nport = 7624").is_err());
+        assert!(matches!(parse("name = \"local\"\nprotocol = \"indi\"\nhost = \"localhost\"\nprot = 7624"), Err(ConfigError::Parse { .. })));
+    }
+}
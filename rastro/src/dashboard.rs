@@ -0,0 +1,567 @@
+use std::collections::VecDeque;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::time::Duration;
+use crossterm::event::{Event, EventStream, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::execute;
+use crossterm::terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen};
+use futures::stream::FuturesUnordered;
+use futures::StreamExt;
+use ratatui::backend::{Backend, CrosstermBackend};
+use ratatui::layout::{Constraint, Direction, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Span, Spans};
+use ratatui::widgets::{Block, Borders, List, ListItem, ListState, Paragraph, Tabs};
+use ratatui::{Frame, Terminal};
+use tokio::sync::broadcast::error::RecvError;
+
+use rastro::indi::common::{IndiPermission, IndiState};
+use rastro::indi::error::IndiResult;
+use rastro::indi::number_format::{format_number, parse_number};
+use rastro::indi::registry::{Device, PropertyKind, PropertyValue, PropertyVector};
+use rastro::indi::session::{Session, SessionEvent};
+use rastro::indi::switch::{IndiSwitch, IndiSwitchOptions};
+use rastro::indi::IncomingMsg;
+
+use crate::cli::CliResult;
+use crate::logging;
+
+/// Lines kept in the message pane.
+const MESSAGE_LINES: usize = 500;
+
+const HELP: &str = "Tab device  ←/→ group  ↑/↓ element  Enter toggle/edit  PgUp/PgDn messages  q quit";
+
+/// A line of the property pane, a vector heading or one of its elements.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Row {
+    Vector(String),
+    Element(String, String),
+}
+
+/// New values for the elements of one property, to be sent with `Session::set_*`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Request {
+    Switch(String, String, Vec<(String, IndiSwitch)>),
+    Number(String, String, Vec<(String, f64)>),
+    Text(String, String, Vec<(String, String)>),
+}
+
+/// The element whose new value is being typed in.
+#[derive(Debug)]
+struct Edit {
+    device: String,
+    property: String,
+    element: String,
+    input: String,
+}
+
+/**
+What the dashboard shows, apart from the devices themselves which are read from the session
+on every frame.
+
+Selections are kept by name so they survive devices and properties coming and going.
+*/
+#[derive(Debug, Default)]
+pub struct Dashboard {
+    device: Option<String>,
+    group: Option<String>,
+    selected: Option<(String, String)>,
+    messages: VecDeque<String>,
+    /// Lines scrolled back from the newest message.
+    scroll: usize,
+    edit: Option<Edit>,
+    status: String,
+    quit: bool,
+}
+
+/// Groups of `device` in the order their first property sorts.
+pub fn groups(device: &Device) -> Vec<&str> {
+    let mut groups: Vec<&str> = Vec::new();
+    for vector in device.properties.values() {
+        if !groups.contains(&vector.group.as_str()) {
+            groups.push(&vector.group);
+        }
+    }
+    groups
+}
+
+pub fn rows(device: &Device, group: &str) -> Vec<Row> {
+    device
+        .properties
+        .values()
+        .filter(|v| v.group == group)
+        .flat_map(|v| {
+            std::iter::once(Row::Vector(v.name.clone()))
+                .chain(v.elements.iter().map(|e| Row::Element(v.name.clone(), e.name.clone())))
+        })
+        .collect()
+}
+
+/// `selected` if it is one of `devices`, otherwise the first device.
+fn pick<'a>(selected: Option<&'a str>, devices: &'a [String]) -> Option<&'a str> {
+    match selected {
+        Some(device) if devices.iter().any(|d| d == device) => Some(device),
+        _ => devices.first().map(|d| d.as_str()),
+    }
+}
+
+fn state_color(state: IndiState) -> Color {
+    match state {
+        IndiState::Idle => Color::Gray,
+        IndiState::Ok => Color::Green,
+        IndiState::Busy => Color::Yellow,
+        IndiState::Alert => Color::Red,
+    }
+}
+
+impl Dashboard {
+    pub fn new() -> Dashboard {
+        Dashboard::default()
+    }
+
+    pub fn should_quit(&self) -> bool {
+        self.quit
+    }
+
+    /// The selected device, falling back to the first one.
+    pub fn device<'a>(&'a self, devices: &'a [String]) -> Option<&'a str> {
+        pick(self.device.as_deref(), devices)
+    }
+
+    fn group<'a>(&'a self, device: &'a Device) -> Option<&'a str> {
+        let groups = groups(device);
+        match &self.group {
+            Some(group) if groups.contains(&group.as_str()) => Some(group),
+            _ => groups.first().copied(),
+        }
+    }
+
+    fn rows(&self, device: Option<&Device>) -> Vec<Row> {
+        match device.and_then(|d| Some((d, self.group(d)?))) {
+            Some((device, group)) => rows(device, group),
+            None => Vec::new(),
+        }
+    }
+
+    /// Index of the selected element in `rows`, falling back to the first element.
+    fn selected(&self, rows: &[Row]) -> Option<usize> {
+        let selected = self.selected.as_ref().and_then(|(p, e)| rows.iter().position(|r| *r == Row::Element(p.clone(), e.clone())));
+        selected.or_else(|| rows.iter().position(|r| matches!(r, Row::Element(..))))
+    }
+
+    pub fn push_message(&mut self, line: String) {
+        if self.messages.len() == MESSAGE_LINES {
+            self.messages.pop_front();
+        }
+        self.messages.push_back(line);
+        if self.scroll > 0 {
+            //keep the lines being read in place
+            self.scroll = (self.scroll + 1).min(self.messages.len());
+        }
+    }
+
+    pub fn on_event(&mut self, event: &SessionEvent) {
+        match event {
+            SessionEvent::Message { msg, .. } => {
+                if let IncomingMsg::Message(m) = &**msg {
+                    self.push_message(format!("{} {}: {}", m.timestamp, m.device, m.message));
+                }
+            },
+            SessionEvent::State { connection, state } => self.push_message(format!("[{}] {:?}", connection, state)),
+        }
+    }
+
+    pub fn set_status(&mut self, status: String) {
+        self.status = status;
+    }
+
+    /**
+    Handles a key press, `device` being the selected one of `devices`.
+
+    Returns the request to send when the key toggled a switch or finished editing a value.
+    */
+    pub fn on_key(&mut self, key: KeyEvent, devices: &[String], device: Option<&Device>) -> Option<Request> {
+        if self.edit.is_some() {
+            return self.on_edit_key(key, device);
+        }
+        let rows = self.rows(device);
+        let selected = self.selected(&rows);
+        match key.code {
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => self.quit = true,
+            KeyCode::Char('q') | KeyCode::Esc => self.quit = true,
+            KeyCode::Tab | KeyCode::BackTab if !devices.is_empty() => {
+                let current = self.device(devices).and_then(|d| devices.iter().position(|n| n == d)).unwrap_or(0);
+                let next = if key.code == KeyCode::Tab { current + 1 } else { current + devices.len() - 1 };
+                self.device = Some(devices[next % devices.len()].clone());
+                self.group = None;
+                self.selected = None;
+            },
+            KeyCode::Left | KeyCode::Right => if let Some(device) = device.filter(|d| !d.properties.is_empty()) {
+                let groups = groups(device);
+                let current = self.group(device).and_then(|g| groups.iter().position(|n| *n == g)).unwrap_or(0);
+                let next = if key.code == KeyCode::Right { current + 1 } else { current + groups.len() - 1 };
+                self.group = groups.get(next % groups.len()).map(|g| g.to_string());
+                self.selected = None;
+            },
+            KeyCode::Up | KeyCode::Down => if let Some(selected) = selected {
+                let next = if key.code == KeyCode::Down {
+                    rows.iter().enumerate().skip(selected + 1).find(|(_, r)| matches!(r, Row::Element(..)))
+                } else {
+                    rows.iter().enumerate().take(selected).rev().find(|(_, r)| matches!(r, Row::Element(..)))
+                };
+                if let Some((_, Row::Element(property, element))) = next {
+                    self.selected = Some((property.clone(), element.clone()));
+                }
+            },
+            KeyCode::PageUp => self.scroll = (self.scroll + 5).min(self.messages.len().saturating_sub(1)),
+            KeyCode::PageDown => self.scroll = self.scroll.saturating_sub(5),
+            KeyCode::End => self.scroll = 0,
+            KeyCode::Enter | KeyCode::Char(' ') => {
+                if let (Some(device), Some(Row::Element(property, element))) = (device, selected.map(|i| &rows[i])) {
+                    return self.activate(device, &device.properties[property], element);
+                }
+            },
+            _ => {},
+        }
+        None
+    }
+
+    /// Toggles a switch right away, starts editing numbers and texts.
+    fn activate(&mut self, device: &Device, vector: &PropertyVector, element: &str) -> Option<Request> {
+        if vector.perm == Some(IndiPermission::RO) || matches!(vector.kind, PropertyKind::Light | PropertyKind::Blob) {
+            self.status = format!("{} is read only", vector.label);
+            return None;
+        }
+        let value = &vector.element(element)?.value;
+        match value {
+            PropertyValue::Switch(current) => {
+                let value = match (vector.rule, current) {
+                    (Some(IndiSwitchOptions::OneOfMany), _) | (_, IndiSwitch::Off) => IndiSwitch::On,
+                    (_, IndiSwitch::On) => IndiSwitch::Off,
+                };
+                Some(Request::Switch(device.name.clone(), vector.name.clone(), vec![(element.to_string(), value)]))
+            },
+            PropertyValue::Number { value, format, .. } => {
+                self.start_edit(device, vector, element, format_number(format, *value).trim().to_string());
+                None
+            },
+            value => {
+                self.start_edit(device, vector, element, value.to_string());
+                None
+            },
+        }
+    }
+
+    fn start_edit(&mut self, device: &Device, vector: &PropertyVector, element: &str, input: String) {
+        self.edit = Some(Edit { device: device.name.clone(), property: vector.name.clone(), element: element.to_string(), input });
+    }
+
+    fn on_edit_key(&mut self, key: KeyEvent, device: Option<&Device>) -> Option<Request> {
+        let edit = self.edit.as_mut()?;
+        match key.code {
+            KeyCode::Esc => self.edit = None,
+            KeyCode::Backspace => {
+                edit.input.pop();
+            },
+            KeyCode::Char(c) => edit.input.push(c),
+            KeyCode::Enter => {
+                let edit = self.edit.take()?;
+                let vector = device.filter(|d| d.name == edit.device)?.properties.get(&edit.property)?;
+                //numbers and texts are sent with all their elements, the others keep their values
+                return match vector.kind {
+                    PropertyKind::Number => match parse_number(&edit.input) {
+                        Some(number) => Some(Request::Number(edit.device.clone(), edit.property.clone(), vector.elements.iter().filter_map(|e| match &e.value {
+                            _ if e.name == edit.element => Some((e.name.clone(), number)),
+                            PropertyValue::Number { value, .. } => Some((e.name.clone(), *value)),
+                            _ => None,
+                        }).collect())),
+                        None => {
+                            self.status = format!("{} is not a number", edit.input);
+                            self.edit = Some(edit);
+                            None
+                        },
+                    },
+                    PropertyKind::Text => Some(Request::Text(edit.device.clone(), edit.property.clone(), vector.elements.iter().map(|e| {
+                        let value = if e.name == edit.element { edit.input.clone() } else { e.value.to_string() };
+                        (e.name.clone(), value)
+                    }).collect())),
+                    _ => None,
+                };
+            },
+            _ => {},
+        }
+        None
+    }
+
+    pub fn draw<B: Backend>(&self, f: &mut Frame<B>, devices: &[String], device: Option<&Device>) {
+        let outer = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Min(8), Constraint::Length(8), Constraint::Length(1)])
+            .split(f.size());
+        let main = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Length(28), Constraint::Min(20)])
+            .split(outer[0]);
+        let properties = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Length(3), Constraint::Min(3)])
+            .split(main[1]);
+        let highlight = Style::default().add_modifier(Modifier::REVERSED);
+
+        let selected_device = self.device(devices);
+        let items: Vec<ListItem> = devices.iter().map(|d| ListItem::new(d.as_str())).collect();
+        let mut state = ListState::default();
+        state.select(devices.iter().position(|d| Some(d.as_str()) == selected_device));
+        let list = List::new(items).block(Block::default().borders(Borders::ALL).title("Devices")).highlight_style(highlight);
+        f.render_stateful_widget(list, main[0], &mut state);
+
+        let groups = device.map(groups).unwrap_or_default();
+        let group = device.and_then(|d| self.group(d));
+        let titles = groups.iter().map(|g| Spans::from(*g)).collect();
+        let tabs = Tabs::new(titles)
+            .block(Block::default().borders(Borders::ALL).title(selected_device.unwrap_or("no devices")))
+            .select(groups.iter().position(|g| Some(*g) == group).unwrap_or(0))
+            .highlight_style(highlight);
+        f.render_widget(tabs, properties[0]);
+
+        let rows = self.rows(device);
+        let items: Vec<ListItem> = match device {
+            Some(device) => rows.iter().map(|row| ListItem::new(row_line(device, row))).collect(),
+            None => Vec::new(),
+        };
+        let mut state = ListState::default();
+        state.select(self.selected(&rows));
+        let list = List::new(items).block(Block::default().borders(Borders::ALL)).highlight_style(highlight);
+        f.render_stateful_widget(list, properties[1], &mut state);
+
+        let height = outer[1].height.saturating_sub(2) as usize;
+        let end = self.messages.len() - self.scroll.min(self.messages.len());
+        let lines: Vec<Spans> = self.messages.range(end.saturating_sub(height)..end).map(|m| Spans::from(m.as_str())).collect();
+        let title = if self.scroll > 0 { format!("Messages (-{})", self.scroll) } else { "Messages".to_string() };
+        f.render_widget(Paragraph::new(lines).block(Block::default().borders(Borders::ALL).title(title)), outer[1]);
+
+        let footer = match &self.edit {
+            Some(edit) => Spans::from(vec![
+                Span::styled(format!("{}.{} = ", edit.property, edit.element), Style::default().add_modifier(Modifier::BOLD)),
+                Span::raw(format!("{}_  (Enter sends, Esc cancels)", edit.input)),
+            ]),
+            None if !self.status.is_empty() => Spans::from(self.status.as_str()),
+            None => Spans::from(HELP),
+        };
+        f.render_widget(Paragraph::new(footer), outer[2]);
+    }
+}
+
+fn row_line<'a>(device: &'a Device, row: &Row) -> Spans<'a> {
+    match row {
+        Row::Vector(property) => {
+            let vector = &device.properties[property];
+            let mut spans = vec![
+                Span::styled("● ", Style::default().fg(state_color(vector.state))),
+                Span::styled(vector.label.as_str(), Style::default().add_modifier(Modifier::BOLD)),
+                Span::raw(format!(" ({})", vector.name)),
+            ];
+            if vector.stale {
+                spans.push(Span::styled(" stale", Style::default().fg(Color::DarkGray)));
+            }
+            Spans::from(spans)
+        },
+        Row::Element(property, element) => {
+            let element = match device.properties[property].element(element) {
+                Some(element) => element,
+                None => return Spans::default(),
+            };
+            let style = match &element.value {
+                PropertyValue::Switch(IndiSwitch::On) => Style::default().add_modifier(Modifier::BOLD),
+                PropertyValue::Light(state) => Style::default().fg(state_color(*state)),
+                _ => Style::default(),
+            };
+            Spans::from(vec![
+                Span::raw(format!("    {:<30} ", element.label)),
+                Span::styled(element.value.to_string(), style),
+            ])
+        },
+    }
+}
+
+/// Restores the terminal when dropped, also while unwinding from a panic.
+struct TerminalGuard;
+
+impl TerminalGuard {
+    fn enter() -> io::Result<(TerminalGuard, Terminal<CrosstermBackend<io::Stdout>>)> {
+        enable_raw_mode()?;
+        execute!(io::stdout(), EnterAlternateScreen)?;
+        let guard = TerminalGuard;
+        let mut terminal = Terminal::new(CrosstermBackend::new(io::stdout()))?;
+        terminal.hide_cursor()?;
+        Ok((guard, terminal))
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        let _ = disable_raw_mode();
+        let _ = execute!(io::stdout(), LeaveAlternateScreen, crossterm::cursor::Show);
+    }
+}
+
+type Pending<'a> = FuturesUnordered<Pin<Box<dyn Future<Output = (String, IndiResult<IndiState>)> + 'a>>>;
+
+fn send(session: &Session, request: Request) -> Pin<Box<dyn Future<Output = (String, IndiResult<IndiState>)> + '_>> {
+    Box::pin(async move {
+        match request {
+            Request::Switch(device, property, values) => {
+                let values: Vec<_> = values.iter().map(|(e, v)| (e.as_str(), *v)).collect();
+                (format!("{}.{}", device, property), session.set_switch(&device, &property, &values).await)
+            },
+            Request::Number(device, property, values) => {
+                let values: Vec<_> = values.iter().map(|(e, v)| (e.as_str(), *v)).collect();
+                (format!("{}.{}", device, property), session.set_number(&device, &property, &values).await)
+            },
+            Request::Text(device, property, values) => {
+                let values: Vec<_> = values.iter().map(|(e, v)| (e.as_str(), v.as_str())).collect();
+                (format!("{}.{}", device, property), session.set_text(&device, &property, &values).await)
+            },
+        }
+    })
+}
+
+/// Calls `f` with the selected device, holding the registry lock only for the call.
+fn with_device<R>(session: &Session, selected: Option<&str>, f: impl FnOnce(&[String], Option<&Device>) -> R) -> R {
+    let devices: Vec<String> = session.devices().into_iter().map(|(_, device)| device).collect();
+    let name = pick(selected, &devices).map(|d| d.to_string());
+    let client = name.as_deref().and_then(|name| session.client_for(name).ok());
+    let registry = client.map(|client| client.registry());
+    let device = registry.as_ref().and_then(|r| r.device(name.as_deref()?));
+    f(&devices, device)
+}
+
+/**
+Runs the dashboard until `q` is pressed or `quit` returns true, starting the session
+(`blob_mode`, `auto_connect`) in the background.
+
+Log records show in the message pane while it runs.
+*/
+pub async fn run(session: &Session, start_timeout: Duration, quit: impl Fn() -> bool) -> CliResult<()> {
+    let mut events = session.subscribe();
+    let start = session.start(start_timeout);
+    tokio::pin!(start);
+    let mut started = false;
+    let mut pending: Pending = FuturesUnordered::new();
+    let mut keys = EventStream::new();
+    let mut dashboard = Dashboard::new();
+
+    //dropped after the terminal is restored, logs go back to stderr once it is readable
+    let (_logging, mut logs) = logging::to_pane();
+    let (_guard, mut terminal) = TerminalGuard::enter()?;
+    while !dashboard.should_quit() && !quit() {
+        let selected = dashboard.device.clone();
+        with_device(session, selected.as_deref(), |devices, device| terminal.draw(|f| dashboard.draw(f, devices, device)))?;
+
+        tokio::select! {
+            key = keys.next() => match key {
+                Some(Ok(Event::Key(key))) if key.kind != KeyEventKind::Release => {
+                    let request = with_device(session, selected.as_deref(), |devices, device| dashboard.on_key(key, devices, device));
+                    if let Some(request) = request {
+                        pending.push(send(session, request));
+                    }
+                },
+                Some(Ok(_)) => {},
+                Some(Err(e)) => return Err(e.into()),
+                None => break,
+            },
+            event = events.recv() => match event {
+                Ok(event) => dashboard.on_event(&event),
+                Err(RecvError::Lagged(n)) => dashboard.push_message(format!("dropped {} events", n)),
+                Err(RecvError::Closed) => break,
+            },
+            Some(line) = logs.recv() => dashboard.push_message(line),
+            result = &mut start, if !started => {
+                started = true;
+                if let Err(e) = result {
+                    dashboard.set_status(format!("starting failed: {}", e));
+                }
+            },
+            Some((what, result)) = pending.next(), if !pending.is_empty() => {
+                dashboard.set_status(match result {
+                    Ok(state) => format!("{} {:?}", what, state),
+                    Err(e) => format!("{}: {}", what, e),
+                });
+            },
+            //picks up the quit flag of the signal handlers
+            _ = tokio::time::sleep(Duration::from_secs(1)) => {},
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+    use ratatui::backend::TestBackend;
+    use ratatui::Terminal;
+    use rastro::indi::registry::DeviceRegistry;
+    use rastro::indi::switch::IndiSwitch;
+    use rastro::indi::IncomingMsg;
+    use crate::dashboard::{groups, Dashboard, Request};
+
+    fn key(code: KeyCode) -> KeyEvent {
+        KeyEvent::new(code, KeyModifiers::NONE)
+    }
+
+    #[test]
+    fn it_navigates_and_edits_properties() {
+        let mut registry = DeviceRegistry::new();
+        for xml in [
+            r#"<defSwitchVector device="CCD Simulator" name="CONNECTION" label="Connection" group="Main Control" state="Idle" perm="rw" rule="OneOfMany" timeout="60" timestamp="2023-01-12T20:51:39">
+                <defSwitch name="CONNECT" label="Connect">Off</defSwitch>
+                <defSwitch name="DISCONNECT" label="Disconnect">On</defSwitch>
+            </defSwitchVector>"#,
+            r#"<defNumberVector device="CCD Simulator" name="CCD_TEMPERATURE" label="Temperature (C)" group="Main Control" state="Busy" perm="rw" timeout="60" timestamp="2023-01-12T20:51:39">
+                <defNumber name="CCD_TEMPERATURE_VALUE" label="Temperature (C)" format="%5.2f" min="-50" max="50" step="0">20</defNumber>
+            </defNumberVector>"#,
+            r#"<defTextVector device="CCD Simulator" name="DRIVER_INFO" label="Driver Info" group="General Info" state="Idle" perm="ro" timeout="60" timestamp="2023-01-12T20:51:39">
+                <defText name="DRIVER_NAME" label="Name">CCD Simulator</defText>
+            </defTextVector>"#,
+        ] {
+            registry.apply(&IncomingMsg::parse(xml).unwrap());
+        }
+        let device = registry.device("CCD Simulator");
+        let devices = vec!["CCD Simulator".to_string()];
+        assert_eq!(groups(device.unwrap()), vec!["Main Control", "General Info"]);
+
+        //CCD_TEMPERATURE sorts first, Down moves on to the switches
+        let mut dashboard = Dashboard::new();
+        assert_eq!(dashboard.on_key(key(KeyCode::Down), &devices, device), None);
+        assert_eq!(
+            dashboard.on_key(key(KeyCode::Enter), &devices, device),
+            Some(Request::Switch("CCD Simulator".to_string(), "CONNECTION".to_string(), vec![("CONNECT".to_string(), IndiSwitch::On)]))
+        );
+
+        dashboard.on_key(key(KeyCode::Up), &devices, device);
+        dashboard.on_key(key(KeyCode::Enter), &devices, device);
+        for _ in 0..5 {
+            dashboard.on_key(key(KeyCode::Backspace), &devices, device);
+        }
+        for c in "-10".chars() {
+            dashboard.on_key(key(KeyCode::Char(c)), &devices, device);
+        }
+        assert_eq!(
+            dashboard.on_key(key(KeyCode::Enter), &devices, device),
+            Some(Request::Number("CCD Simulator".to_string(), "CCD_TEMPERATURE".to_string(), vec![("CCD_TEMPERATURE_VALUE".to_string(), -10.0)]))
+        );
+
+        dashboard.on_key(key(KeyCode::Right), &devices, device);
+        assert_eq!(dashboard.on_key(key(KeyCode::Enter), &devices, device), None);
+
+        let mut terminal = Terminal::new(TestBackend::new(100, 30)).unwrap();
+        terminal.draw(|f| dashboard.draw(f, &devices, device)).unwrap();
+        let screen: String = terminal.backend().buffer().content().iter().map(|c| c.symbol.as_str()).collect();
+        assert!(screen.contains("Driver Info is read only"));
+        assert!(screen.contains("General Info"));
+
+        dashboard.on_key(key(KeyCode::Char('q')), &devices, device);
+        assert!(dashboard.should_quit());
+    }
+}
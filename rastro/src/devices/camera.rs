@@ -0,0 +1,358 @@
+use std::time::Duration;
+use futures::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+use tokio::time::Instant;
+
+use crate::devices::BaseDevice;
+use crate::indi::blob::SetBlobVector;
+use crate::indi::common::IndiState;
+use crate::indi::enable_blob::EnableBLOBValue;
+use crate::indi::error::{IndiError, IndiResult};
+use crate::indi::registry::PropertyValue;
+use crate::indi::session::{Session, SessionEvent};
+use crate::indi::IncomingMsg;
+
+const CCD_EXPOSURE: &str = "CCD_EXPOSURE";
+const CCD_ABORT_EXPOSURE: &str = "CCD_ABORT_EXPOSURE";
+const CCD_FRAME: &str = "CCD_FRAME";
+const CCD_FRAME_RESET: &str = "CCD_FRAME_RESET";
+const CCD_FRAME_TYPE: &str = "CCD_FRAME_TYPE";
+const CCD_BINNING: &str = "CCD_BINNING";
+const CCD_COOLER: &str = "CCD_COOLER";
+const CCD_TEMPERATURE: &str = "CCD_TEMPERATURE";
+const UPLOAD_MODE: &str = "UPLOAD_MODE";
+const UPLOAD_SETTINGS: &str = "UPLOAD_SETTINGS";
+
+/// Where drivers put gain and offset, the first one the camera has is used.
+const GAIN: &[(&str, &str)] = &[("CCD_GAIN", "GAIN"), ("CCD_CONTROLS", "Gain")];
+const OFFSET: &[(&str, &str)] = &[("CCD_OFFSET", "OFFSET"), ("CCD_CONTROLS", "Offset")];
+
+/// How long a frame may take to arrive once the exposure is done.
+pub const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// `CCD_FRAME_TYPE` of a frame.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FrameType {
+    #[default]
+    Light,
+    Bias,
+    Dark,
+    Flat,
+}
+
+impl FrameType {
+    pub fn element(&self) -> &'static str {
+        match self {
+            FrameType::Light => "FRAME_LIGHT",
+            FrameType::Bias => "FRAME_BIAS",
+            FrameType::Dark => "FRAME_DARK",
+            FrameType::Flat => "FRAME_FLAT",
+        }
+    }
+}
+
+/// `UPLOAD_MODE`, where the driver delivers frames.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UploadMode {
+    /// As BLOBs to the clients.
+    Client,
+    /// Into `UPLOAD_SETTINGS` on the machine running the driver.
+    Local,
+    Both,
+}
+
+impl UploadMode {
+    fn element(&self) -> &'static str {
+        match self {
+            UploadMode::Client => "UPLOAD_CLIENT",
+            UploadMode::Local => "UPLOAD_LOCAL",
+            UploadMode::Both => "UPLOAD_BOTH",
+        }
+    }
+}
+
+/// Region of the sensor read out, in unbinned pixels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Roi {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// A frame as the driver sent it, `data` is decoded and inflated.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Image {
+    pub device: String,
+    /// Format of `data`, e.g. `.fits`.
+    pub format: String,
+    pub timestamp: String,
+    pub data: Vec<u8>,
+}
+
+impl Image {
+    /**
+    The first non-empty BLOB of `vector`.
+
+    A BLOB streamed to a `BlobStorage::Directory` is read back from its file, one handed to a
+    `BlobStorage::Writer` cannot be.
+    */
+    pub fn from_blob(vector: &SetBlobVector) -> IndiResult<Image> {
+        let blob = vector.blobs
+            .iter()
+            .find(|b| b.size > 0)
+            .ok_or_else(|| IndiError::InvalidData(format!("{}::{} is empty", vector.device, vector.name)))?;
+        let data = match &blob.stored {
+            None => blob.decode()?,
+            Some(stored) => match &stored.path {
+                Some(path) => {
+                    stored.check()?;
+                    std::fs::read(path)?
+                },
+                None => return Err(IndiError::InvalidData(format!("{} was streamed to a writer", blob.name))),
+            },
+        };
+        Ok(Image {
+            device: vector.device.clone(),
+            format: blob.decoded_format().to_string(),
+            timestamp: vector.timestamp.clone(),
+            data,
+        })
+    }
+}
+
+/**
+A camera, driven through the standard CCD properties.
+
+`expose` starts an exposure and waits for the image, `countdown` follows its progress. Setters
+wait for the driver to settle and fail with `IndiError::Unsupported` when the driver lacks the
+property they need.
+*/
+#[derive(Clone)]
+pub struct Camera<'a> {
+    device: BaseDevice<'a>,
+}
+
+impl<'a> Camera<'a> {
+    pub fn new(session: &'a Session, name: &str) -> Camera<'a> {
+        Camera { device: BaseDevice::new(session, name) }
+    }
+
+    pub fn device(&self) -> &BaseDevice<'a> {
+        &self.device
+    }
+
+    /// Starts an exposure of `seconds` without waiting for it, the image arrives as a BLOB.
+    pub async fn start_exposure(&self, seconds: f64) -> IndiResult<()> {
+        self.device.send_numbers(CCD_EXPOSURE, &[("CCD_EXPOSURE_VALUE", seconds)]).await
+    }
+
+    pub async fn abort_exposure(&self) -> IndiResult<()> {
+        self.device.switch(CCD_ABORT_EXPOSURE, "ABORT").await
+    }
+
+    /**
+    Exposes for `seconds` with the current settings and returns the image.
+
+    Enables BLOBs for the camera first. Fails if the driver only saves frames locally, if the
+    exposure ends in `Alert`, e.g. when aborted, or if no image arrives within `DOWNLOAD_TIMEOUT`
+    of the end of the exposure.
+    */
+    pub async fn expose(&self, seconds: f64) -> IndiResult<Image> {
+        let name = self.device.name();
+        if self.device.has(UPLOAD_MODE) && self.upload_mode()? == Some(UploadMode::Local) {
+            return Err(IndiError::InvalidRequest(format!("{} only saves frames locally", name)));
+        }
+        self.device.session().enable_blob(EnableBLOBValue::Also, Some(name), None).await?;
+        Image::from_blob(&self.capture(seconds).await?)
+    }
+
+    /**
+    Exposes for `seconds` with the current settings and returns the `setBLOBVector` carrying the image.
+
+    BLOBs must be enabled for the camera. Fails if the exposure ends in `Alert` or if no image
+    arrives within `DOWNLOAD_TIMEOUT` of the end of the exposure.
+    */
+    pub async fn capture(&self, seconds: f64) -> IndiResult<SetBlobVector> {
+        let name = self.device.name();
+        //subscribe first, drivers may send the image before CCD_EXPOSURE turns Ok
+        let mut events = self.device.session().subscribe();
+        self.start_exposure(seconds).await?;
+        let deadline = Instant::now() + Duration::from_secs_f64(seconds.max(0.0)) + DOWNLOAD_TIMEOUT;
+        loop {
+            let msg = match tokio::time::timeout_at(deadline, events.recv()).await {
+                Err(_) => return Err(IndiError::Timeout(format!("{} sent no image", name))),
+                Ok(Err(broadcast::error::RecvError::Closed)) => return Err(IndiError::ChannelClosed),
+                Ok(Err(broadcast::error::RecvError::Lagged(n))) => {
+                    log::warn!("missed {} messages waiting for the image", n);
+                    continue;
+                },
+                Ok(Ok(SessionEvent::Message { msg, .. })) => msg,
+                Ok(Ok(_)) => continue,
+            };
+            match &*msg {
+                IncomingMsg::SetBlobVector(blob) if blob.device == name && blob.blobs.iter().any(|b| b.size > 0) => {
+                    return Ok(blob.clone());
+                },
+                IncomingMsg::SetNumberVector(v) if v.device == name && v.name == CCD_EXPOSURE && v.state == IndiState::Alert => {
+                    return Err(IndiError::Alert { device: name.to_string(), property: CCD_EXPOSURE.to_string() });
+                },
+                _ => {},
+            }
+        }
+    }
+
+    /// Seconds left of the running exposure, every time the driver counts down.
+    pub fn countdown(&self) -> impl Stream<Item = f64> + 'a {
+        self.device.updates(CCD_EXPOSURE).filter_map(|vector| async move {
+            match vector.element("CCD_EXPOSURE_VALUE").map(|e| &e.value) {
+                Some(PropertyValue::Number { value, .. }) => Some(*value),
+                _ => None,
+            }
+        })
+    }
+
+    pub fn roi(&self) -> IndiResult<Roi> {
+        let number = |element| self.device.number(CCD_FRAME, element).map(|v| v as u32);
+        Ok(Roi { x: number("X")?, y: number("Y")?, width: number("WIDTH")?, height: number("HEIGHT")? })
+    }
+
+    pub async fn set_roi(&self, roi: Roi) -> IndiResult<()> {
+        self.device.set_numbers(CCD_FRAME, &[
+            ("X", roi.x as f64),
+            ("Y", roi.y as f64),
+            ("WIDTH", roi.width as f64),
+            ("HEIGHT", roi.height as f64),
+        ]).await
+    }
+
+    /// Reads out the whole sensor again.
+    pub async fn reset_roi(&self) -> IndiResult<()> {
+        self.device.switch(CCD_FRAME_RESET, "RESET").await
+    }
+
+    /// `(horizontal, vertical)`.
+    pub fn binning(&self) -> IndiResult<(u32, u32)> {
+        Ok((self.device.number(CCD_BINNING, "HOR_BIN")? as u32, self.device.number(CCD_BINNING, "VER_BIN")? as u32))
+    }
+
+    pub async fn set_binning(&self, horizontal: u32, vertical: u32) -> IndiResult<()> {
+        self.device.set_numbers(CCD_BINNING, &[("HOR_BIN", horizontal as f64), ("VER_BIN", vertical as f64)]).await
+    }
+
+    pub fn frame_type(&self) -> IndiResult<Option<FrameType>> {
+        let on = self.device.switch_on(CCD_FRAME_TYPE)?;
+        Ok([FrameType::Light, FrameType::Bias, FrameType::Dark, FrameType::Flat]
+            .into_iter()
+            .find(|t| on.as_deref() == Some(t.element())))
+    }
+
+    pub async fn set_frame_type(&self, frame_type: FrameType) -> IndiResult<()> {
+        self.device.switch(CCD_FRAME_TYPE, frame_type.element()).await
+    }
+
+    pub fn gain(&self) -> IndiResult<f64> {
+        let (property, element) = self.control(GAIN)?;
+        self.device.number(property, element)
+    }
+
+    pub async fn set_gain(&self, gain: f64) -> IndiResult<()> {
+        let (property, element) = self.control(GAIN)?;
+        self.device.set_numbers(property, &[(element, gain)]).await
+    }
+
+    pub fn offset(&self) -> IndiResult<f64> {
+        let (property, element) = self.control(OFFSET)?;
+        self.device.number(property, element)
+    }
+
+    pub async fn set_offset(&self, offset: f64) -> IndiResult<()> {
+        let (property, element) = self.control(OFFSET)?;
+        self.device.set_numbers(property, &[(element, offset)]).await
+    }
+
+    /// The first of `candidates` the camera has, as `(property, element)`.
+    fn control(&self, candidates: &[(&'static str, &'static str)]) -> IndiResult<(&'static str, &'static str)> {
+        candidates
+            .iter()
+            .find(|(property, element)| self.device.property(property).is_ok_and(|p| p.element(element).is_some()))
+            .copied()
+            .ok_or_else(|| {
+                let names: Vec<_> = candidates.iter().map(|(p, e)| format!("{}.{}", p, e)).collect();
+                self.device.unsupported(&names.join(" or "))
+            })
+    }
+
+    pub fn is_cooler_on(&self) -> IndiResult<bool> {
+        self.device.is_on(CCD_COOLER, "COOLER_ON")
+    }
+
+    pub async fn set_cooler(&self, on: bool) -> IndiResult<()> {
+        self.device.switch(CCD_COOLER, if on { "COOLER_ON" } else { "COOLER_OFF" }).await
+    }
+
+    /// Sensor temperature in °C.
+    pub fn temperature(&self) -> IndiResult<f64> {
+        self.device.number(CCD_TEMPERATURE, "CCD_TEMPERATURE_VALUE")
+    }
+
+    /// Returns once the sensor reached `celsius`, drivers keep `CCD_TEMPERATURE` busy until then.
+    pub async fn set_temperature(&self, celsius: f64) -> IndiResult<()> {
+        self.device.set_numbers(CCD_TEMPERATURE, &[("CCD_TEMPERATURE_VALUE", celsius)]).await
+    }
+
+    pub fn upload_mode(&self) -> IndiResult<Option<UploadMode>> {
+        let on = self.device.switch_on(UPLOAD_MODE)?;
+        Ok([UploadMode::Client, UploadMode::Local, UploadMode::Both]
+            .into_iter()
+            .find(|mode| on.as_deref() == Some(mode.element())))
+    }
+
+    pub async fn set_upload_mode(&self, mode: UploadMode) -> IndiResult<()> {
+        self.device.switch(UPLOAD_MODE, mode.element()).await
+    }
+
+    /// Where the driver saves frames in `UploadMode::Local` and `Both`, the prefix may contain `XXX` for a counter.
+    pub async fn set_upload_settings(&self, directory: &str, prefix: &str) -> IndiResult<()> {
+        self.device.set_texts(UPLOAD_SETTINGS, &[("UPLOAD_DIR", directory), ("UPLOAD_PREFIX", prefix)]).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use std::time::Duration;
+    use futures::StreamExt;
+    use crate::devices::camera::{Camera, FrameType, Roi};
+    use crate::devices::fake::remove;
+    use crate::indi::error::IndiError;
+    use crate::indi::session::Session;
+    use crate::simulator::{spawn, CcdSimulator};
+
+    #[tokio::test]
+    async fn it_exposes_and_counts_down() -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut simulator = CcdSimulator::new("CCD Simulator");
+        (simulator.width, simulator.height) = (320, 240);
+        let (runtime, spec) = spawn("camera", simulator).await?;
+        let session = Session::connect(&[spec]).await?;
+        session.get_properties(None, None).await?;
+        session.wait_for_property("CCD Simulator", "CCD1", Duration::from_secs(5)).await?;
+        let camera = Camera::new(&session, "CCD Simulator");
+        assert_eq!(camera.frame_type()?, Some(FrameType::Light));
+
+        let mut countdown = Box::pin(camera.countdown());
+        let frame = camera.expose(0.3).await?;
+        assert_eq!(frame.format, ".fits");
+        assert!(frame.data.starts_with(b"SIMPLE"));
+        assert!(countdown.next().await.is_some_and(|left| left <= 0.3));
+
+        let roi = Roi { x: 10, y: 20, width: 160, height: 120 };
+        camera.set_roi(roi).await?;
+        assert_eq!(camera.roi()?, roi);
+
+        remove(&runtime, &session, "CCD Simulator", "CCD_GAIN").await;
+        assert!(matches!(camera.set_gain(100.0).await, Err(IndiError::Unsupported { .. })));
+        Ok(())
+    }
+}
@@ -0,0 +1,119 @@
+use crate::devices::BaseDevice;
+use crate::indi::error::{IndiError, IndiResult};
+use crate::indi::registry::PropertyValue;
+use crate::indi::session::Session;
+
+const FILTER_SLOT: &str = "FILTER_SLOT";
+const FILTER_NAME: &str = "FILTER_NAME";
+
+/**
+A filter wheel, driven through `FILTER_SLOT` with slots numbered from 1.
+
+Filters can be picked by the names of `FILTER_NAME`. Moves return once the driver reports the
+wheel stopped.
+*/
+#[derive(Clone)]
+pub struct FilterWheel<'a> {
+    device: BaseDevice<'a>,
+}
+
+impl<'a> FilterWheel<'a> {
+    pub fn new(session: &'a Session, name: &str) -> FilterWheel<'a> {
+        FilterWheel { device: BaseDevice::new(session, name) }
+    }
+
+    pub fn device(&self) -> &BaseDevice<'a> {
+        &self.device
+    }
+
+    pub fn slot(&self) -> IndiResult<usize> {
+        Ok(self.device.number(FILTER_SLOT, "FILTER_SLOT_VALUE")? as usize)
+    }
+
+    pub async fn set_slot(&self, slot: usize) -> IndiResult<()> {
+        self.device.set_numbers(FILTER_SLOT, &[("FILTER_SLOT_VALUE", slot as f64)]).await
+    }
+
+    /// Filter names in slot order, the first is slot 1.
+    pub fn names(&self) -> IndiResult<Vec<String>> {
+        Ok(self.device
+            .property(FILTER_NAME)?
+            .elements
+            .iter()
+            .filter_map(|e| match &e.value {
+                PropertyValue::Text(name) => Some(name.clone()),
+                _ => None,
+            })
+            .collect())
+    }
+
+    /// Name of the filter in the light path, if the driver names its slots.
+    pub fn filter(&self) -> IndiResult<Option<String>> {
+        if !self.device.has(FILTER_NAME) {
+            return Ok(None);
+        }
+        let slot = self.slot()?;
+        Ok(self.names()?.get(slot.wrapping_sub(1)).cloned())
+    }
+
+    /// Slot of `filter`, a name from `FILTER_NAME` in any case or a slot number.
+    pub fn slot_of(&self, filter: &str) -> IndiResult<usize> {
+        if let Ok(slot) = filter.parse() {
+            return Ok(slot);
+        }
+        let names = self.names()?;
+        names
+            .iter()
+            .position(|name| name.eq_ignore_ascii_case(filter))
+            .map(|i| i + 1)
+            .ok_or_else(|| IndiError::InvalidRequest(format!("{} has no filter {}, only {}", self.device.name(), filter, names.join(", "))))
+    }
+
+    /// Moves `filter`, a name or a slot number, into the light path.
+    pub async fn select(&self, filter: &str) -> IndiResult<()> {
+        self.set_slot(self.slot_of(filter)?).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use std::time::Duration;
+    use crate::devices::fake::{listen, script};
+    use crate::devices::filter_wheel::FilterWheel;
+    use crate::indi::session::Session;
+
+    #[tokio::test]
+    async fn it_selects_filters_by_name() -> Result<(), Box<dyn Error + Send + Sync>> {
+        let (spec, listener) = listen("wheel").await?;
+        let moves: String = [("Busy", "1"), ("Ok", "3")]
+            .iter()
+            .map(|(state, slot)| format!(r#"<setNumberVector device="Filter Simulator" name="FILTER_SLOT" state="{}" timeout="60" timestamp="2023-01-12T20:51:40"><oneNumber name="FILTER_SLOT_VALUE">{}</oneNumber></setNumberVector>"#, state, slot))
+            .collect();
+        let server = script(listener, r#"
+            <defNumberVector device="Filter Simulator" name="FILTER_SLOT" label="Filter Slot" group="Filter Wheel" state="Idle" perm="rw" timeout="60" timestamp="2023-01-12T20:51:39">
+                <defNumber name="FILTER_SLOT_VALUE" label="Filter" format="%3.0f" min="1" max="3" step="1">1</defNumber>
+            </defNumberVector>
+            <defTextVector device="Filter Simulator" name="FILTER_NAME" label="Filter" group="Filter Wheel" state="Idle" perm="rw" timeout="60" timestamp="2023-01-12T20:51:39">
+                <defText name="FILTER_SLOT_NAME_1" label="Filter#1">L</defText>
+                <defText name="FILTER_SLOT_NAME_2" label="Filter#2">Ha</defText>
+                <defText name="FILTER_SLOT_NAME_3" label="Filter#3">OIII</defText>
+            </defTextVector>
+        "#, vec![("</newNumberVector>", moves)]);
+
+        let session = Session::connect(&[spec]).await?;
+        session.wait_for_property("Filter Simulator", "FILTER_NAME", Duration::from_secs(5)).await?;
+        let wheel = FilterWheel::new(&session, "Filter Simulator");
+        assert_eq!(wheel.names()?, ["L", "Ha", "OIII"]);
+        assert_eq!((wheel.slot_of("ha")?, wheel.slot_of("3")?), (2, 3));
+        assert!(wheel.slot_of("SII").is_err());
+
+        wheel.select("OIII").await?;
+        assert_eq!((wheel.slot()?, wheel.filter()?.as_deref()), (3, Some("OIII")));
+
+        drop(wheel);
+        drop(session);
+        assert!(server.await?.contains(r#"<oneNumber name="FILTER_SLOT_VALUE">3</oneNumber>"#));
+        Ok(())
+    }
+}
@@ -0,0 +1,106 @@
+use crate::devices::BaseDevice;
+use crate::indi::error::IndiResult;
+use crate::indi::session::Session;
+
+const ABS_FOCUS_POSITION: &str = "ABS_FOCUS_POSITION";
+const REL_FOCUS_POSITION: &str = "REL_FOCUS_POSITION";
+const FOCUS_MOTION: &str = "FOCUS_MOTION";
+const FOCUS_ABORT_MOTION: &str = "FOCUS_ABORT_MOTION";
+const FOCUS_TEMPERATURE: &str = "FOCUS_TEMPERATURE";
+const FOCUS_BACKLASH_TOGGLE: &str = "FOCUS_BACKLASH_TOGGLE";
+const FOCUS_BACKLASH_STEPS: &str = "FOCUS_BACKLASH_STEPS";
+
+/**
+A focuser, driven through the standard focuser properties.
+
+Moves return once the driver reports the focuser stopped, since drivers keep the position
+property busy while it moves. Methods fail with `IndiError::Unsupported` when the driver lacks
+the property they need, many focusers only do relative moves.
+*/
+#[derive(Clone)]
+pub struct Focuser<'a> {
+    device: BaseDevice<'a>,
+}
+
+impl<'a> Focuser<'a> {
+    pub fn new(session: &'a Session, name: &str) -> Focuser<'a> {
+        Focuser { device: BaseDevice::new(session, name) }
+    }
+
+    pub fn device(&self) -> &BaseDevice<'a> {
+        &self.device
+    }
+
+    /// Absolute position in steps.
+    pub fn position(&self) -> IndiResult<u32> {
+        Ok(self.device.number(ABS_FOCUS_POSITION, "FOCUS_ABSOLUTE_POSITION")? as u32)
+    }
+
+    pub async fn move_to(&self, position: u32) -> IndiResult<()> {
+        self.device.set_numbers(ABS_FOCUS_POSITION, &[("FOCUS_ABSOLUTE_POSITION", position as f64)]).await
+    }
+
+    /// Moves `steps` outward, or inward when negative.
+    pub async fn move_by(&self, steps: i32) -> IndiResult<()> {
+        //check before switching, so a focuser without relative moves keeps its direction
+        self.device.property(REL_FOCUS_POSITION)?;
+        self.device.switch(FOCUS_MOTION, if steps < 0 { "FOCUS_INWARD" } else { "FOCUS_OUTWARD" }).await?;
+        self.device.set_numbers(REL_FOCUS_POSITION, &[("FOCUS_RELATIVE_POSITION", steps.unsigned_abs() as f64)]).await
+    }
+
+    pub async fn abort(&self) -> IndiResult<()> {
+        self.device.switch(FOCUS_ABORT_MOTION, "ABORT").await
+    }
+
+    /// Temperature in °C of the probe the focuser has.
+    pub fn temperature(&self) -> IndiResult<f64> {
+        self.device.number(FOCUS_TEMPERATURE, "TEMPERATURE")
+    }
+
+    pub fn is_backlash_enabled(&self) -> IndiResult<bool> {
+        self.device.is_on(FOCUS_BACKLASH_TOGGLE, "INDI_ENABLED")
+    }
+
+    pub async fn set_backlash_enabled(&self, enabled: bool) -> IndiResult<()> {
+        self.device.switch(FOCUS_BACKLASH_TOGGLE, if enabled { "INDI_ENABLED" } else { "INDI_DISABLED" }).await
+    }
+
+    /// Steps the driver adds when the direction changes.
+    pub fn backlash(&self) -> IndiResult<i32> {
+        Ok(self.device.number(FOCUS_BACKLASH_STEPS, "FOCUS_BACKLASH_VALUE")? as i32)
+    }
+
+    pub async fn set_backlash(&self, steps: i32) -> IndiResult<()> {
+        self.device.set_numbers(FOCUS_BACKLASH_STEPS, &[("FOCUS_BACKLASH_VALUE", steps as f64)]).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use std::time::Duration;
+    use crate::devices::fake::remove;
+    use crate::devices::focuser::Focuser;
+    use crate::indi::error::IndiError;
+    use crate::indi::session::Session;
+    use crate::simulator::{spawn, FocuserSimulator};
+
+    #[tokio::test]
+    async fn it_moves_until_the_focuser_stops() -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut simulator = FocuserSimulator::new("Focuser Simulator");
+        simulator.speed = 50000.0;
+        let (runtime, spec) = spawn("focuser", simulator).await?;
+        let session = Session::connect(&[spec]).await?;
+        session.get_properties(None, None).await?;
+        session.wait_for_property("Focuser Simulator", "FOCUS_BACKLASH_STEPS", Duration::from_secs(5)).await?;
+        let focuser = Focuser::new(&session, "Focuser Simulator");
+        assert_eq!(focuser.position()?, 50000);
+
+        focuser.move_to(38000).await?;
+        assert_eq!(focuser.position()?, 38000);
+
+        remove(&runtime, &session, "Focuser Simulator", "REL_FOCUS_POSITION").await;
+        assert!(matches!(focuser.move_by(-100).await, Err(IndiError::Unsupported { property, .. }) if property == "REL_FOCUS_POSITION"));
+        Ok(())
+    }
+}
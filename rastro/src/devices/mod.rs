@@ -0,0 +1,283 @@
+use std::time::Duration;
+use futures::Stream;
+use tokio::sync::broadcast;
+
+use crate::indi::common::IndiState;
+use crate::indi::error::{IndiError, IndiResult};
+use crate::indi::registry::{DeviceRegistry, PropertyValue, PropertyVector};
+use crate::indi::session::{Session, SessionEvent};
+use crate::indi::number::{NewNumberValue, NewNumberVector};
+use crate::indi::switch::IndiSwitch;
+use crate::indi::OutgoingMsg;
+
+pub mod camera;
+pub mod filter_wheel;
+pub mod focuser;
+pub mod telescope;
+
+pub use camera::Camera;
+pub use filter_wheel::FilterWheel;
+pub use focuser::Focuser;
+pub use telescope::Telescope;
+
+/**
+A device of a `Session` addressed by name, the base of the typed wrappers like `Telescope` and `Camera`.
+
+Properties are read from the registry on every call, so values are always the latest the driver
+sent. A property the driver does not define is reported as `IndiError::Unsupported`.
+*/
+#[derive(Clone)]
+pub struct BaseDevice<'a> {
+    session: &'a Session,
+    name: String,
+}
+
+impl<'a> BaseDevice<'a> {
+    pub fn new(session: &'a Session, name: &str) -> BaseDevice<'a> {
+        BaseDevice { session, name: name.to_string() }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn session(&self) -> &'a Session {
+        self.session
+    }
+
+    pub fn has(&self, property: &str) -> bool {
+        self.session.property(&self.name, property).is_some()
+    }
+
+    pub fn property(&self, property: &str) -> IndiResult<PropertyVector> {
+        self.session.property(&self.name, property).ok_or_else(|| self.unsupported(property))
+    }
+
+    pub fn unsupported(&self, property: &str) -> IndiError {
+        IndiError::Unsupported { device: self.name.clone(), property: property.to_string() }
+    }
+
+    pub fn number(&self, property: &str, element: &str) -> IndiResult<f64> {
+        match self.property(property)?.element(element).map(|e| &e.value) {
+            Some(PropertyValue::Number { value, .. }) => Ok(*value),
+            _ => Err(self.unsupported(&format!("{}.{}", property, element))),
+        }
+    }
+
+    pub fn text(&self, property: &str, element: &str) -> IndiResult<String> {
+        match self.property(property)?.element(element).map(|e| &e.value) {
+            Some(PropertyValue::Text(value)) => Ok(value.clone()),
+            _ => Err(self.unsupported(&format!("{}.{}", property, element))),
+        }
+    }
+
+    /// Name of the first element of a switch vector that is `On`.
+    pub fn switch_on(&self, property: &str) -> IndiResult<Option<String>> {
+        Ok(self.property(property)?
+            .elements
+            .iter()
+            .find(|e| e.value == PropertyValue::Switch(IndiSwitch::On))
+            .map(|e| e.name.clone()))
+    }
+
+    pub fn is_on(&self, property: &str, element: &str) -> IndiResult<bool> {
+        match self.property(property)?.element(element).map(|e| &e.value) {
+            Some(PropertyValue::Switch(value)) => Ok(*value == IndiSwitch::On),
+            _ => Err(self.unsupported(&format!("{}.{}", property, element))),
+        }
+    }
+
+    /// Turns `element` of a switch vector on and waits for the driver to finish.
+    pub async fn switch(&self, property: &str, element: &str) -> IndiResult<()> {
+        let vector = self.property(property)?;
+        if vector.element(element).is_none() {
+            return Err(self.unsupported(&format!("{}.{}", property, element)));
+        }
+        let state = self.session.set_switch(&self.name, property, &[(element, IndiSwitch::On)]).await?;
+        self.settled(property, state)
+    }
+
+    /**
+    Sets some elements of a number vector and waits for the driver to finish.
+
+    The other elements are sent with their current values, INDI expects complete vectors.
+    */
+    pub async fn set_numbers(&self, property: &str, updates: &[(&str, f64)]) -> IndiResult<()> {
+        let values = self.numbers(property, updates)?;
+        let values: Vec<(&str, f64)> = values.iter().map(|(name, value)| (name.as_str(), *value)).collect();
+        let state = self.session.set_number(&self.name, property, &values).await?;
+        self.settled(property, state)
+    }
+
+    /// Like `set_numbers` but returns once sent, for properties that stay busy for long like `CCD_EXPOSURE`.
+    pub async fn send_numbers(&self, property: &str, updates: &[(&str, f64)]) -> IndiResult<()> {
+        let msg = OutgoingMsg::NewNumberVector(NewNumberVector {
+            device: self.name.clone(),
+            name: property.to_string(),
+            timestamp: None,
+            numbers: self.numbers(property, updates)?.into_iter().map(|(name, value)| NewNumberValue { name, value }).collect(),
+        });
+        self.session.client_for(&self.name)?.send(&msg).await
+    }
+
+    fn numbers(&self, property: &str, updates: &[(&str, f64)]) -> IndiResult<Vec<(String, f64)>> {
+        let vector = self.property(property)?;
+        if let Some((missing, _)) = updates.iter().find(|(name, _)| vector.element(name).is_none()) {
+            return Err(self.unsupported(&format!("{}.{}", property, missing)));
+        }
+        Ok(vector
+            .elements
+            .iter()
+            .filter_map(|e| match (updates.iter().find(|(name, _)| *name == e.name), &e.value) {
+                (Some((_, value)), _) => Some((e.name.clone(), *value)),
+                (None, PropertyValue::Number { value, .. }) => Some((e.name.clone(), *value)),
+                _ => None,
+            })
+            .collect())
+    }
+
+    /// Sets some elements of a text vector and waits for the driver to finish.
+    pub async fn set_texts(&self, property: &str, updates: &[(&str, &str)]) -> IndiResult<()> {
+        let vector = self.property(property)?;
+        if let Some((missing, _)) = updates.iter().find(|(name, _)| vector.element(name).is_none()) {
+            return Err(self.unsupported(&format!("{}.{}", property, missing)));
+        }
+        let values: Vec<(&str, &str)> = vector
+            .elements
+            .iter()
+            .filter_map(|e| match (updates.iter().find(|(name, _)| *name == e.name), &e.value) {
+                (Some((_, value)), _) => Some((e.name.as_str(), *value)),
+                (None, PropertyValue::Text(value)) => Some((e.name.as_str(), value.as_str())),
+                _ => None,
+            })
+            .collect();
+        let state = self.session.set_text(&self.name, property, &values).await?;
+        self.settled(property, state)
+    }
+
+    fn settled(&self, property: &str, state: IndiState) -> IndiResult<()> {
+        match state {
+            IndiState::Alert => Err(IndiError::Alert { device: self.name.clone(), property: property.to_string() }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Waits until `property` is no longer `Busy`, returning the state it settled in.
+    pub async fn wait_idle(&self, property: &str, timeout: Duration) -> IndiResult<IndiState> {
+        let mut events = self.session.subscribe();
+        let deadline = tokio::time::Instant::now() + timeout;
+        loop {
+            match self.property(property)?.state {
+                IndiState::Busy => {},
+                state => return Ok(state),
+            }
+            match tokio::time::timeout_at(deadline, events.recv()).await {
+                Err(_) => return Err(IndiError::Timeout(format!("{}::{} stayed busy", self.name, property))),
+                Ok(Err(broadcast::error::RecvError::Closed)) => return Err(IndiError::ChannelClosed),
+                Ok(_) => {},
+            }
+        }
+    }
+
+    /**
+    The vector `property` every time the driver updates it.
+
+    Subscribes right away and replays the updates onto its own copy of the vector, so every
+    update is seen with the values it carried. The stream ends when the session is dropped.
+    */
+    pub fn updates(&self, property: &str) -> impl Stream<Item = PropertyVector> + 'a {
+        let events = self.session.subscribe();
+        let mut registry = DeviceRegistry::new();
+        if let Some(vector) = self.session.property(&self.name, property) {
+            registry.define(vector);
+        }
+        let (device, property) = (self.name.clone(), property.to_string());
+        futures::stream::unfold((events, registry), move |(mut events, mut registry)| {
+            let (device, property) = (device.clone(), property.clone());
+            async move {
+                loop {
+                    match events.recv().await {
+                        Ok(SessionEvent::Message { msg, .. }) => {
+                            if msg.target() != Some((device.as_str(), Some(property.as_str()))) || !registry.apply(&msg) {
+                                continue;
+                            }
+                            if let Some(vector) = registry.property(&device, &property).cloned() {
+                                return Some((vector, (events, registry)));
+                            }
+                        },
+                        Ok(SessionEvent::State { .. }) => {},
+                        Err(broadcast::error::RecvError::Lagged(n)) => log::warn!("{}::{} missed {} updates", device, property, n),
+                        Err(broadcast::error::RecvError::Closed) => return None,
+                    }
+                }
+            }
+        })
+    }
+}
+
+/// The driver side of the wrapper tests.
+#[cfg(test)]
+pub(crate) mod fake {
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio::task::JoinHandle;
+    use crate::config_file::ConnectionSpec;
+    use crate::driver::{DriverRuntime, IndiDriver};
+    use crate::indi::session::Session;
+
+    /// A listener to answer as the driver, and a spec that connects to it without reconnecting.
+    pub async fn listen(name: &str) -> std::io::Result<(ConnectionSpec, TcpListener)> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let mut spec = ConnectionSpec::new(name, "127.0.0.1", listener.local_addr()?.port() as usize);
+        spec.reconnect.enabled = Some(false);
+        Ok((spec, listener))
+    }
+
+    /// Reads what the client sends into `received` until it contains `end`.
+    pub async fn read_until(socket: &mut TcpStream, received: &mut String, end: &str) {
+        let mut buf = vec![0u8; 4096];
+        while !received.contains(end) {
+            let n = socket.read(&mut buf).await.unwrap();
+            assert!(n > 0, "the client left before sending {}", end);
+            received.push_str(std::str::from_utf8(&buf[..n]).unwrap());
+        }
+    }
+
+    /**
+    Answers the first client of `listener` from a script, for devices without a simulator.
+
+    Sends `definitions`, then for every `(request, reply)` waits until the client sent `request`
+    and sends `reply`. Resolves to everything the client sent once it disconnected.
+    */
+    pub fn script(listener: TcpListener, definitions: &str, steps: Vec<(&'static str, String)>) -> JoinHandle<String> {
+        let definitions = definitions.to_string();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            socket.write_all(definitions.as_bytes()).await.unwrap();
+            let mut received = String::new();
+            for (request, reply) in steps {
+                read_until(&mut socket, &mut received, request).await;
+                socket.write_all(reply.as_bytes()).await.unwrap();
+            }
+            let mut buf = vec![0u8; 4096];
+            while let Ok(n) = socket.read(&mut buf).await {
+                if n == 0 {
+                    break;
+                }
+                received.push_str(&String::from_utf8_lossy(&buf[..n]));
+            }
+            received
+        })
+    }
+
+    /// Deletes `property` from a simulator and waits until the session lost it, to play a driver without it.
+    pub async fn remove<D: IndiDriver>(runtime: &DriverRuntime<D>, session: &Session, device: &str, property: &str) {
+        runtime.handle().delete(device, Some(property));
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while session.property(device, property).is_some() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        }).await.unwrap();
+    }
+}
@@ -0,0 +1,205 @@
+use std::fmt::{Display, Formatter};
+use futures::{Stream, StreamExt};
+
+use crate::devices::BaseDevice;
+use crate::indi::error::IndiResult;
+use crate::indi::registry::PropertyValue;
+use crate::indi::session::Session;
+
+const EQUATORIAL_EOD_COORD: &str = "EQUATORIAL_EOD_COORD";
+const ON_COORD_SET: &str = "ON_COORD_SET";
+const TELESCOPE_ABORT_MOTION: &str = "TELESCOPE_ABORT_MOTION";
+const TELESCOPE_PARK: &str = "TELESCOPE_PARK";
+const TELESCOPE_TRACK_STATE: &str = "TELESCOPE_TRACK_STATE";
+const TELESCOPE_TRACK_MODE: &str = "TELESCOPE_TRACK_MODE";
+const TELESCOPE_TRACK_RATE: &str = "TELESCOPE_TRACK_RATE";
+const TELESCOPE_PIER_SIDE: &str = "TELESCOPE_PIER_SIDE";
+
+/// Position in JNow, `ra` in hours and `dec` in degrees.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EquatorialCoords {
+    pub ra: f64,
+    pub dec: f64,
+}
+
+impl EquatorialCoords {
+    pub fn new(ra: f64, dec: f64) -> EquatorialCoords {
+        EquatorialCoords { ra, dec }
+    }
+}
+
+impl Display for EquatorialCoords {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "RA {:.4}h DEC {:+.4}°", self.ra, self.dec)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PierSide {
+    East,
+    West,
+}
+
+/// `TELESCOPE_TRACK_MODE`, `Custom` uses the rates of `TELESCOPE_TRACK_RATE`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrackMode {
+    Sidereal,
+    Solar,
+    Lunar,
+    Custom,
+}
+
+impl TrackMode {
+    fn element(&self) -> &'static str {
+        match self {
+            TrackMode::Sidereal => "TRACK_SIDEREAL",
+            TrackMode::Solar => "TRACK_SOLAR",
+            TrackMode::Lunar => "TRACK_LUNAR",
+            TrackMode::Custom => "TRACK_CUSTOM",
+        }
+    }
+}
+
+/**
+A mount, driven through the standard telescope properties.
+
+Slews and syncs set `ON_COORD_SET` and then `EQUATORIAL_EOD_COORD`, and like the other requests
+wait for the driver to settle. Methods fail with `IndiError::Unsupported` when the driver lacks
+the property they need.
+*/
+#[derive(Clone)]
+pub struct Telescope<'a> {
+    device: BaseDevice<'a>,
+}
+
+impl<'a> Telescope<'a> {
+    pub fn new(session: &'a Session, name: &str) -> Telescope<'a> {
+        Telescope { device: BaseDevice::new(session, name) }
+    }
+
+    pub fn device(&self) -> &BaseDevice<'a> {
+        &self.device
+    }
+
+    pub fn coordinates(&self) -> IndiResult<EquatorialCoords> {
+        Ok(EquatorialCoords {
+            ra: self.device.number(EQUATORIAL_EOD_COORD, "RA")?,
+            dec: self.device.number(EQUATORIAL_EOD_COORD, "DEC")?,
+        })
+    }
+
+    /// Slews to `target` and keeps tracking it, or stops there if the driver cannot track.
+    pub async fn slew_to(&self, target: EquatorialCoords) -> IndiResult<()> {
+        let action = match self.device.property(ON_COORD_SET)?.element("TRACK") {
+            Some(_) => "TRACK",
+            None => "SLEW",
+        };
+        self.goto(action, target).await
+    }
+
+    /// Tells the mount it is pointing at `position`.
+    pub async fn sync(&self, position: EquatorialCoords) -> IndiResult<()> {
+        self.goto("SYNC", position).await
+    }
+
+    async fn goto(&self, action: &str, coords: EquatorialCoords) -> IndiResult<()> {
+        //check before switching, so a mount without coordinates is not left in SYNC mode
+        self.device.property(EQUATORIAL_EOD_COORD)?;
+        self.device.switch(ON_COORD_SET, action).await?;
+        self.device.set_numbers(EQUATORIAL_EOD_COORD, &[("RA", coords.ra), ("DEC", coords.dec)]).await
+    }
+
+    pub async fn abort(&self) -> IndiResult<()> {
+        self.device.switch(TELESCOPE_ABORT_MOTION, "ABORT").await
+    }
+
+    /// Returns once the mount reports it is parked.
+    pub async fn park(&self) -> IndiResult<()> {
+        self.device.switch(TELESCOPE_PARK, "PARK").await
+    }
+
+    pub async fn unpark(&self) -> IndiResult<()> {
+        self.device.switch(TELESCOPE_PARK, "UNPARK").await
+    }
+
+    pub fn is_parked(&self) -> IndiResult<bool> {
+        self.device.is_on(TELESCOPE_PARK, "PARK")
+    }
+
+    pub async fn set_tracking(&self, on: bool) -> IndiResult<()> {
+        self.device.switch(TELESCOPE_TRACK_STATE, if on { "TRACK_ON" } else { "TRACK_OFF" }).await
+    }
+
+    pub fn is_tracking(&self) -> IndiResult<bool> {
+        self.device.is_on(TELESCOPE_TRACK_STATE, "TRACK_ON")
+    }
+
+    pub async fn set_track_mode(&self, mode: TrackMode) -> IndiResult<()> {
+        self.device.switch(TELESCOPE_TRACK_MODE, mode.element()).await
+    }
+
+    pub fn track_mode(&self) -> IndiResult<Option<TrackMode>> {
+        let on = self.device.switch_on(TELESCOPE_TRACK_MODE)?;
+        Ok([TrackMode::Sidereal, TrackMode::Solar, TrackMode::Lunar, TrackMode::Custom]
+            .into_iter()
+            .find(|mode| on.as_deref() == Some(mode.element())))
+    }
+
+    /// Custom rates in arcseconds per second, used with `TrackMode::Custom`.
+    pub async fn set_track_rate(&self, ra: f64, dec: f64) -> IndiResult<()> {
+        self.device.set_numbers(TELESCOPE_TRACK_RATE, &[("TRACK_RATE_RA", ra), ("TRACK_RATE_DE", dec)]).await
+    }
+
+    pub fn pier_side(&self) -> IndiResult<Option<PierSide>> {
+        Ok(match self.device.switch_on(TELESCOPE_PIER_SIDE)?.as_deref() {
+            Some("PIER_EAST") => Some(PierSide::East),
+            Some("PIER_WEST") => Some(PierSide::West),
+            _ => None,
+        })
+    }
+
+    /// The position every time the driver reports it, typically once a second while slewing.
+    pub fn positions(&self) -> impl Stream<Item = EquatorialCoords> + 'a {
+        self.device.updates(EQUATORIAL_EOD_COORD).filter_map(|vector| async move {
+            let number = |name: &str| match vector.element(name).map(|e| &e.value) {
+                Some(PropertyValue::Number { value, .. }) => Some(*value),
+                _ => None,
+            };
+            Some(EquatorialCoords { ra: number("RA")?, dec: number("DEC")? })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use std::time::Duration;
+    use futures::StreamExt;
+    use crate::devices::fake::remove;
+    use crate::devices::telescope::{EquatorialCoords, Telescope};
+    use crate::indi::error::IndiError;
+    use crate::indi::session::Session;
+    use crate::simulator::{spawn, TelescopeSimulator};
+
+    #[tokio::test]
+    async fn it_slews_and_reports_positions() -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut simulator = TelescopeSimulator::new("Telescope Simulator");
+        simulator.slew_rate = 1000.0;
+        let (runtime, spec) = spawn("mount", simulator).await?;
+        let session = Session::connect(&[spec]).await?;
+        session.get_properties(None, None).await?;
+        session.wait_for_property("Telescope Simulator", "TELESCOPE_PIER_SIDE", Duration::from_secs(5)).await?;
+        let mount = Telescope::new(&session, "Telescope Simulator");
+        assert_eq!(mount.coordinates()?.dec, 90.0);
+
+        let mut positions = Box::pin(mount.positions());
+        let target = EquatorialCoords::new(5.5, -5.4);
+        mount.slew_to(target).await?;
+        assert!(positions.next().await.is_some());
+        assert_eq!(mount.coordinates()?, target);
+
+        remove(&runtime, &session, "Telescope Simulator", "TELESCOPE_PARK").await;
+        assert!(matches!(mount.park().await, Err(IndiError::Unsupported { property, .. }) if property == "TELESCOPE_PARK"));
+        Ok(())
+    }
+}
@@ -0,0 +1,493 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use async_trait::async_trait;
+use futures::StreamExt;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::sync::broadcast;
+use tokio_util::codec::FramedRead;
+
+use crate::indi::async_connection::serve_clients;
+use crate::indi::codec::OutgoingMsgCodec;
+use crate::indi::common::{IndiPermission, IndiState};
+use crate::indi::enable_blob::{self, EnableBLOBValue};
+use crate::indi::error::{IndiError, IndiResult};
+use crate::indi::registry::{PropertyKind, PropertyVector};
+use crate::indi::switch::IndiSwitch;
+use crate::indi::OutgoingMsg;
+
+pub(crate) mod xml;
+
+/// Updates kept for a slow client before it misses some.
+const OUTPUT_CAPACITY: usize = 1024;
+
+/// What a client asked a driver to change, `values` in the order they were sent.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Request<T> {
+    pub device: String,
+    pub property: String,
+    pub values: Vec<(String, T)>,
+}
+
+impl<T> Request<T> {
+    pub fn value(&self, element: &str) -> Option<&T> {
+        self.values.iter().find(|(name, _)| name == element).map(|(_, value)| value)
+    }
+}
+
+/// A BLOB a client sent, decoded and inflated.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlobData {
+    /// Like `.fits`.
+    pub format: String,
+    pub data: Vec<u8>,
+}
+
+/// Values of the elements of a `Request`, see `DriverHandle::accept`.
+pub trait RequestValue {
+    /// Stores the value in `element` of `vector`, returns false if there is no such element.
+    fn store(&self, vector: &mut PropertyVector, element: &str) -> bool;
+}
+
+impl RequestValue for IndiSwitch {
+    fn store(&self, vector: &mut PropertyVector, element: &str) -> bool {
+        vector.set_switch(element, *self)
+    }
+}
+
+impl RequestValue for f64 {
+    fn store(&self, vector: &mut PropertyVector, element: &str) -> bool {
+        vector.set_number(element, *self)
+    }
+}
+
+impl RequestValue for String {
+    fn store(&self, vector: &mut PropertyVector, element: &str) -> bool {
+        vector.set_text(element, self)
+    }
+}
+
+impl RequestValue for BlobData {
+    fn store(&self, vector: &mut PropertyVector, element: &str) -> bool {
+        vector.set_blob(element, &self.format, &self.data)
+    }
+}
+
+/**
+A driver written in Rust, served by a `DriverRuntime`.
+
+`init` defines the properties through the `DriverHandle`, the runtime answers `getProperties`
+from them and only hands the driver requests for properties it defined with the right type that
+are writable. The default handlers accept whatever the client sent. A handler returning an
+error turns the property `Alert` with the error as message.
+
+Handlers run one at a time. Anything slow, like moving hardware, is better done in a task that
+reports through a clone of the handle, setting the property `Busy` until then.
+*/
+#[async_trait]
+pub trait IndiDriver: Send + 'static {
+    async fn init(&mut self, driver: &DriverHandle) -> IndiResult<()>;
+
+    async fn new_switch(&mut self, driver: &DriverHandle, request: Request<IndiSwitch>) -> IndiResult<()> {
+        driver.accept(&request)
+    }
+
+    async fn new_number(&mut self, driver: &DriverHandle, request: Request<f64>) -> IndiResult<()> {
+        driver.accept(&request)
+    }
+
+    async fn new_text(&mut self, driver: &DriverHandle, request: Request<String>) -> IndiResult<()> {
+        driver.accept(&request)
+    }
+
+    async fn new_blob(&mut self, driver: &DriverHandle, request: Request<BlobData>) -> IndiResult<()> {
+        driver.accept(&request)
+    }
+}
+
+/// Something for the clients, BLOBs only go to those that asked for them.
+#[derive(Debug)]
+struct Output {
+    device: String,
+    property: Option<String>,
+    blob: bool,
+    xml: String,
+}
+
+/**
+The properties of a driver, and the way to tell clients about changes to them.
+
+Cheap to clone, every change is sent to all connected clients.
+*/
+#[derive(Clone)]
+pub struct DriverHandle {
+    /// In the order they were defined, which is the order clients show them in.
+    properties: Arc<Mutex<Vec<PropertyVector>>>,
+    output: broadcast::Sender<Arc<Output>>,
+}
+
+impl DriverHandle {
+    fn new() -> DriverHandle {
+        let (output, _) = broadcast::channel(OUTPUT_CAPACITY);
+        DriverHandle { properties: Arc::new(Mutex::new(Vec::new())), output }
+    }
+
+    fn emit(&self, device: &str, property: Option<&str>, blob: bool, xml: String) {
+        //nobody may be connected yet, they get the definitions with getProperties
+        let _ = self.output.send(Arc::new(Output { device: device.to_string(), property: property.map(str::to_string), blob, xml }));
+    }
+
+    /// Defines `vector`, or replaces the one of the same device and name.
+    pub fn define(&self, mut vector: PropertyVector) {
+        if vector.timestamp.is_empty() {
+            vector.timestamp = timestamp();
+        }
+        let xml = xml::def(&vector);
+        {
+            let mut properties = self.properties.lock().unwrap();
+            match properties.iter_mut().find(|p| p.device == vector.device && p.name == vector.name) {
+                Some(existing) => *existing = vector.clone(),
+                None => properties.push(vector.clone()),
+            }
+        }
+        self.emit(&vector.device, Some(&vector.name), false, xml);
+    }
+
+    pub fn property(&self, device: &str, name: &str) -> Option<PropertyVector> {
+        self.properties.lock().unwrap().iter().find(|p| p.device == device && p.name == name).cloned()
+    }
+
+    pub fn properties(&self) -> Vec<PropertyVector> {
+        self.properties.lock().unwrap().clone()
+    }
+
+    /// Changes `device::name` with `change` and sends the clients all its values.
+    pub fn update(&self, device: &str, name: &str, change: impl FnOnce(&mut PropertyVector)) -> IndiResult<()> {
+        self.update_with_message(device, name, None, change)
+    }
+
+    /// Like `update`, with a message clients show alongside, e.g. why the property is `Alert`.
+    pub fn update_with_message(&self, device: &str, name: &str, message: Option<&str>, change: impl FnOnce(&mut PropertyVector)) -> IndiResult<()> {
+        let (xml, blob) = {
+            let mut properties = self.properties.lock().unwrap();
+            let vector = properties
+                .iter_mut()
+                .find(|p| p.device == device && p.name == name)
+                .ok_or_else(|| IndiError::InvalidRequest(format!("{}::{} is not defined", device, name)))?;
+            change(vector);
+            vector.timestamp = timestamp();
+            (xml::set(vector, message), vector.kind == PropertyKind::Blob)
+        };
+        self.emit(device, Some(name), blob, xml);
+        Ok(())
+    }
+
+    /// Stores the values of `request` and turns the property `Ok`.
+    pub fn accept<T: RequestValue>(&self, request: &Request<T>) -> IndiResult<()> {
+        self.update(&request.device, &request.property, |vector| {
+            for (element, value) in &request.values {
+                if !value.store(vector, element) {
+                    log::warn!("{}::{} has no element {}", request.device, request.property, element);
+                }
+            }
+            vector.state = IndiState::Ok;
+        })
+    }
+
+    pub fn message(&self, device: &str, message: &str) {
+        self.emit(device, None, false, xml::message(device, message, &timestamp()));
+    }
+
+    /// Deletes the property `name` of `device`, or the whole device.
+    pub fn delete(&self, device: &str, name: Option<&str>) {
+        self.properties.lock().unwrap().retain(|p| p.device != device || name.is_some_and(|name| p.name != name));
+        self.emit(device, name, false, xml::del(device, name, &timestamp()));
+    }
+}
+
+/// Which BLOBs a connection gets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Blobs {
+    /// Everything, for `indiserver` which keeps track of `enableBLOB` for its clients.
+    All,
+    /// What the client asked for with `enableBLOB`, nothing until then.
+    Enabled,
+}
+
+/**
+Serves an `IndiDriver` to clients.
+
+Over stdin and stdout it can be started by a stock `indiserver`, over its own TCP listener clients
+connect to it directly. Any number of connections share the driver.
+*/
+pub struct DriverRuntime<D> {
+    driver: Arc<tokio::sync::Mutex<D>>,
+    handle: DriverHandle,
+}
+
+impl<D> Clone for DriverRuntime<D> {
+    fn clone(&self) -> Self {
+        DriverRuntime { driver: self.driver.clone(), handle: self.handle.clone() }
+    }
+}
+
+impl<D: IndiDriver> DriverRuntime<D> {
+    /// Lets `driver` define its properties.
+    pub async fn start(mut driver: D) -> IndiResult<DriverRuntime<D>> {
+        let handle = DriverHandle::new();
+        driver.init(&handle).await?;
+        Ok(DriverRuntime { driver: Arc::new(tokio::sync::Mutex::new(driver)), handle })
+    }
+
+    pub fn handle(&self) -> &DriverHandle {
+        &self.handle
+    }
+
+    /// Serves `indiserver`, returns when it closes stdin.
+    pub async fn serve_stdio(&self) -> IndiResult<()> {
+        self.serve(tokio::io::stdin(), tokio::io::stdout(), Blobs::All).await
+    }
+
+    /// Serves the driver to every client that connects to `listener`, BLOBs only once they enable them.
+    pub async fn serve_tcp(&self, listener: TcpListener) -> IndiResult<()> {
+        serve_clients(listener, |reader, writer| {
+            let runtime = self.clone();
+            async move { runtime.serve(reader, writer, Blobs::Enabled).await }
+        }).await
+    }
+
+    /// Serves one client, returns when it is gone.
+    pub async fn serve<R, W>(&self, reader: R, mut writer: W, blobs: Blobs) -> IndiResult<()>
+        where R: AsyncRead + Unpin, W: AsyncWrite + Unpin {
+        //subscribe first, so nothing between the definitions and the updates is missed
+        let mut outputs = self.handle.output.subscribe();
+        let mut requests = FramedRead::new(reader, OutgoingMsgCodec::new());
+        let mut enabled: BTreeMap<(String, Option<String>), EnableBLOBValue> = BTreeMap::new();
+        loop {
+            tokio::select! {
+                request = requests.next() => match request {
+                    None => return Ok(()),
+                    Some(Err(e)) => return Err(e),
+                    Some(Ok(OutgoingMsg::GetProperties(get))) => {
+                        let wanted = |p: &PropertyVector| {
+                            get.device.as_ref().is_none_or(|d| *d == p.device) && get.name.as_ref().is_none_or(|n| *n == p.name)
+                        };
+                        for vector in self.handle.properties().iter().filter(|p| wanted(p)) {
+                            writer.write_all(xml::def(vector).as_bytes()).await?;
+                        }
+                        writer.flush().await?;
+                    },
+                    Some(Ok(OutgoingMsg::EnableBLOB(enable))) => match enable.device {
+                        Some(device) => {
+                            enabled.insert((device, enable.name), enable.value);
+                        },
+                        None => log::warn!("ignoring enableBLOB without a device"),
+                    },
+                    Some(Ok(msg)) => self.dispatch(msg).await,
+                },
+                output = outputs.recv() => match output {
+                    Ok(output) => {
+                        if blobs == Blobs::All || enable_blob::wants(&enabled, &output.device, output.property.as_deref(), output.blob) {
+                            writer.write_all(output.xml.as_bytes()).await?;
+                            writer.flush().await?;
+                        }
+                    },
+                    Err(broadcast::error::RecvError::Lagged(n)) => log::warn!("a client missed {} updates", n),
+                    Err(broadcast::error::RecvError::Closed) => return Ok(()),
+                },
+            }
+        }
+    }
+
+    /// Hands a `new*Vector` to the driver, a property it cannot take it for is answered with `Alert`.
+    async fn dispatch(&self, msg: OutgoingMsg) {
+        let (device, property, kind) = match &msg {
+            OutgoingMsg::NewSwitchVector(v) => (v.device.clone(), v.name.clone(), PropertyKind::Switch),
+            OutgoingMsg::NewNumberVector(v) => (v.device.clone(), v.name.clone(), PropertyKind::Number),
+            OutgoingMsg::NewTextVector(v) => (v.device.clone(), v.name.clone(), PropertyKind::Text),
+            OutgoingMsg::NewBlobVector(v) => (v.device.clone(), v.name.clone(), PropertyKind::Blob),
+            OutgoingMsg::GetProperties(_) | OutgoingMsg::EnableBLOB(_) => return,
+        };
+        match self.handle.property(&device, &property) {
+            None => {
+                log::warn!("ignoring a request for {}::{}, it is not defined", device, property);
+                return;
+            },
+            Some(vector) if vector.kind != kind || vector.perm == Some(IndiPermission::RO) => {
+                //the client waits for an answer to its request
+                let reason = format!("{}::{} is not a writable {:?} vector", device, property, kind);
+                log::warn!("rejecting a request, {}", reason);
+                let _ = self.handle.update_with_message(&device, &property, Some(&reason), |v| v.state = IndiState::Alert);
+                return;
+            },
+            Some(_) => {},
+        }
+
+        let mut driver = self.driver.lock().await;
+        let handle = &self.handle;
+        let result = match msg {
+            OutgoingMsg::NewSwitchVector(v) => {
+                let values = v.switches.into_iter().map(|s| (s.name, s.value)).collect();
+                driver.new_switch(handle, Request { device: v.device, property: v.name, values }).await
+            },
+            OutgoingMsg::NewNumberVector(v) => {
+                let values = v.numbers.into_iter().map(|n| (n.name, n.value)).collect();
+                driver.new_number(handle, Request { device: v.device, property: v.name, values }).await
+            },
+            OutgoingMsg::NewTextVector(v) => {
+                let values = v.texts.into_iter().map(|t| (t.name, t.value)).collect();
+                driver.new_text(handle, Request { device: v.device, property: v.name, values }).await
+            },
+            OutgoingMsg::NewBlobVector(v) => {
+                let values: IndiResult<Vec<_>> = v.blobs
+                    .iter()
+                    .map(|b| Ok((b.name.clone(), BlobData { format: b.decoded_format().to_string(), data: b.decode()? })))
+                    .collect();
+                match values {
+                    Ok(values) => driver.new_blob(handle, Request { device: v.device, property: v.name, values }).await,
+                    Err(e) => Err(e),
+                }
+            },
+            OutgoingMsg::GetProperties(_) | OutgoingMsg::EnableBLOB(_) => Ok(()),
+        };
+        drop(driver);
+
+        if let Err(e) = result {
+            log::warn!("{}::{}: {}", device, property, e);
+            let _ = handle.update_with_message(&device, &property, Some(&e.to_string()), |v| v.state = IndiState::Alert);
+        }
+    }
+}
+
+/// The current time in UTC as INDI writes it, like `2023-01-12T20:51:39`.
+pub(crate) fn timestamp() -> String {
+    let seconds = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0);
+    let (days, time) = (seconds.div_euclid(86400), seconds.rem_euclid(86400));
+    //civil date of a day count, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}", year, month, day, time / 3600, time % 3600 / 60, time % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use std::time::Duration;
+    use async_trait::async_trait;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use crate::devices::fake::listen;
+    use crate::driver::{DriverHandle, DriverRuntime, IndiDriver, Request};
+    use crate::indi::common::{IndiPermission, IndiState};
+    use crate::indi::error::{IndiError, IndiResult};
+    use crate::indi::registry::{PropertyKind, PropertyValue, PropertyVector};
+    use crate::indi::session::Session;
+    use crate::indi::switch::IndiSwitch;
+
+    struct Roof {
+        open: bool,
+    }
+
+    #[async_trait]
+    impl IndiDriver for Roof {
+        async fn init(&mut self, driver: &DriverHandle) -> IndiResult<()> {
+            driver.define(PropertyVector::new("Roof", "ROOF_MOTION", PropertyKind::Switch, "Motion", "Main Control")
+                .with_element("OPEN", "Open", PropertyValue::Switch(IndiSwitch::Off))
+                .with_element("CLOSE", "Close", PropertyValue::Switch(IndiSwitch::On)));
+            driver.define(PropertyVector::new("Roof", "ROOF_NOTE", PropertyKind::Text, "Note", "Options")
+                .with_element("TEXT", "Text", PropertyValue::Text(String::new())));
+            driver.define(PropertyVector::new("Roof", "ROOF_LIMIT", PropertyKind::Number, "Limit", "Options")
+                .with_element("SPEED", "Speed", PropertyValue::number(1.0, "%.1f", 0.0, 2.0, 0.1)));
+            driver.define(PropertyVector::new("Roof", "ROOF_SERIAL", PropertyKind::Text, "Serial", "Options")
+                .with_perm(IndiPermission::RO)
+                .with_element("TEXT", "Text", PropertyValue::Text("R-1".to_string())));
+            Ok(())
+        }
+
+        async fn new_switch(&mut self, driver: &DriverHandle, request: Request<IndiSwitch>) -> IndiResult<()> {
+            if request.value("OPEN") == Some(&IndiSwitch::On) && self.open {
+                return Err(IndiError::InvalidRequest("the roof is open already".to_string()));
+            }
+            self.open = request.value("OPEN") == Some(&IndiSwitch::On);
+            driver.accept(&request)
+        }
+    }
+
+    #[tokio::test]
+    async fn it_serves_drivers_to_clients() -> Result<(), Box<dyn Error + Send + Sync>> {
+        let runtime = DriverRuntime::start(Roof { open: false }).await?;
+        let (spec, listener) = listen("roof").await?;
+        let server = runtime.clone();
+        tokio::spawn(async move { server.serve_tcp(listener).await });
+
+        let session = Session::connect(&[spec]).await?;
+        session.get_properties(None, None).await?;
+        session.wait_for_property("Roof", "ROOF_LIMIT", Duration::from_secs(5)).await?;
+        assert_eq!(session.property("Roof", "ROOF_MOTION").map(|p| p.elements.len()), Some(2));
+
+        assert_eq!(session.set_switch("Roof", "ROOF_MOTION", &[("OPEN", IndiSwitch::On)]).await?, IndiState::Ok);
+        let motion = session.property("Roof", "ROOF_MOTION").unwrap();
+        assert_eq!(motion.element("CLOSE").unwrap().value, PropertyValue::Switch(IndiSwitch::Off));
+        assert_eq!(session.set_switch("Roof", "ROOF_MOTION", &[("OPEN", IndiSwitch::On)]).await?, IndiState::Alert);
+
+        assert_eq!(session.set_text("Roof", "ROOF_NOTE", &[("TEXT", "<dome & co>")]).await?, IndiState::Ok);
+        assert_eq!(session.set_number("Roof", "ROOF_LIMIT", &[("SPEED", 1.5)]).await?, IndiState::Ok);
+        assert_eq!(runtime.handle().property("Roof", "ROOF_NOTE").unwrap().element("TEXT").unwrap().value, PropertyValue::Text("<dome & co>".to_string()));
+        assert_eq!(session.property("Roof", "ROOF_LIMIT").unwrap().element("SPEED").map(|e| e.value.to_string()), Some("1.5".to_string()));
+
+        runtime.handle().delete("Roof", Some("ROOF_NOTE"));
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while session.property("Roof", "ROOF_NOTE").is_some() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        }).await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_answers_indiserver_over_a_pipe() -> Result<(), Box<dyn Error + Send + Sync>> {
+        let runtime = DriverRuntime::start(Roof { open: false }).await?;
+        let (client, driver) = tokio::io::duplex(4096);
+        let (reader, writer) = tokio::io::split(driver);
+        let server = runtime.clone();
+        let serving = tokio::spawn(async move { server.serve(reader, writer, crate::driver::Blobs::All).await });
+
+        let (mut from_driver, mut to_driver) = tokio::io::split(client);
+        to_driver.write_all(br#"<getProperties version="1.7" device="Roof" name="ROOF_MOTION"/>"#).await?;
+        let mut received = String::new();
+        let mut buf = vec![0u8; 4096];
+        while !received.contains("</defSwitchVector>") {
+            let n = from_driver.read(&mut buf).await?;
+            received.push_str(std::str::from_utf8(&buf[..n])?);
+        }
+        assert!(received.starts_with(r#"<defSwitchVector device="Roof" name="ROOF_MOTION" label="Motion" group="Main Control" state="Idle" perm="rw" rule="OneOfMany" timeout="60""#));
+        assert!(!received.contains("ROOF_NOTE"));
+
+        //requests the driver cannot take are answered, not dropped
+        for request in [
+            r#"<newTextVector device="Roof" name="ROOF_SERIAL"><oneText name="TEXT">R-2</oneText></newTextVector>"#,
+            r#"<newNumberVector device="Roof" name="ROOF_NOTE"><oneNumber name="TEXT">1</oneNumber></newNumberVector>"#,
+        ] {
+            to_driver.write_all(request.as_bytes()).await?;
+            received.clear();
+            while !received.contains("</setTextVector>") {
+                let n = from_driver.read(&mut buf).await?;
+                received.push_str(std::str::from_utf8(&buf[..n])?);
+            }
+            assert!(received.contains(r#"state="Alert""#), "{}", received);
+            assert!(received.contains("is not a writable"), "{}", received);
+        }
+        assert_eq!(runtime.handle().property("Roof", "ROOF_SERIAL").unwrap().element("TEXT").unwrap().value, PropertyValue::Text("R-1".to_string()));
+
+        to_driver.shutdown().await?;
+        serving.await??;
+        Ok(())
+    }
+}
@@ -0,0 +1,117 @@
+use std::fmt::Write;
+use quick_xml::escape::escape;
+
+use crate::indi::common::IndiPermission;
+use crate::indi::registry::{PropertyKind, PropertyValue, PropertyVector};
+use crate::indi::switch::IndiSwitch;
+
+fn tag(kind: PropertyKind) -> &'static str {
+    match kind {
+        PropertyKind::Number => "Number",
+        PropertyKind::Switch => "Switch",
+        PropertyKind::Text => "Text",
+        PropertyKind::Light => "Light",
+        PropertyKind::Blob => "BLOB",
+    }
+}
+
+fn perm(perm: IndiPermission) -> &'static str {
+    match perm {
+        IndiPermission::RO => "ro",
+        IndiPermission::RW => "rw",
+        IndiPermission::WO => "wo",
+    }
+}
+
+fn switch(value: IndiSwitch) -> &'static str {
+    match value {
+        IndiSwitch::On => "On",
+        IndiSwitch::Off => "Off",
+    }
+}
+
+/// Text of an element as `def*` and `set*` carry it, BLOBs are base64 already.
+fn text(value: &PropertyValue) -> String {
+    match value {
+        PropertyValue::Number { value, .. } => value.to_string(),
+        PropertyValue::Switch(value) => switch(*value).to_string(),
+        PropertyValue::Text(value) => escape(value).into_owned(),
+        PropertyValue::Light(value) => format!("{:?}", value),
+        PropertyValue::Blob { value, .. } => value.clone(),
+    }
+}
+
+/// `def*Vector` announcing `vector` with its current values.
+pub(crate) fn def(vector: &PropertyVector) -> String {
+    let tag = tag(vector.kind);
+    let mut xml = format!(
+        r#"<def{}Vector device="{}" name="{}" label="{}" group="{}" state="{:?}""#,
+        tag, escape(&vector.device), escape(&vector.name), escape(&vector.label), escape(&vector.group), vector.state
+    );
+    if let Some(p) = vector.perm {
+        let _ = write!(xml, r#" perm="{}""#, perm(p));
+    }
+    if let Some(rule) = vector.rule {
+        let _ = write!(xml, r#" rule="{:?}""#, rule);
+    }
+    if vector.kind != PropertyKind::Light {
+        let _ = write!(xml, r#" timeout="{}""#, vector.timeout);
+    }
+    let _ = writeln!(xml, r#" timestamp="{}">"#, escape(&vector.timestamp));
+
+    for e in &vector.elements {
+        let _ = write!(xml, r#"  <def{} name="{}" label="{}""#, tag, escape(&e.name), escape(&e.label));
+        match &e.value {
+            PropertyValue::Number { format, min, max, step, .. } => {
+                let _ = write!(xml, r#" format="{}" min="{}" max="{}" step="{}""#, escape(format), min, max, step);
+            },
+            //clients learn about BLOBs from `set`, the definition is empty
+            PropertyValue::Blob { .. } => {
+                xml.push_str("/>\n");
+                continue;
+            },
+            _ => {},
+        }
+        let _ = writeln!(xml, ">{}</def{}>", text(&e.value), tag);
+    }
+    let _ = writeln!(xml, "</def{}Vector>", tag);
+    xml
+}
+
+/// `set*Vector` with the state and all values of `vector`.
+pub(crate) fn set(vector: &PropertyVector, message: Option<&str>) -> String {
+    let tag = tag(vector.kind);
+    let mut xml = format!(
+        r#"<set{}Vector device="{}" name="{}" state="{:?}""#,
+        tag, escape(&vector.device), escape(&vector.name), vector.state
+    );
+    if vector.kind != PropertyKind::Light {
+        let _ = write!(xml, r#" timeout="{}""#, vector.timeout);
+    }
+    let _ = write!(xml, r#" timestamp="{}""#, escape(&vector.timestamp));
+    if let Some(message) = message {
+        let _ = write!(xml, r#" message="{}""#, escape(message));
+    }
+    xml.push_str(">\n");
+
+    for e in &vector.elements {
+        let _ = write!(xml, r#"  <one{} name="{}""#, tag, escape(&e.name));
+        if let PropertyValue::Blob { format, size, value, .. } = &e.value {
+            let _ = write!(xml, r#" size="{}" format="{}" enclen="{}""#, size, escape(format), value.len());
+        }
+        let _ = writeln!(xml, ">{}</one{}>", text(&e.value), tag);
+    }
+    let _ = writeln!(xml, "</set{}Vector>", tag);
+    xml
+}
+
+pub(crate) fn message(device: &str, message: &str, timestamp: &str) -> String {
+    format!(r#"<message device="{}" timestamp="{}" message="{}"/>"#, escape(device), escape(timestamp), escape(message)) + "\n"
+}
+
+pub(crate) fn del(device: &str, name: Option<&str>, timestamp: &str) -> String {
+    match name {
+        Some(name) => format!(r#"<delProperty device="{}" name="{}" timestamp="{}"/>"#, escape(device), escape(name), escape(timestamp)) + "\n",
+        None => format!(r#"<delProperty device="{}" timestamp="{}"/>"#, escape(device), escape(timestamp)) + "\n",
+    }
+}
@@ -0,0 +1,121 @@
+use bytes::{Bytes, BytesMut};
+use quick_xml::events::Event;
+use tokio_util::codec::Decoder;
+
+use crate::indi::codec::IndiXmlFramer;
+use crate::indi::error::IndiError;
+
+/**
+A top level element exactly as it went over the wire, with what routing it takes from its start tag.
+
+Nothing else of it is parsed, so elements we have no type for pass through unchanged.
+*/
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub tag: String,
+    pub device: Option<String>,
+    pub name: Option<String>,
+    pub xml: Bytes,
+}
+
+impl Frame {
+    pub fn new(xml: Bytes) -> Frame {
+        let mut frame = Frame { tag: String::new(), device: None, name: None, xml };
+        let mut reader = quick_xml::Reader::from_reader(&frame.xml[..]);
+        let start = loop {
+            match reader.read_event() {
+                Ok(Event::Start(start)) | Ok(Event::Empty(start)) => break start,
+                Ok(Event::Eof) | Err(_) => return frame,
+                Ok(_) => {},
+            }
+        };
+        let tag = String::from_utf8_lossy(start.name().as_ref()).into_owned();
+        for attribute in start.attributes().flatten() {
+            let value = match attribute.decode_and_unescape_value(&reader) {
+                Ok(value) => value.into_owned(),
+                Err(_) => continue,
+            };
+            match attribute.key.as_ref() {
+                b"device" => frame.device = Some(value),
+                b"name" => frame.name = Some(value),
+                _ => {},
+            }
+        }
+        frame.tag = tag;
+        frame
+    }
+
+    /// Carries BLOB data, which clients only get if they asked for it with `enableBLOB`.
+    pub fn is_blob(&self) -> bool {
+        self.tag == "setBLOBVector"
+    }
+}
+
+/// Frames the elements of a connection without parsing them, in either direction.
+#[derive(Debug)]
+pub struct FrameCodec {
+    framer: IndiXmlFramer,
+}
+
+impl FrameCodec {
+    pub fn new() -> FrameCodec {
+        FrameCodec { framer: IndiXmlFramer::new() }
+    }
+}
+
+impl Default for FrameCodec {
+    fn default() -> Self {
+        FrameCodec::new()
+    }
+}
+
+impl Decoder for FrameCodec {
+    type Item = Frame;
+    type Error = IndiError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        match self.framer.next_frame(src) {
+            Some(range) => {
+                let frame = src.split_to(range.end).freeze();
+                Ok(Some(Frame::new(frame.slice(range.start..))))
+            },
+            None => {
+                let discardable = self.framer.discardable();
+                if discardable > 0 {
+                    let _ = src.split_to(discardable);
+                    self.framer.discard(discardable);
+                }
+                Ok(None)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::BytesMut;
+    use tokio_util::codec::Decoder;
+    use crate::hub::frame::FrameCodec;
+
+    #[test]
+    fn it_frames_without_parsing() {
+        let xml = r#"
+            <setBLOBVector device="CCD &amp; Co" name="CCD1" state="Ok"><oneBLOB name="CCD1" size="3" format=".fits" len="4">AAAA</oneBLOB></setBLOBVector>
+            <pingRequest uid="1"/>
+            <message message="hello"/>
+        "#;
+        let mut codec = FrameCodec::new();
+        let mut buf = BytesMut::from(xml);
+        let mut frames = Vec::new();
+        while let Some(frame) = codec.decode(&mut buf).unwrap() {
+            frames.push(frame);
+        }
+
+        assert_eq!(frames.len(), 3);
+        assert_eq!((frames[0].device.as_deref(), frames[0].name.as_deref()), (Some("CCD & Co"), Some("CCD1")));
+        assert!(frames[0].is_blob());
+        assert_eq!(&frames[1].xml[..], br#"<pingRequest uid="1"/>"#);
+        assert_eq!((frames[1].tag.as_str(), frames[1].device.as_deref()), ("pingRequest", None));
+        assert!(!frames[2].is_blob());
+    }
+}
@@ -0,0 +1,411 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use futures::StreamExt;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{TcpListener, TcpStream};
+use bytes::Bytes;
+use tokio::sync::{broadcast, mpsc};
+use tokio_util::codec::FramedRead;
+
+use crate::config_file::ConnectionSpec;
+use crate::driver::{timestamp, xml};
+use crate::hub::frame::{Frame, FrameCodec};
+use crate::indi::async_connection::serve_clients;
+use crate::indi::enable_blob::{self, EnableBLOB};
+use crate::indi::error::{IndiError, IndiResult};
+use crate::indi::recording::{Recorder, Tap};
+use crate::indi::OutgoingMsg;
+
+pub mod frame;
+
+/// Upstream messages a slow client may fall behind by before it misses some.
+const BACKLOG: usize = 1024;
+
+type Frames = FramedRead<Tap<OwnedReadHalf>, FrameCodec>;
+
+struct Upstream {
+    spec: ConnectionSpec,
+    /// Elements for the task writing to the upstream, `None` while it is not connected.
+    queue: Mutex<Option<mpsc::UnboundedSender<Bytes>>>,
+}
+
+/// The last `def*` and `set*` of a property, what a client asking for it is answered with.
+struct Cached {
+    device: String,
+    name: String,
+    def: Frame,
+    set: Option<Frame>,
+}
+
+struct Shared {
+    upstreams: Vec<Upstream>,
+    /// The upstream each device is on, by its index.
+    devices: Mutex<BTreeMap<String, usize>>,
+    /// In the order the upstreams defined them.
+    properties: Mutex<Vec<Cached>>,
+    frames: broadcast::Sender<Frame>,
+}
+
+/**
+Serves the devices of several INDI servers to clients as if they were on one.
+
+Every element is forwarded as it was read, clients get what the upstreams send for the devices they
+asked for with `getProperties`, BLOBs only after their own `enableBLOB`. `getProperties` for known
+devices is answered from the last `def*` and `set*` of each property, so other clients do not get
+them defined again. Requests go to the upstream that defined the device, those without a device to
+all of them. Upstreams that cannot be reached or go away are retried by their `reconnect` policy,
+their devices are deleted for the clients meanwhile.
+*/
+#[derive(Clone)]
+pub struct Hub {
+    shared: Arc<Shared>,
+}
+
+impl Hub {
+    /// Connects to every upstream, those that cannot be reached yet are retried in the background.
+    pub async fn connect(specs: &[ConnectionSpec]) -> Hub {
+        let (frames, _) = broadcast::channel(BACKLOG);
+        let shared = Arc::new(Shared {
+            upstreams: specs.iter().map(|spec| Upstream { spec: spec.clone(), queue: Mutex::new(None) }).collect(),
+            devices: Mutex::new(BTreeMap::new()),
+            properties: Mutex::new(Vec::new()),
+            frames,
+        });
+        for (upstream, spec) in specs.iter().enumerate() {
+            let frames = match shared.open(upstream).await {
+                Ok(frames) => Some(frames),
+                Err(e) => {
+                    log::warn!("{} is down: {}", spec.name, e);
+                    None
+                },
+            };
+            tokio::spawn(read_upstream(shared.clone(), upstream, frames));
+        }
+        Hub { shared }
+    }
+
+    /// The devices defined so far, with the name of the connection each is on.
+    pub fn devices(&self) -> Vec<(String, String)> {
+        let devices = self.shared.devices.lock().unwrap();
+        devices.iter().map(|(device, upstream)| (device.clone(), self.shared.upstreams[*upstream].spec.name.clone())).collect()
+    }
+
+    /// Merges the upstreams for every client that connects to `listener`.
+    pub async fn serve_tcp(&self, listener: TcpListener) -> IndiResult<()> {
+        serve_clients(listener, |reader, writer| {
+            let hub = self.clone();
+            async move { hub.serve(reader, writer).await }
+        }).await
+    }
+
+    /// Serves one client, returns when it is gone.
+    pub async fn serve<R, W>(&self, reader: R, mut writer: W) -> IndiResult<()>
+        where R: AsyncRead + Unpin, W: AsyncWrite + Unpin {
+        let mut frames = self.shared.frames.subscribe();
+        let mut requests = FramedRead::new(reader, FrameCodec::new());
+        //what the client sent getProperties for, `None` standing for all
+        let mut interests: Vec<(Option<String>, Option<String>)> = Vec::new();
+        let mut enabled = BTreeMap::new();
+        loop {
+            tokio::select! {
+                request = requests.next() => match request {
+                    None => return Ok(()),
+                    Some(Err(e)) => return Err(e),
+                    Some(Ok(request)) => match request.tag.as_str() {
+                        "getProperties" => {
+                            interests.push((request.device.clone(), request.name.clone()));
+                            let known = request.device.as_ref().is_none_or(|device| self.shared.devices.lock().unwrap().contains_key(device));
+                            if known {
+                                for frame in self.shared.cached(request.device.as_deref(), request.name.as_deref()) {
+                                    writer.write_all(&frame.xml).await?;
+                                }
+                                writer.flush().await?;
+                            } else {
+                                self.shared.route(&request);
+                            }
+                        },
+                        //the upstreams send every BLOB to the hub, clients only get what they enabled
+                        "enableBLOB" => match OutgoingMsg::parse(&String::from_utf8_lossy(&request.xml)) {
+                            Ok(OutgoingMsg::EnableBLOB(EnableBLOB { device: Some(device), name, value })) => {
+                                enabled.insert((device, name), value);
+                            },
+                            Ok(_) => log::warn!("ignoring enableBLOB without a device"),
+                            Err(e) => log::warn!("ignoring enableBLOB: {}", e),
+                        },
+                        _ => self.shared.route(&request),
+                    },
+                },
+                frame = frames.recv() => match frame {
+                    Ok(frame) => {
+                        let wanted = match &frame.device {
+                            Some(device) => interested(&interests, device, frame.name.as_deref())
+                                && enable_blob::wants(&enabled, device, frame.name.as_deref(), frame.is_blob()),
+                            None => true,
+                        };
+                        if wanted {
+                            writer.write_all(&frame.xml).await?;
+                            writer.flush().await?;
+                        }
+                    },
+                    Err(broadcast::error::RecvError::Lagged(n)) => log::warn!("a client missed {} messages", n),
+                    Err(broadcast::error::RecvError::Closed) => return Ok(()),
+                },
+            }
+        }
+    }
+}
+
+impl Shared {
+    async fn open(&self, upstream: usize) -> IndiResult<Frames> {
+        let spec = &self.upstreams[upstream].spec;
+        let address = spec.address();
+        let stream = TcpStream::connect(&address).await
+            .map_err(|source| IndiError::Connect { address, source })?;
+        let (reader, writer) = stream.into_split();
+        let recorder = match &spec.record {
+            Some(path) => Some(Arc::new(Recorder::open(path)?)),
+            None => None,
+        };
+        let (queue, queued) = mpsc::unbounded_channel();
+        tokio::spawn(write_upstream(writer, recorder.clone(), queued));
+        *self.upstreams[upstream].queue.lock().unwrap() = Some(queue);
+        //learn the devices right away, so requests can be routed before a client asked for them
+        self.send(upstream, Bytes::from_static(br#"<getProperties version="1.7"/>"#))?;
+        Ok(FramedRead::new(Tap::new(reader, recorder), FrameCodec::new()))
+    }
+
+    /// Queues `xml` for `upstream`, the reading and writing of a connection do not wait for each other.
+    fn send(&self, upstream: usize, xml: Bytes) -> IndiResult<()> {
+        let queue = self.upstreams[upstream].queue.lock().unwrap();
+        queue.as_ref().and_then(|queue| queue.send(xml).ok()).ok_or(IndiError::NotConnected)
+    }
+
+    /// Forwards a client's `request` to the upstream of its device, or to all of them.
+    fn route(&self, request: &Frame) {
+        let upstream = request.device.as_ref().and_then(|device| self.devices.lock().unwrap().get(device).copied());
+        let upstreams = match (upstream, &request.device) {
+            (Some(upstream), _) => vec![upstream],
+            (None, Some(device)) if request.tag.starts_with("new") => {
+                log::warn!("dropping {} for {}, no upstream defined it", request.tag, device);
+                return;
+            },
+            (None, _) => (0..self.upstreams.len()).collect(),
+        };
+        for upstream in upstreams {
+            if let Err(e) = self.send(upstream, request.xml.clone()) {
+                log::warn!("could not forward {} to {}: {}", request.tag, self.upstreams[upstream].spec.name, e);
+            }
+        }
+    }
+
+    /// Hands an element of `upstream` to the clients, keeping track of the devices on it.
+    fn received(&self, upstream: usize, frame: Frame) {
+        if let Some(device) = &frame.device {
+            let added = {
+                let mut devices = self.devices.lock().unwrap();
+                match devices.get(device) {
+                    Some(owner) if *owner != upstream => {
+                        log::warn!("ignoring {} of {} from {}, it is on {}", frame.tag, device, self.upstreams[upstream].spec.name, self.upstreams[*owner].spec.name);
+                        return;
+                    },
+                    Some(_) if frame.tag == "delProperty" && frame.name.is_none() => {
+                        devices.remove(device);
+                        false
+                    },
+                    Some(_) => false,
+                    None => {
+                        devices.insert(device.clone(), upstream);
+                        true
+                    },
+                }
+            };
+            if added {
+                let enable = format!(r#"<enableBLOB device="{}">Also</enableBLOB>"#, quick_xml::escape::escape(device));
+                if let Err(e) = self.send(upstream, enable.into()) {
+                    log::warn!("could not enable BLOBs of {}: {}", device, e);
+                }
+            }
+        }
+        self.remember(&frame);
+        let _ = self.frames.send(frame);
+    }
+
+    fn remember(&self, frame: &Frame) {
+        let device = match &frame.device {
+            Some(device) => device,
+            None => return,
+        };
+        let mut properties = self.properties.lock().unwrap();
+        let position = properties.iter().position(|p| p.device == *device && Some(&p.name) == frame.name.as_ref());
+        match (frame.tag.as_str(), &frame.name, position) {
+            (tag, Some(name), position) if tag.starts_with("def") => {
+                let cached = Cached { device: device.clone(), name: name.clone(), def: frame.clone(), set: None };
+                match position {
+                    Some(position) => properties[position] = cached,
+                    None => properties.push(cached),
+                }
+            },
+            //BLOBs are not worth keeping, a client that just asked has not enabled them yet
+            (tag, _, Some(position)) if tag.starts_with("set") && !frame.is_blob() => properties[position].set = Some(frame.clone()),
+            ("delProperty", name, _) => properties.retain(|p| p.device != *device || name.as_ref().is_some_and(|name| p.name != *name)),
+            _ => {},
+        }
+    }
+
+    /// What a `getProperties` for `device::name` is answered with, `None` standing for all.
+    fn cached(&self, device: Option<&str>, name: Option<&str>) -> Vec<Frame> {
+        let properties = self.properties.lock().unwrap();
+        properties.iter()
+            .filter(|p| device.is_none_or(|d| d == p.device) && name.is_none_or(|n| n == p.name))
+            .flat_map(|p| std::iter::once(p.def.clone()).chain(p.set.clone()))
+            .collect()
+    }
+
+    /// Forgets the devices of `upstream`, deleting them for the clients.
+    fn lost(&self, upstream: usize) {
+        *self.upstreams[upstream].queue.lock().unwrap() = None;
+        let lost: Vec<String> = {
+            let mut devices = self.devices.lock().unwrap();
+            let lost = devices.iter().filter(|(_, owner)| **owner == upstream).map(|(device, _)| device.clone()).collect();
+            devices.retain(|_, owner| *owner != upstream);
+            lost
+        };
+        self.properties.lock().unwrap().retain(|p| !lost.contains(&p.device));
+        for device in lost {
+            let del = xml::del(&device, None, &timestamp());
+            let _ = self.frames.send(Frame::new(del.into_bytes().into()));
+        }
+    }
+}
+
+async fn write_upstream(mut writer: OwnedWriteHalf, recorder: Option<Arc<Recorder>>, mut queued: mpsc::UnboundedReceiver<Bytes>) {
+    while let Some(xml) = queued.recv().await {
+        let written = match writer.write_all(&xml).await {
+            Ok(()) => writer.flush().await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = written {
+            //the reading side notices too and reconnects
+            log::warn!("writing upstream failed: {}", e);
+            return;
+        }
+        if let Some(recorder) = &recorder {
+            recorder.sent(&String::from_utf8_lossy(&xml));
+        }
+    }
+}
+
+async fn read_upstream(shared: Arc<Shared>, upstream: usize, mut frames: Option<Frames>) {
+    let name = shared.upstreams[upstream].spec.name.clone();
+    let policy = shared.upstreams[upstream].spec.reconnect_policy();
+    let mut attempt = 0;
+    loop {
+        if let Some(mut connected) = frames.take() {
+            while let Some(frame) = connected.next().await {
+                match frame {
+                    Ok(frame) => shared.received(upstream, frame),
+                    Err(e) => {
+                        log::warn!("reading {} failed: {}", name, e);
+                        break;
+                    },
+                }
+            }
+            log::warn!("lost {}", name);
+            shared.lost(upstream);
+            attempt = 0;
+        }
+
+        let delay = match policy.delay(attempt) {
+            Some(delay) => delay,
+            None => {
+                log::warn!("giving up on {}", name);
+                return;
+            },
+        };
+        tokio::time::sleep(delay).await;
+        match shared.open(upstream).await {
+            Ok(connected) => {
+                log::info!("connected to {}", name);
+                frames = Some(connected);
+            },
+            Err(e) => {
+                log::warn!("connect attempt {} to {} failed: {}", attempt, name, e);
+                attempt += 1;
+            }
+        }
+    }
+}
+
+/// Whether a client asked for `device::name` with `getProperties`.
+fn interested(interests: &[(Option<String>, Option<String>)], device: &str, name: Option<&str>) -> bool {
+    interests.iter().any(|(d, n)| {
+        d.as_deref().is_none_or(|d| d == device) && (n.is_none() || name.is_none() || n.as_deref() == name)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use std::time::Duration;
+    use futures::StreamExt;
+    use tokio::io::AsyncWriteExt;
+    use tokio_util::codec::FramedRead;
+    use crate::config_file::ConnectionSpec;
+    use crate::devices::camera::Camera;
+    use crate::devices::focuser::Focuser;
+    use crate::hub::frame::FrameCodec;
+    use crate::hub::Hub;
+    use crate::indi::session::Session;
+    use crate::simulator::{spawn, CcdSimulator, FocuserSimulator};
+
+    #[tokio::test]
+    async fn it_merges_upstreams_and_routes_requests() -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut ccd = CcdSimulator::new("CCD Simulator");
+        (ccd.width, ccd.height) = (64, 48);
+        let mut focuser = FocuserSimulator::new("Focuser Simulator");
+        focuser.speed = 5000.0;
+        let (_, ccd) = spawn("ccd", ccd).await?;
+        let (_, focuser) = spawn("focuser", focuser).await?;
+        //an upstream that is down does not keep the others from being served
+        let down = tokio::net::TcpListener::bind("127.0.0.1:0").await?.local_addr()?.port() as usize;
+        let hub = Hub::connect(&[ccd, ConnectionSpec::new("mount", "127.0.0.1", down), focuser]).await;
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let spec = ConnectionSpec::new("hub", "127.0.0.1", listener.local_addr()?.port() as usize);
+        let server = hub.clone();
+        tokio::spawn(async move { server.serve_tcp(listener).await });
+        while hub.devices().len() < 2 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        //a client that watches the focuser and the image property, without enabling BLOBs
+        let socket = tokio::net::TcpStream::connect(("127.0.0.1", spec.port as u16)).await?;
+        let (reader, mut writer) = socket.into_split();
+        writer.write_all(br#"<getProperties version="1.7" device="Focuser Simulator"/>"#).await?;
+        writer.write_all(br#"<getProperties version="1.7" device="CCD Simulator" name="CCD1"/>"#).await?;
+        let watched = tokio::spawn(async move {
+            let frames = FramedRead::new(reader, FrameCodec::new()).take_until(tokio::time::sleep(Duration::from_secs(2)));
+            frames.map(|frame| frame.unwrap()).collect::<Vec<_>>().await
+        });
+
+        let session = Session::connect(&[spec]).await?;
+        session.get_properties(None, None).await?;
+        session.wait_for_property("CCD Simulator", "CCD1", Duration::from_secs(5)).await?;
+        session.wait_for_property("Focuser Simulator", "FOCUS_BACKLASH_STEPS", Duration::from_secs(5)).await?;
+        assert_eq!(hub.devices(), [("CCD Simulator".to_string(), "ccd".to_string()), ("Focuser Simulator".to_string(), "focuser".to_string())]);
+
+        let focuser = Focuser::new(&session, "Focuser Simulator");
+        focuser.move_to(50500).await?;
+        assert_eq!(focuser.position()?, 50500);
+        let image = Camera::new(&session, "CCD Simulator").expose(0.1).await?;
+        assert!(image.data.starts_with(b"SIMPLE"));
+
+        let watched = watched.await?;
+        //asking for the properties again does not define them again for the other clients
+        assert_eq!(watched.iter().filter(|f| f.tag == "defNumberVector" && f.name.as_deref() == Some("ABS_FOCUS_POSITION")).count(), 1);
+        assert!(watched.iter().any(|f| f.tag == "setNumberVector" && f.name.as_deref() == Some("ABS_FOCUS_POSITION")));
+        assert!(watched.iter().any(|f| f.tag == "defBLOBVector"));
+        assert!(watched.iter().all(|f| !f.is_blob() && f.name.as_deref() != Some("CCD_EXPOSURE")));
+        Ok(())
+    }
+}
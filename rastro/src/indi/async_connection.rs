@@ -0,0 +1,145 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tokio::io::AsyncWriteExt;
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_stream::Stream;
+use tokio_util::codec::FramedRead;
+
+use crate::config_file::ConnectionSpec;
+use crate::indi::blob_stream::BlobStorage;
+use crate::indi::codec::IncomingMsgCodec;
+use crate::indi::error::{IndiError, IndiResult};
+use crate::indi::recording::{Recorder, Tap};
+use crate::indi::{IncomingMsg, OutgoingMsg};
+
+/**
+Sending half of an INDI connection running on the tokio runtime.
+
+Cheap to clone, so any number of tasks can send over the same socket.
+The incoming messages are delivered through the `IncomingMsgStream` returned by `connect`.
+*/
+#[derive(Clone)]
+pub struct AsyncIndiConnection {
+    writer: Arc<tokio::sync::Mutex<OwnedWriteHalf>>,
+    recorder: Option<Arc<Recorder>>,
+}
+
+/// Messages read from the server, ends when the socket is closed or fails.
+pub struct IncomingMsgStream {
+    frames: FramedRead<Tap<OwnedReadHalf>, IncomingMsgCodec>,
+}
+
+impl Stream for IncomingMsgStream {
+    type Item = IncomingMsg;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        match Pin::new(&mut self.frames).poll_next(cx) {
+            Poll::Ready(Some(Err(e))) => {
+                log::error!("reader exited with error {}", e);
+                Poll::Ready(None)
+            },
+            Poll::Ready(Some(Ok(msg))) => Poll::Ready(Some(msg)),
+            Poll::Ready(None) => Poll::Ready(None),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl AsyncIndiConnection {
+    pub async fn connect(spec: &ConnectionSpec) -> IndiResult<(AsyncIndiConnection, IncomingMsgStream)> {
+        AsyncIndiConnection::connect_with_storage(spec, BlobStorage::InMemory).await
+    }
+
+    pub async fn connect_with_storage(spec: &ConnectionSpec, storage: BlobStorage) -> IndiResult<(AsyncIndiConnection, IncomingMsgStream)> {
+        let address = spec.address();
+        let stream = TcpStream::connect(&address).await
+            .map_err(|source| IndiError::Connect { address, source })?;
+        let (read, write) = stream.into_split();
+        let recorder = match &spec.record {
+            Some(path) => Some(Arc::new(Recorder::open(path)?)),
+            None => None,
+        };
+
+        Ok((
+            AsyncIndiConnection { writer: Arc::new(tokio::sync::Mutex::new(write)), recorder: recorder.clone() },
+            IncomingMsgStream { frames: FramedRead::new(Tap::new(read, recorder), IncomingMsgCodec::with_blob_storage(storage)) }
+        ))
+    }
+
+    pub async fn send(&self, msg: &OutgoingMsg) -> IndiResult<()> {
+        let str = quick_xml::se::to_string(msg).map_err(IndiError::Serialize)?;
+        let mut writer = self.writer.lock().await;
+        writer.write_all(str.as_bytes()).await?;
+        writer.flush().await?;
+        if let Some(recorder) = &self.recorder {
+            recorder.sent(&str);
+        }
+        Ok(())
+    }
+
+    /// Waits until what was recorded so far is in the session file, see `ConnectionSpec::record`.
+    pub async fn flush_recording(&self) {
+        if let Some(recorder) = &self.recorder {
+            recorder.flush().await;
+        }
+    }
+}
+
+/// Accepts clients on `listener` for good, `serve` runs each in a task of its own.
+pub(crate) async fn serve_clients<F, S>(listener: TcpListener, serve: F) -> IndiResult<()>
+    where F: Fn(OwnedReadHalf, OwnedWriteHalf) -> S, S: Future<Output = IndiResult<()>> + Send + 'static {
+    loop {
+        let (socket, peer) = listener.accept().await?;
+        log::info!("{} connected", peer);
+        let (reader, writer) = socket.into_split();
+        let served = serve(reader, writer);
+        tokio::spawn(async move {
+            match served.await {
+                Ok(()) => log::info!("{} disconnected", peer),
+                Err(e) => log::warn!("{} disconnected: {}", peer, e),
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio_stream::StreamExt;
+    use crate::config_file::ConnectionSpec;
+    use crate::indi::async_connection::AsyncIndiConnection;
+    use crate::indi::get_properties::GetProperties;
+    use crate::indi::{IncomingMsg, OutgoingMsg};
+
+    #[tokio::test]
+    async fn it_sends_and_streams() -> Result<(), Box<dyn Error + Send + Sync>> {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let spec = ConnectionSpec::new("test", "127.0.0.1", listener.local_addr()?.port() as usize);
+
+        let server = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut received = vec![0u8; 30];
+            socket.read_exact(&mut received).await.unwrap();
+            socket.write_all(br#"
+                <setSwitchVector device="CCD Simulator" name="CONNECTION" state="Ok" timeout="60" timestamp="2023-01-12T20:51:39">
+                    <oneSwitch name="CONNECT">On</oneSwitch>
+                </setSwitchVector>
+                <delProperty device="CCD Simulator" name="CCD_TEMPERATURE"></delProperty>
+            "#).await.unwrap();
+            String::from_utf8(received).unwrap()
+        });
+
+        let (conn, mut messages) = AsyncIndiConnection::connect(&spec).await?;
+        conn.send(&OutgoingMsg::GetProperties(GetProperties::default())).await?;
+
+        assert!(matches!(messages.next().await, Some(IncomingMsg::SetSwitchVector(_))));
+        assert!(matches!(messages.next().await, Some(IncomingMsg::DelProperty(_))));
+        assert!(messages.next().await.is_none());
+        assert_eq!(server.await?, r#"<getProperties version="1.7"/>"#);
+        Ok(())
+    }
+}
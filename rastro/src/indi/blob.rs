@@ -1,3 +1,6 @@
+use std::io::Read;
+use super::blob_stream::StoredBlob;
+use super::error::{IndiError, IndiResult};
 use super::common::{IndiState, IndiPermission};
 
 
@@ -8,7 +11,7 @@ pub struct DefBlobValue {
     #[serde(rename = "@label")]
     pub label: String,
 
-    #[serde(rename = "$text")]
+    #[serde(rename = "$text", default)]
     pub value: String,
 
     #[serde(flatten)]
@@ -62,7 +65,7 @@ impl std::fmt::Display for DefBlobValue {
 }
 
 
-#[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq)]
+#[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Clone)]
 pub struct SetBlobValue {
     #[serde(rename = "@name")]
     pub name: String,
@@ -70,20 +73,29 @@ pub struct SetBlobValue {
     #[serde(rename = "@size")]
     pub size: usize,
 
-    #[serde(rename = "@len")]
+    /// Bytes sent after base64 decoding, 0 when the driver did not say.
+    #[serde(rename = "@len", default)]
     pub len: usize,
 
+    /// Length of the base64 text as sent by libindi, 0 when the driver did not say.
+    #[serde(rename = "@enclen", default)]
+    pub enclen: usize,
+
     #[serde(rename = "@format")]
     pub format: String,
 
-    #[serde(rename = "$text")]
+    /// Empty when the payload was streamed to a `BlobStorage`, see `stored`.
+    #[serde(rename = "$text", default)]
     pub value: String,
 
+    #[serde(skip)]
+    pub stored: Option<StoredBlob>,
+
     #[serde(flatten)]
     extra: std::collections::HashMap<String, String>,
 }
 
-#[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq)]
+#[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Clone)]
 pub struct SetBlobVector {
     #[serde(rename = "@name")]
     pub name: String,
@@ -121,8 +133,186 @@ impl std::fmt::Display for SetBlobVector {
 
 impl std::fmt::Display for SetBlobValue {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{} = {} bytes = ", self.name, self.value.len()).unwrap();
-        write!(f, "{}", self.value)
+        write!(f, "{} = {} bytes ({}, {} on the wire)", self.name, self.size, self.format, self.len)
+    }
+}
+
+/// Formats ending in `.z` are zlib compressed by the driver.
+fn is_compressed_format(format: &str) -> bool {
+    format.ends_with(".z")
+}
+
+/// The format of a payload once inflated, i.e. `.fits.z` becomes `.fits`.
+pub(crate) fn decoded_format(format: &str) -> &str {
+    format.strip_suffix(".z").unwrap_or(format)
+}
+
+impl SetBlobValue {
+    pub fn is_compressed(&self) -> bool {
+        is_compressed_format(&self.format)
+    }
+
+    /// The format of the payload returned by `decode`, i.e. `.fits.z` becomes `.fits`.
+    pub fn decoded_format(&self) -> &str {
+        decoded_format(&self.format)
+    }
+
+    /**
+    Base64 decodes the payload, inflating it if the format says it is compressed.
+
+    `enclen` is checked against the base64 text, `len` against the bytes it decodes to and `size`
+    against the final payload.
+    */
+    pub fn decode(&self) -> IndiResult<Vec<u8>> {
+        if let Some(stored) = &self.stored {
+            return Err(IndiError::InvalidData(format!("{} was streamed to {:?}", self.name, stored.path)));
+        }
+        decode(&self.name, &self.value, self.enclen, self.len, &self.format, self.size)
+    }
+}
+
+/// Base64 decodes `text` and inflates it for compressed formats, checking `enclen` and `len` unless they are 0 and `size`.
+fn decode(name: &str, text: &str, enclen: usize, len: usize, format: &str, size: usize) -> IndiResult<Vec<u8>> {
+    let text: Vec<u8> = text.bytes().filter(|b| !b.is_ascii_whitespace()).collect();
+    if enclen != 0 && text.len() != enclen {
+        return Err(IndiError::InvalidData(format!("{} has enclen {} but {} characters were sent", name, enclen, text.len())));
+    }
+    let raw = base64::decode(text)?;
+    if len != 0 && raw.len() != len {
+        return Err(IndiError::InvalidData(format!("{} has len {} but {} bytes were sent", name, len, raw.len())));
+    }
+
+    let data = if is_compressed_format(format) {
+        let mut inflated = Vec::with_capacity(size);
+        flate2::read::ZlibDecoder::new(raw.as_slice()).read_to_end(&mut inflated)
+            .map_err(|e| IndiError::InvalidData(format!("{} does not inflate: {}", name, e)))?;
+        inflated
+    } else {
+        raw
+    };
+
+    if data.len() != size {
+        return Err(IndiError::InvalidData(format!("{} has size {} but decoded to {} bytes", name, size, data.len())));
+    }
+    Ok(data)
+}
+
+
+
+#[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Clone)]
+pub struct NewBlobValue {
+    #[serde(rename = "@name")]
+    pub name: String,
+
+    #[serde(rename = "@size")]
+    pub size: usize,
+
+    #[serde(rename = "@format")]
+    pub format: String,
+
+    #[serde(rename = "$text", default)]
+    pub value: String,
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Clone)]
+pub struct NewBlobVector {
+    #[serde(rename = "@device")]
+    pub device: String,
+    #[serde(rename = "@name")]
+    pub name: String,
+    #[serde(rename = "@timestamp", default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<String>,
+
+    #[serde(rename = "oneBLOB", default)]
+    pub blobs: Vec<NewBlobValue>,
+}
+
+impl NewBlobValue {
+    /// The payload a client sent, see `SetBlobValue::decode`.
+    pub fn decode(&self) -> IndiResult<Vec<u8>> {
+        decode(&self.name, &self.value, 0, 0, &self.format, self.size)
+    }
+
+    /// The format of the payload returned by `decode`, i.e. `.fits.z` becomes `.fits`.
+    pub fn decoded_format(&self) -> &str {
+        decoded_format(&self.format)
+    }
+
+    pub fn from_bytes(name: &str, format: &str, data: &[u8]) -> NewBlobValue {
+        NewBlobValue {
+            name: name.to_string(),
+            size: data.len(),
+            format: format.to_string(),
+            value: base64::encode(data),
+        }
+    }
+}
+
+impl std::fmt::Display for NewBlobVector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "➡ {}::{}(blob)", self.device, self.name)?;
+        for v in &self.blobs {
+            writeln!(f, "\tformat: {}, size: {}", v.format, v.size)?;
+        }
+        Ok(())
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use std::io::Write;
+    use crate::indi::blob::SetBlobVector;
+
+    fn set_blob_xml(size: usize, format: &str, payload: &[u8]) -> String {
+        let encoded = base64::encode(payload);
+        let wrapped: Vec<&str> = encoded.as_bytes()
+            .chunks(72)
+            .map(|c| std::str::from_utf8(c).unwrap())
+            .collect();
+        format!(
+            r#"<setBLOBVector device="CCD Simulator" name="CCD1" state="Ok" timeout="60" timestamp="2023-02-11T07:16:57">
+                <oneBLOB name="CCD1" size="{}" format="{}" len="{}">
+                {}
+                </oneBLOB>
+            </setBLOBVector>"#,
+            size, format, payload.len(), wrapped.join("\n")
+        )
+    }
+
+    #[test]
+    fn it_decodes_blobs() -> Result<(), Box<dyn Error>> {
+        let image: Vec<u8> = (0..2880u32).map(|i| (i % 251) as u8).collect();
+
+        let xml = set_blob_xml(image.len(), ".fits", &image);
+        let v = quick_xml::de::from_str::<SetBlobVector>(&xml)?;
+        assert!(!v.blobs[0].is_compressed());
+        assert_eq!(v.blobs[0].decode()?, image);
+
+        let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(&image)?;
+        let compressed = encoder.finish()?;
+        let xml = set_blob_xml(image.len(), ".fits.z", &compressed);
+        let v = quick_xml::de::from_str::<SetBlobVector>(&xml)?;
+        assert_eq!(v.blobs[0].decoded_format(), ".fits");
+        assert_eq!(v.blobs[0].decode()?, image);
+
+        let xml = set_blob_xml(image.len() + 1, ".fits", &image);
+        let v = quick_xml::de::from_str::<SetBlobVector>(&xml)?;
+        assert!(v.blobs[0].decode().is_err());
+
+        //libindi sends the length of the base64 text as `enclen` instead of `len`
+        let enclen = base64::encode(&image).len();
+        let xml = set_blob_xml(image.len(), ".fits", &image)
+            .replace(&format!(" len=\"{}\"", image.len()), &format!(" enclen=\"{}\"", enclen));
+        let v = quick_xml::de::from_str::<SetBlobVector>(&xml)?;
+        assert_eq!((v.blobs[0].len, v.blobs[0].enclen), (0, enclen));
+        assert_eq!(v.blobs[0].decode()?, image);
+
+        let xml = xml.replace(&format!(" enclen=\"{}\"", enclen), &format!(" enclen=\"{}\"", enclen + 4));
+        let v = quick_xml::de::from_str::<SetBlobVector>(&xml)?;
+        assert!(v.blobs[0].decode().is_err());
+        Ok(())
+    }
+}
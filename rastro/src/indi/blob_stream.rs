@@ -0,0 +1,258 @@
+use std::fmt::{Debug, Formatter};
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, ErrorKind, Write};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use crate::indi::error::{IndiError, IndiResult};
+
+/// What is known about a BLOB when its payload starts arriving.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlobInfo {
+    pub device: String,
+    pub property: String,
+    pub element: String,
+    /// The format after decompression, i.e. `.fits` for a `.fits.z` BLOB.
+    pub format: String,
+    pub size: usize,
+    pub timestamp: String,
+}
+
+pub type BlobWriterFactory = Arc<dyn Fn(&BlobInfo) -> std::io::Result<Box<dyn Write + Send>> + Send + Sync>;
+
+/**
+Where the payload of incoming `oneBLOB` elements ends up.
+
+With anything but `InMemory` the base64 text is decoded (and inflated) while it is being read,
+so a frame is never held in memory. `SetBlobValue::value` is then empty and
+`SetBlobValue::stored` describes the result.
+*/
+#[derive(Clone, Default)]
+pub enum BlobStorage {
+    /// Keep the base64 text in `SetBlobValue::value`.
+    #[default]
+    InMemory,
+    /// Write each BLOB to a new file in this directory.
+    Directory(PathBuf),
+    /// Write each BLOB to whatever the factory returns.
+    Writer(BlobWriterFactory),
+}
+
+impl Debug for BlobStorage {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BlobStorage::InMemory => write!(f, "InMemory"),
+            BlobStorage::Directory(dir) => write!(f, "Directory({:?})", dir),
+            BlobStorage::Writer(_) => write!(f, "Writer"),
+        }
+    }
+}
+
+impl BlobStorage {
+    pub fn is_streaming(&self) -> bool {
+        !matches!(self, BlobStorage::InMemory)
+    }
+}
+
+/// A BLOB payload that was decoded while it was read.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StoredBlob {
+    /// Set when the BLOB was written to `BlobStorage::Directory`.
+    pub path: Option<PathBuf>,
+    /// Bytes received, before inflating.
+    pub len: usize,
+    /// Bytes written.
+    pub size: usize,
+    /// Why the payload disagrees with the `len` and `size` of its element, if it does.
+    pub mismatch: Option<String>,
+}
+
+impl StoredBlob {
+    /// Fails like `SetBlobValue::decode` does when the payload is not what its element announced.
+    pub fn check(&self) -> IndiResult<()> {
+        match &self.mismatch {
+            Some(mismatch) => Err(IndiError::InvalidData(mismatch.clone())),
+            None => Ok(()),
+        }
+    }
+}
+
+struct Counting {
+    inner: Box<dyn Write + Send>,
+    count: usize,
+}
+
+impl Write for Counting {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.count += n;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+enum Output {
+    Plain(Counting),
+    Inflate(flate2::write::ZlibDecoder<Counting>),
+}
+
+/// Incremental base64 decoder feeding one BLOB into its storage.
+pub(crate) struct BlobDecoder {
+    pending: Vec<u8>,
+    decoded: Vec<u8>,
+    len: usize,
+    output: Output,
+    path: Option<PathBuf>,
+}
+
+/// `text` with everything but ASCII letters, digits, `-` and `.` replaced by `_`, so it cannot leave a directory.
+pub(crate) fn file_name_part(text: &str) -> String {
+    text.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '.' { c } else { '_' })
+        .collect()
+}
+
+fn file_name(info: &BlobInfo, attempt: usize) -> String {
+    let mut name = file_name_part(&format!("{}-{}-{}-{}", info.device, info.property, info.element, info.timestamp));
+    if attempt > 0 {
+        name.push_str(&format!("-{}", attempt));
+    }
+    //the format comes from the server as well
+    name.push_str(&file_name_part(&info.format));
+    name
+}
+
+fn create_file(dir: &std::path::Path, info: &BlobInfo) -> std::io::Result<(File, PathBuf)> {
+    let mut attempt = 0;
+    loop {
+        let path = dir.join(file_name(info, attempt));
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(file) => return Ok((file, path)),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => attempt += 1,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+impl BlobDecoder {
+    /// `None` when the storage keeps BLOBs in memory.
+    pub(crate) fn open(storage: &BlobStorage, info: &BlobInfo, compressed: bool) -> std::io::Result<Option<BlobDecoder>> {
+        let (inner, path): (Box<dyn Write + Send>, Option<PathBuf>) = match storage {
+            BlobStorage::InMemory => return Ok(None),
+            BlobStorage::Directory(dir) => {
+                let (file, path) = create_file(dir, info)?;
+                (Box::new(BufWriter::new(file)), Some(path))
+            },
+            BlobStorage::Writer(factory) => (factory(info)?, None),
+        };
+
+        let counting = Counting { inner, count: 0 };
+        Ok(Some(BlobDecoder {
+            pending: Vec::new(),
+            decoded: Vec::new(),
+            len: 0,
+            output: if compressed {
+                Output::Inflate(flate2::write::ZlibDecoder::new(counting))
+            } else {
+                Output::Plain(counting)
+            },
+            path,
+        }))
+    }
+
+    fn decode(&mut self, n: usize) -> std::io::Result<()> {
+        self.decoded.resize(n / 4 * 3 + 3, 0);
+        let decoded = base64::decode_config_slice(&self.pending[..n], base64::STANDARD, &mut self.decoded)
+            .map_err(|e| std::io::Error::new(ErrorKind::InvalidData, e))?;
+        self.len += decoded;
+        match &mut self.output {
+            Output::Plain(w) => w.write_all(&self.decoded[..decoded])?,
+            Output::Inflate(w) => w.write_all(&self.decoded[..decoded])?,
+        }
+        self.pending.drain(..n);
+        Ok(())
+    }
+
+    /// Feeds more of the element text, which may be split anywhere.
+    pub(crate) fn write_text(&mut self, text: &[u8]) -> std::io::Result<()> {
+        self.pending.extend(text.iter().filter(|b| !b.is_ascii_whitespace()));
+        let n = self.pending.len() / 4 * 4;
+        if n > 0 {
+            self.decode(n)?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn finish(mut self) -> std::io::Result<StoredBlob> {
+        if !self.pending.is_empty() {
+            self.decode(self.pending.len())?;
+        }
+        let mut counting = match self.output {
+            Output::Plain(w) => w,
+            Output::Inflate(w) => w.finish()?,
+        };
+        counting.flush()?;
+        Ok(StoredBlob { path: self.path, len: self.len, size: counting.count, mismatch: None })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use crate::indi::blob_stream::{BlobDecoder, BlobInfo, BlobStorage};
+
+    #[test]
+    fn it_decodes_in_pieces() -> Result<(), Box<dyn Error>> {
+        let dir = tempfile::tempdir()?;
+        let storage = BlobStorage::Directory(dir.path().to_path_buf());
+        let info = BlobInfo {
+            device: "CCD Simulator".to_string(),
+            property: "CCD1".to_string(),
+            element: "CCD1".to_string(),
+            format: ".fits".to_string(),
+            size: 1000,
+            timestamp: "2023-02-11T07:16:57".to_string(),
+        };
+        let image: Vec<u8> = (0..1000u32).map(|i| (i % 251) as u8).collect();
+        let text = base64::encode(&image);
+
+        let mut decoder = BlobDecoder::open(&storage, &info, false)?.unwrap();
+        for piece in text.as_bytes().chunks(7) {
+            decoder.write_text(piece)?;
+            decoder.write_text(b"\n ")?;
+        }
+        let stored = decoder.finish()?;
+        assert_eq!((stored.len, stored.size), (1000, 1000));
+
+        let path = stored.path.unwrap();
+        assert_eq!(path.file_name().unwrap(), "CCD_Simulator-CCD1-CCD1-2023-02-11T07_16_57.fits");
+        assert_eq!(std::fs::read(path)?, image);
+
+        let second = BlobDecoder::open(&storage, &info, false)?.unwrap().finish()?;
+        assert_eq!(second.path.unwrap().file_name().unwrap(), "CCD_Simulator-CCD1-CCD1-2023-02-11T07_16_57-1.fits");
+        Ok(())
+    }
+
+    #[test]
+    fn it_keeps_hostile_formats_in_the_directory() -> Result<(), Box<dyn Error>> {
+        let dir = tempfile::tempdir()?;
+        let storage = BlobStorage::Directory(dir.path().join("blobs"));
+        std::fs::create_dir(dir.path().join("blobs"))?;
+        let info = BlobInfo {
+            device: "CCD Simulator".to_string(),
+            property: "CCD1".to_string(),
+            element: "CCD1".to_string(),
+            format: "/../x".to_string(),
+            size: 0,
+            timestamp: "2023-02-11T07:16:57".to_string(),
+        };
+
+        let path = BlobDecoder::open(&storage, &info, false)?.unwrap().finish()?.path.unwrap();
+        assert_eq!(path.parent(), Some(dir.path().join("blobs").as_path()));
+        assert_eq!(path.file_name().unwrap(), "CCD_Simulator-CCD1-CCD1-2023-02-11T07_16_57_.._x");
+        Ok(())
+    }
+}
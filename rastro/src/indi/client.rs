@@ -0,0 +1,569 @@
+use std::sync::{Arc, Mutex, RwLock, RwLockReadGuard};
+use std::time::Duration;
+use tokio::sync::{broadcast, watch};
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+use tokio_stream::StreamExt;
+
+use crate::config_file::ConnectionSpec;
+use crate::indi::async_connection::{AsyncIndiConnection, IncomingMsgStream};
+use crate::indi::blob_stream::BlobStorage;
+use crate::indi::common::{IndiPermission, IndiState};
+use crate::indi::condition::Condition;
+use crate::indi::enable_blob::{EnableBLOB, EnableBLOBValue};
+use crate::indi::error::{IndiError, IndiResult};
+use crate::indi::get_properties::GetProperties;
+use crate::indi::number::{NewNumberValue, NewNumberVector};
+use crate::indi::reconnect::{ConnectionState, ReconnectPolicy};
+use crate::indi::registry::{DeviceRegistry, PropertyKind, PropertyValue, PropertyVector};
+use crate::indi::switch::{IndiSwitch, NewSwitchValue, NewSwitchVector};
+use crate::indi::text::{NewTextValue, NewTextVector};
+use crate::indi::{IncomingMsg, OutgoingMsg};
+
+/// Used when a vector does not specify a timeout of its own.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/**
+An INDI client on top of `AsyncIndiConnection`.
+
+Incoming messages are applied to a shared `DeviceRegistry` and then broadcast to subscribers,
+which lets `set_number` and friends correlate the `set*Vector` that answers a `new*Vector`.
+
+When built with `connect` the client reconnects according to its `ReconnectPolicy` and replays
+the `getProperties`/`enableBLOB` requests made through it, the registry is marked stale meanwhile.
+Vectors the server has not defined again once it goes quiet after the resync are dropped.
+*/
+pub struct IndiClient {
+    shared: Arc<Shared>,
+    reader: JoinHandle<()>,
+}
+
+struct Shared {
+    connection: Mutex<Option<AsyncIndiConnection>>,
+    /// Requests replayed after a reconnect.
+    resync: Mutex<Vec<OutgoingMsg>>,
+    registry: RwLock<DeviceRegistry>,
+    updates: broadcast::Sender<Arc<IncomingMsg>>,
+    state: watch::Sender<ConnectionState>,
+}
+
+impl Drop for IndiClient {
+    fn drop(&mut self) {
+        self.reader.abort();
+    }
+}
+
+impl IndiClient {
+    pub async fn connect(spec: &ConnectionSpec) -> IndiResult<IndiClient> {
+        IndiClient::connect_with_policy(spec, ReconnectPolicy::default()).await
+    }
+
+    pub async fn connect_with_policy(spec: &ConnectionSpec, policy: ReconnectPolicy) -> IndiResult<IndiClient> {
+        IndiClient::connect_with_storage(spec, policy, BlobStorage::InMemory).await
+    }
+
+    /// Like `connect_with_policy`, with BLOB payloads going to `storage`.
+    pub async fn connect_with_storage(spec: &ConnectionSpec, policy: ReconnectPolicy, storage: BlobStorage) -> IndiResult<IndiClient> {
+        let (connection, messages) = AsyncIndiConnection::connect_with_storage(spec, storage.clone()).await?;
+        Ok(IndiClient::create(Some((connection, messages)), Some((spec.clone(), policy, storage))))
+    }
+
+    /**
+    A client that has yet to reach the server, it connects by `policy` as if it had lost the connection.
+
+    Requests made meanwhile fail with `NotConnected`, `getProperties` and `enableBLOB` are sent once connected.
+    */
+    pub fn disconnected(spec: &ConnectionSpec, policy: ReconnectPolicy, storage: BlobStorage) -> IndiClient {
+        IndiClient::create(None, Some((spec.clone(), policy, storage)))
+    }
+
+    /// A client over an existing connection, it cannot reconnect.
+    pub fn new(connection: AsyncIndiConnection, messages: IncomingMsgStream) -> IndiClient {
+        IndiClient::create(Some((connection, messages)), None)
+    }
+
+    fn create(connected: Option<(AsyncIndiConnection, IncomingMsgStream)>, reconnect: Option<(ConnectionSpec, ReconnectPolicy, BlobStorage)>) -> IndiClient {
+        let (updates, _) = broadcast::channel(1024);
+        let initial = if connected.is_some() { ConnectionState::Connected } else { ConnectionState::Reconnecting { attempt: 0 } };
+        let (state, _) = watch::channel(initial);
+        let (connection, messages) = connected.unzip();
+        let shared = Arc::new(Shared {
+            connection: Mutex::new(connection),
+            resync: Mutex::new(Vec::new()),
+            registry: RwLock::new(DeviceRegistry::new()),
+            updates,
+            state,
+        });
+
+        let reader = tokio::spawn(reader_main(shared.clone(), messages, reconnect));
+        IndiClient { shared, reader }
+    }
+
+    pub fn registry(&self) -> RwLockReadGuard<'_, DeviceRegistry> {
+        self.shared.registry.read().unwrap()
+    }
+
+    pub fn connection_state(&self) -> watch::Receiver<ConnectionState> {
+        self.shared.state.subscribe()
+    }
+
+    /// Every message received after this call, already applied to the registry.
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<IncomingMsg>> {
+        self.shared.updates.subscribe()
+    }
+
+    pub async fn send(&self, msg: &OutgoingMsg) -> IndiResult<()> {
+        let connection = self.shared.connection.lock().unwrap().clone();
+        match connection {
+            Some(connection) => connection.send(msg).await,
+            None => Err(IndiError::NotConnected),
+        }
+    }
+
+    /// See `AsyncIndiConnection::flush_recording`, returns right away while disconnected.
+    pub async fn flush_recording(&self) {
+        let connection = self.shared.connection.lock().unwrap().clone();
+        if let Some(connection) = connection {
+            connection.flush_recording().await;
+        }
+    }
+
+    /// Sends `msg` now and again after every reconnect.
+    async fn send_and_remember(&self, msg: OutgoingMsg) -> IndiResult<()> {
+        {
+            let mut resync = self.shared.resync.lock().unwrap();
+            //a later request for the same target supersedes the earlier one
+            resync.retain(|m| target(m) != target(&msg));
+            resync.push(msg.clone());
+        }
+        self.send(&msg).await
+    }
+
+    /**
+    Asks for the definitions of every device, one device or a single property.
+
+    After snooping a device this way, its `set*Vector`s keep arriving on this connection.
+    */
+    pub async fn get_properties(&self, device: Option<&str>, name: Option<&str>) -> IndiResult<()> {
+        self.send_and_remember(OutgoingMsg::GetProperties(GetProperties {
+            device: device.map(str::to_string),
+            name: name.map(str::to_string),
+            ..GetProperties::default()
+        })).await
+    }
+
+    /// Sets whether BLOBs are sent for every device, one device or a single property.
+    pub async fn enable_blob(&self, value: EnableBLOBValue, device: Option<&str>, name: Option<&str>) -> IndiResult<()> {
+        self.send_and_remember(OutgoingMsg::EnableBLOB(EnableBLOB {
+            device: device.map(str::to_string),
+            name: name.map(str::to_string),
+            value,
+        })).await
+    }
+
+    /// Waits until the driver has defined `device.property`.
+    pub async fn wait_for_property(&self, device: &str, property: &str, timeout: Duration) -> IndiResult<()> {
+        let mut updates = self.subscribe();
+        let deadline = Instant::now() + timeout;
+        loop {
+            if self.registry().property(device, property).is_some() {
+                return Ok(());
+            }
+            match tokio::time::timeout_at(deadline, updates.recv()).await {
+                Err(_) => return Err(IndiError::Timeout(format!("{}::{} was never defined", device, property))),
+                Ok(Err(broadcast::error::RecvError::Closed)) => return Err(IndiError::ChannelClosed),
+                Ok(_) => {}
+            }
+        }
+    }
+
+    /// Waits until `condition` holds, checking it again after every message.
+    pub async fn wait_until(&self, condition: &Condition, timeout: Duration) -> IndiResult<()> {
+        let mut updates = self.subscribe();
+        let deadline = Instant::now() + timeout;
+        loop {
+            if condition.evaluate_in(&self.registry())? == Some(true) {
+                return Ok(());
+            }
+            match tokio::time::timeout_at(deadline, updates.recv()).await {
+                Err(_) => return Err(IndiError::Timeout(format!("{} did not become true", condition))),
+                Ok(Err(broadcast::error::RecvError::Closed)) => return Err(IndiError::ChannelClosed),
+                Ok(_) => {}
+            }
+        }
+    }
+
+    pub async fn set_number(&self, device: &str, property: &str, values: &[(&str, f64)]) -> IndiResult<IndiState> {
+        let msg = OutgoingMsg::NewNumberVector(NewNumberVector {
+            device: device.to_string(),
+            name: property.to_string(),
+            timestamp: None,
+            numbers: values.iter().map(|(name, value)| NewNumberValue { name: name.to_string(), value: *value }).collect(),
+        });
+        self.request(device, property, PropertyKind::Number, &msg).await
+    }
+
+    pub async fn set_switch(&self, device: &str, property: &str, values: &[(&str, IndiSwitch)]) -> IndiResult<IndiState> {
+        let msg = OutgoingMsg::NewSwitchVector(NewSwitchVector {
+            device: device.to_string(),
+            name: property.to_string(),
+            timestamp: None,
+            switches: values.iter().map(|(name, value)| NewSwitchValue { name: name.to_string(), value: *value }).collect(),
+        });
+        self.request(device, property, PropertyKind::Switch, &msg).await
+    }
+
+    pub async fn set_text(&self, device: &str, property: &str, values: &[(&str, &str)]) -> IndiResult<IndiState> {
+        let msg = OutgoingMsg::NewTextVector(NewTextVector {
+            device: device.to_string(),
+            name: property.to_string(),
+            timestamp: None,
+            texts: values.iter().map(|(name, value)| NewTextValue { name: name.to_string(), value: value.to_string() }).collect(),
+        });
+        self.request(device, property, PropertyKind::Text, &msg).await
+    }
+
+    /**
+    Sends `msg` and waits for the property to leave `Busy`, returning the state it settled in.
+
+    INDI has no acknowledgements, so a `set*Vector` for the property only counts as the answer
+    once it was `Busy` before, holds the requested values or is `Alert`. Anything else may have
+    been on its way before the driver saw the request. Every update from the driver restarts the
+    vector's timeout.
+
+    This still takes an update that happens to hold the requested values, or an `Alert` sent for
+    an earlier request, as the answer. Switches are not compared, buttons like `ABORT` turn back
+    off once pressed, so any update of a switch vector that is not `Busy` answers.
+    */
+    pub async fn request(&self, device: &str, property: &str, kind: PropertyKind, msg: &OutgoingMsg) -> IndiResult<IndiState> {
+        let timeout = {
+            let registry = self.registry();
+            let vector = registry.property(device, property)
+                .ok_or_else(|| IndiError::InvalidRequest(format!("{}::{} is not defined", device, property)))?;
+            check_writable(vector, kind)?;
+            response_timeout(vector)
+        };
+
+        //subscribe before sending so the answer cannot be missed
+        let mut updates = self.subscribe();
+        self.send(msg).await?;
+        self.wait_for_settled(&mut updates, device, property, msg, timeout).await
+    }
+
+    async fn wait_for_settled(&self, updates: &mut broadcast::Receiver<Arc<IncomingMsg>>, device: &str, property: &str, request: &OutgoingMsg, timeout: Duration) -> IndiResult<IndiState> {
+        let mut busy = false;
+        let mut deadline = Instant::now() + timeout;
+        loop {
+            let state = match tokio::time::timeout_at(deadline, updates.recv()).await {
+                Err(_) => return Err(IndiError::Timeout(format!("{}::{} did not respond within {:?}", device, property, timeout))),
+                Ok(Err(broadcast::error::RecvError::Closed)) => return Err(IndiError::ChannelClosed),
+                Ok(Err(broadcast::error::RecvError::Lagged(n))) => {
+                    //the answer may have been among the dropped messages, fall back to the registry
+                    log::warn!("missed {} messages waiting for {}::{}", n, device, property);
+                    match self.registry().property(device, property).map(|p| p.state) {
+                        Some(state) => state,
+                        None => continue,
+                    }
+                },
+                Ok(Ok(msg)) => match set_state(&msg, device, property) {
+                    Some(state) => state,
+                    None => continue,
+                },
+            };
+            match state {
+                IndiState::Busy => busy = true,
+                IndiState::Alert => return Ok(state),
+                state if busy || self.registry().property(device, property).is_some_and(|v| holds(v, request)) => return Ok(state),
+                //an update sent before the driver saw the request
+                _ => log::debug!("{}::{} turned {:?} without the requested values", device, property, state),
+            }
+            deadline = Instant::now() + timeout;
+        }
+    }
+}
+
+async fn reader_main(shared: Arc<Shared>, mut messages: Option<IncomingMsgStream>, reconnect: Option<(ConnectionSpec, ReconnectPolicy, BlobStorage)>) {
+    //set while the server is answering the replayed `getProperties`, until it has been quiet for `resync_quiet`
+    let mut resyncing: Option<Instant> = None;
+    loop {
+        //`None` for a client that has not been connected yet
+        if let Some(mut connected) = messages.take() {
+            loop {
+                let msg = match resyncing {
+                    Some(quiet) => match tokio::time::timeout_at(quiet, connected.next()).await {
+                        Ok(msg) => msg,
+                        Err(_) => {
+                            resyncing = None;
+                            let dropped = shared.registry.write().unwrap().drop_stale();
+                            if dropped > 0 {
+                                log::info!("dropped {} properties the server did not define again", dropped);
+                            }
+                            continue;
+                        },
+                    },
+                    None => connected.next().await,
+                };
+                let msg = match msg {
+                    Some(msg) => msg,
+                    None => break,
+                };
+                if resyncing.is_some() && is_definition(&msg) {
+                    resyncing = reconnect.as_ref().map(|(_, policy, _)| Instant::now() + policy.resync_quiet);
+                }
+                shared.registry.write().unwrap().apply(&msg);
+                //no subscribers is fine
+                let _ = shared.updates.send(Arc::new(msg));
+            }
+
+            log::warn!("lost connection to server");
+            shared.connection.lock().unwrap().take();
+            shared.registry.write().unwrap().mark_stale();
+        }
+
+        let (spec, policy, storage) = match &reconnect {
+            Some(reconnect) => reconnect,
+            None => break,
+        };
+
+        let mut attempt = 0;
+        let reconnected = loop {
+            shared.state.send_replace(ConnectionState::Reconnecting { attempt });
+            let delay = match policy.delay(attempt) {
+                Some(delay) => delay,
+                None => break None,
+            };
+            tokio::time::sleep(delay).await;
+
+            match reconnect_and_resync(&shared, spec, storage).await {
+                Ok(messages) => break Some(messages),
+                Err(e) => {
+                    log::warn!("reconnect attempt {} to {} failed: {}", attempt, spec.name, e);
+                    attempt += 1;
+                }
+            }
+        };
+
+        match reconnected {
+            Some(reconnected) => {
+                log::info!("reconnected to {}", spec.name);
+                messages = Some(reconnected);
+                resyncing = Some(Instant::now() + policy.resync_quiet);
+                shared.state.send_replace(ConnectionState::Connected);
+            },
+            None => break,
+        }
+    }
+
+    log::info!("client reader exited");
+    shared.state.send_replace(ConnectionState::Closed);
+}
+
+async fn reconnect_and_resync(shared: &Shared, spec: &ConnectionSpec, storage: &BlobStorage) -> IndiResult<IncomingMsgStream> {
+    let (connection, messages) = AsyncIndiConnection::connect_with_storage(spec, storage.clone()).await?;
+    let resync = shared.resync.lock().unwrap().clone();
+    for msg in &resync {
+        connection.send(msg).await?;
+    }
+    *shared.connection.lock().unwrap() = Some(connection);
+    Ok(messages)
+}
+
+fn is_definition(msg: &IncomingMsg) -> bool {
+    matches!(msg,
+        IncomingMsg::DefNumberVector(_) | IncomingMsg::DefSwitchVector(_) | IncomingMsg::DefTextVector(_) |
+        IncomingMsg::DefBlobVector(_) | IncomingMsg::DefLightVector(_)
+    )
+}
+
+/// The device and property a `getProperties` or `enableBLOB` applies to.
+fn target(msg: &OutgoingMsg) -> Option<(&'static str, Option<&str>, Option<&str>)> {
+    match msg {
+        OutgoingMsg::GetProperties(m) => Some(("getProperties", m.device.as_deref(), m.name.as_deref())),
+        OutgoingMsg::EnableBLOB(m) => Some(("enableBLOB", m.device.as_deref(), m.name.as_deref())),
+        _ => None,
+    }
+}
+
+fn check_writable(vector: &PropertyVector, kind: PropertyKind) -> IndiResult<()> {
+    if vector.kind != kind {
+        return Err(IndiError::InvalidRequest(
+            format!("{}::{} is a {:?} vector, not {:?}", vector.device, vector.name, vector.kind, kind)
+        ));
+    }
+    if vector.perm == Some(IndiPermission::RO) || vector.perm.is_none() {
+        return Err(IndiError::InvalidRequest(format!("{}::{} is read only", vector.device, vector.name)));
+    }
+    Ok(())
+}
+
+fn response_timeout(vector: &PropertyVector) -> Duration {
+    if vector.timeout > 0.0 {
+        Duration::from_secs_f64(vector.timeout)
+    } else {
+        DEFAULT_TIMEOUT
+    }
+}
+
+/// Whether `vector` has the values `request` asks for, switches and BLOBs always do.
+fn holds(vector: &PropertyVector, request: &OutgoingMsg) -> bool {
+    let value = |name: &str| vector.element(name).map(|e| &e.value);
+    match request {
+        OutgoingMsg::NewNumberVector(v) => v.numbers.iter().all(|n| match value(&n.name) {
+            Some(PropertyValue::Number { value, .. }) => (value - n.value).abs() <= 1e-9 * n.value.abs().max(1.0),
+            _ => false,
+        }),
+        OutgoingMsg::NewTextVector(v) => v.texts.iter().all(|t| matches!(value(&t.name), Some(PropertyValue::Text(text)) if *text == t.value)),
+        _ => true,
+    }
+}
+
+/// The state carried by `msg` if it is a `set*Vector` for `device.property`.
+fn set_state(msg: &IncomingMsg, device: &str, property: &str) -> Option<IndiState> {
+    let (d, p, state) = match msg {
+        IncomingMsg::SetNumberVector(v) => (&v.device, &v.name, v.state),
+        IncomingMsg::SetSwitchVector(v) => (&v.device, &v.name, v.state),
+        IncomingMsg::SetTextVector(v) => (&v.device, &v.name, v.state),
+        IncomingMsg::SetBlobVector(v) => (&v.device, &v.name, v.state),
+        IncomingMsg::SetLightVector(v) => (&v.device, &v.name, v.state),
+        _ => return None,
+    };
+    (d == device && p == property).then_some(state)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use crate::config_file::ConnectionSpec;
+    use crate::indi::client::IndiClient;
+    use crate::indi::common::IndiState;
+    use crate::indi::enable_blob::EnableBLOBValue;
+    use crate::indi::reconnect::{ConnectionState, ReconnectPolicy};
+
+    #[tokio::test]
+    async fn it_waits_for_busy_to_settle() -> Result<(), Box<dyn Error + Send + Sync>> {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let spec = ConnectionSpec::new("test", "127.0.0.1", listener.local_addr()?.port() as usize);
+
+        let server = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            socket.write_all(br#"
+                <defNumberVector device="CCD Simulator" name="CCD_TEMPERATURE" label="Temperature (C)" group="Main Control" state="Idle" perm="rw" timeout="1" timestamp="2023-01-12T20:51:39">
+                    <defNumber name="CCD_TEMPERATURE_VALUE" label="Temperature (C)" format="%5.2f" min="-50" max="50" step="0">20</defNumber>
+                </defNumberVector>
+            "#).await.unwrap();
+
+            let mut received = vec![0u8; 1024];
+            let n = socket.read(&mut received).await.unwrap();
+            let set = |state: &str| format!(r#"
+                <setNumberVector device="CCD Simulator" name="CCD_TEMPERATURE" state="{}" timeout="1" timestamp="2023-01-12T20:51:40">
+                    <oneNumber name="CCD_TEMPERATURE_VALUE">-10</oneNumber>
+                </setNumberVector>
+            "#, state);
+            for state in ["Busy", "Busy", "Ok"] {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                socket.write_all(set(state).as_bytes()).await.unwrap();
+            }
+
+            //an update that crossed the next request is not its answer
+            let _ = socket.read(&mut [0u8; 1024]).await.unwrap();
+            socket.write_all((set("Ok") + &set("Alert")).as_bytes()).await.unwrap();
+            String::from_utf8(received[..n].to_vec()).unwrap()
+        });
+
+        let client = IndiClient::connect(&spec).await?;
+        client.wait_for_property("CCD Simulator", "CCD_TEMPERATURE", Duration::from_secs(5)).await?;
+        let state = client.set_number("CCD Simulator", "CCD_TEMPERATURE", &[("CCD_TEMPERATURE_VALUE", -10.0)]).await?;
+        assert_eq!(state, IndiState::Ok);
+        assert_eq!(client.registry().number("CCD Simulator", "CCD_TEMPERATURE", "CCD_TEMPERATURE_VALUE"), Some(-10.0));
+        let state = client.set_number("CCD Simulator", "CCD_TEMPERATURE", &[("CCD_TEMPERATURE_VALUE", -30.0)]).await?;
+        assert_eq!(state, IndiState::Alert);
+        assert!(server.await?.starts_with("<newNumberVector"));
+
+        assert!(client.set_switch("CCD Simulator", "CCD_TEMPERATURE", &[]).await.is_err());
+        assert!(client.set_number("CCD Simulator", "UNKNOWN", &[]).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_reconnects_and_resyncs() -> Result<(), Box<dyn Error + Send + Sync>> {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let spec = ConnectionSpec::new("test", "127.0.0.1", listener.local_addr()?.port() as usize);
+
+        let server = tokio::spawn(async move {
+            let mut requests = Vec::new();
+            let mut kept = None;
+            for value in [20, 30] {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut received = Vec::new();
+                while !String::from_utf8_lossy(&received).contains("</enableBLOB>") {
+                    let mut buf = vec![0u8; 1024];
+                    let n = socket.read(&mut buf).await.unwrap();
+                    received.extend_from_slice(&buf[..n]);
+                }
+                socket.write_all(format!(r#"
+                    <defNumberVector device="CCD Simulator" name="CCD_TEMPERATURE" label="Temperature (C)" group="Main Control" state="Idle" perm="rw" timeout="1" timestamp="2023-01-12T20:51:39">
+                        <defNumber name="CCD_TEMPERATURE_VALUE" label="Temperature (C)" format="%5.2f" min="-50" max="50" step="0">{}</defNumber>
+                    </defNumberVector>
+                "#, value).as_bytes()).await.unwrap();
+                if value == 20 {
+                    //not defined again after the reconnect
+                    socket.write_all(br#"
+                        <defSwitchVector device="CCD Simulator" name="CCD_COOLER" label="Cooler" group="Main Control" state="Idle" perm="rw" rule="OneOfMany" timeout="60" timestamp="2023-01-12T20:51:39">
+                            <defSwitch name="COOLER_ON" label="On">Off</defSwitch>
+                            <defSwitch name="COOLER_OFF" label="Off">On</defSwitch>
+                        </defSwitchVector>
+                    "#).await.unwrap();
+                }
+                requests.push(String::from_utf8(received).unwrap());
+                if value == 20 {
+                    //the first server goes away
+                    drop(socket);
+                } else {
+                    kept = Some(socket);
+                }
+            }
+            (requests, kept)
+        });
+
+        let policy = ReconnectPolicy {
+            initial_delay: Duration::from_millis(10),
+            resync_quiet: Duration::from_millis(50),
+            ..ReconnectPolicy::default()
+        };
+        let client = IndiClient::connect_with_policy(&spec, policy).await?;
+        let mut state = client.connection_state();
+        let mut updates = client.subscribe();
+        client.get_properties(None, None).await?;
+        client.enable_blob(EnableBLOBValue::Also, None, None).await?;
+        client.enable_blob(EnableBLOBValue::Never, Some("Guide Simulator"), None).await?;
+
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while *state.borrow() == ConnectionState::Connected {
+                state.changed().await.unwrap();
+            }
+            while *state.borrow() != ConnectionState::Connected {
+                state.changed().await.unwrap();
+            }
+            while client.registry().number("CCD Simulator", "CCD_TEMPERATURE", "CCD_TEMPERATURE_VALUE") != Some(30.0) {
+                updates.recv().await.unwrap();
+            }
+        }).await?;
+        assert!(!client.registry().property("CCD Simulator", "CCD_TEMPERATURE").unwrap().stale);
+
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while client.registry().property("CCD Simulator", "CCD_COOLER").is_some() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        }).await?;
+        assert!(client.registry().property("CCD Simulator", "CCD_TEMPERATURE").is_some());
+
+        let (requests, _socket) = server.await?;
+        assert_eq!(
+            requests[1],
+            r#"<getProperties version="1.7"/><enableBLOB>Also</enableBLOB><enableBLOB device="Guide Simulator">Never</enableBLOB>"#
+        );
+        Ok(())
+    }
+}
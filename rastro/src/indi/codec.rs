@@ -0,0 +1,591 @@
+use std::ops::Range;
+use bytes::{Buf, BytesMut};
+use quick_xml::events::Event;
+use tokio_util::codec::Decoder;
+
+use crate::indi::blob::decoded_format;
+use crate::indi::blob_stream::{BlobDecoder, BlobInfo, BlobStorage, StoredBlob};
+use crate::indi::error::IndiError;
+use crate::indi::{IncomingMsg, OutgoingMsg};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ScanState {
+    Content,
+    Tag { closing: bool, quote: Option<u8>, last: u8 },
+    Comment,
+    CData,
+    Declaration,
+    /// Inside a `oneBLOB` whose text is handed to the codec instead of being framed.
+    BlobText,
+}
+
+/**
+Finds the boundaries of top level elements in a stream of INDI xml.
+
+The scan is resumable, bytes that were already looked at are not scanned again when more
+data arrives, which keeps multi-megabyte BLOBs linear.
+*/
+#[derive(Debug)]
+pub(crate) struct IndiXmlFramer {
+    scanned: usize,
+    depth: usize,
+    start: Option<usize>,
+    state: ScanState,
+    tag_start: usize,
+    stream_blobs: bool,
+}
+
+impl IndiXmlFramer {
+    pub(crate) fn new() -> IndiXmlFramer {
+        IndiXmlFramer {
+            scanned: 0,
+            depth: 0,
+            start: None,
+            state: ScanState::Content,
+            tag_start: 0,
+            stream_blobs: false,
+        }
+    }
+
+    /// Stops at the text of every `oneBLOB`, see `in_blob_text`.
+    pub(crate) fn streaming_blobs() -> IndiXmlFramer {
+        IndiXmlFramer { stream_blobs: true, ..IndiXmlFramer::new() }
+    }
+
+    /**
+    Returns the range of the next complete top level element in `buf`, if any.
+
+    Once a range is returned the caller must drop `buf[..range.end]` before calling again.
+    */
+    pub(crate) fn next_frame(&mut self, buf: &[u8]) -> Option<Range<usize>> {
+        while self.scanned < buf.len() {
+            let pos = self.scanned;
+            let b = buf[pos];
+            match self.state {
+                ScanState::Content => {
+                    if b != b'<' {
+                        self.scanned += 1;
+                        continue;
+                    }
+                    let rest = &buf[pos..];
+                    if rest.len() < 9 && (b"<!--".starts_with(rest) || b"<![CDATA[".starts_with(rest)) {
+                        //not enough data to tell what this is yet
+                        return None;
+                    }
+                    if self.depth == 0 {
+                        self.start = Some(pos);
+                    }
+                    if rest.starts_with(b"<!--") {
+                        self.state = ScanState::Comment;
+                        self.scanned += 4;
+                    } else if rest.starts_with(b"<![CDATA[") {
+                        self.state = ScanState::CData;
+                        self.scanned += 9;
+                    } else if rest.starts_with(b"<!") || rest.starts_with(b"<?") {
+                        self.state = ScanState::Declaration;
+                        self.scanned += 2;
+                    } else if rest.len() < 2 {
+                        return None;
+                    } else if rest[1] == b'/' {
+                        self.state = ScanState::Tag { closing: true, quote: None, last: b'/' };
+                        self.scanned += 2;
+                    } else {
+                        self.tag_start = pos;
+                        self.state = ScanState::Tag { closing: false, quote: None, last: b'<' };
+                        self.scanned += 1;
+                    }
+                },
+
+                ScanState::Tag { closing, quote: Some(q), last } => {
+                    if b == q {
+                        self.state = ScanState::Tag { closing, quote: None, last };
+                    }
+                    self.scanned += 1;
+                },
+
+                ScanState::Tag { closing, quote: None, last } => {
+                    self.scanned += 1;
+                    match b {
+                        b'"' | b'\'' => self.state = ScanState::Tag { closing, quote: Some(b), last: b },
+                        b'>' => {
+                            self.state = ScanState::Content;
+                            if closing {
+                                self.depth = self.depth.saturating_sub(1);
+                            } else if last != b'/' {
+                                self.depth += 1;
+                                if self.stream_blobs && is_blob_tag(&buf[self.tag_start..]) {
+                                    self.state = ScanState::BlobText;
+                                    return None;
+                                }
+                            }
+                            if self.depth == 0 {
+                                return self.frame_end(pos + 1);
+                            }
+                        },
+                        b if b.is_ascii_whitespace() => {},
+                        b => self.state = ScanState::Tag { closing, quote: None, last: b },
+                    }
+                },
+
+                ScanState::Comment => {
+                    if buf[..=pos].ends_with(b"-->") {
+                        self.state = ScanState::Content;
+                        if self.depth == 0 {
+                            //comments between messages are not messages
+                            self.start = None;
+                        }
+                    }
+                    self.scanned += 1;
+                },
+
+                ScanState::CData => {
+                    if buf[..=pos].ends_with(b"]]>") {
+                        self.state = ScanState::Content;
+                    }
+                    self.scanned += 1;
+                },
+
+                ScanState::BlobText => return None,
+
+                ScanState::Declaration => {
+                    self.scanned += 1;
+                    if b == b'>' {
+                        self.state = ScanState::Content;
+                        if self.depth == 0 {
+                            self.start = None;
+                        }
+                    }
+                },
+            }
+        }
+        None
+    }
+
+    fn frame_end(&mut self, end: usize) -> Option<Range<usize>> {
+        let start = self.start.take().unwrap_or(0);
+        self.scanned = 0;
+        Some(start..end)
+    }
+
+    /// The text of a `oneBLOB` starts at `scanned()`, the caller consumes it and calls `leave_blob_text`.
+    pub(crate) fn in_blob_text(&self) -> bool {
+        self.state == ScanState::BlobText
+    }
+
+    pub(crate) fn leave_blob_text(&mut self) {
+        self.state = ScanState::Content;
+    }
+
+    pub(crate) fn scanned(&self) -> usize {
+        self.scanned
+    }
+
+    pub(crate) fn frame_start(&self) -> Option<usize> {
+        self.start
+    }
+
+    /// The caller moved the current frame so far out of the buffer and dropped everything up to `scanned()`.
+    pub(crate) fn rebase(&mut self) {
+        self.scanned = 0;
+        self.start = Some(0);
+    }
+
+    /// Bytes before this offset belong to no message and can be dropped.
+    pub(crate) fn discardable(&self) -> usize {
+        match (self.start, self.state) {
+            (None, ScanState::Content) if self.depth == 0 => self.scanned,
+            _ => 0,
+        }
+    }
+
+    pub(crate) fn discard(&mut self, n: usize) {
+        self.scanned -= n;
+        if let Some(start) = self.start.as_mut() {
+            *start -= n;
+        }
+    }
+}
+
+fn is_blob_tag(tag: &[u8]) -> bool {
+    tag.starts_with(b"<oneBLOB") && tag.get(8).map(|b| b.is_ascii_whitespace() || *b == b'>').unwrap_or(false)
+}
+
+/**
+`tokio_util` decoder turning the bytes of a connection into `IncomingMsg`s.
+
+Elements that cannot be parsed are logged and skipped, the framing does not depend on them.
+With a streaming `BlobStorage` the text of `oneBLOB` elements is decoded into the storage as it
+arrives and never buffered.
+*/
+pub struct IncomingMsgCodec {
+    framer: IndiXmlFramer,
+    storage: BlobStorage,
+    /// The part of the current element that was moved out of the buffer to make room for BLOB text.
+    head: Vec<u8>,
+    blob: Option<BlobDecoder>,
+    in_blob: bool,
+    stored: Vec<Option<StoredBlob>>,
+}
+
+impl IncomingMsgCodec {
+    pub fn new() -> IncomingMsgCodec {
+        IncomingMsgCodec::with_blob_storage(BlobStorage::InMemory)
+    }
+
+    pub fn with_blob_storage(storage: BlobStorage) -> IncomingMsgCodec {
+        IncomingMsgCodec {
+            framer: if storage.is_streaming() { IndiXmlFramer::streaming_blobs() } else { IndiXmlFramer::new() },
+            storage,
+            head: Vec::new(),
+            blob: None,
+            in_blob: false,
+            stored: Vec::new(),
+        }
+    }
+
+    /// Consumes BLOB text from `src`, returns false when it needs more data.
+    fn stream_blob_text(&mut self, src: &mut BytesMut) -> bool {
+        if !self.in_blob {
+            self.in_blob = true;
+            let text_start = self.framer.scanned();
+            let frame_start = self.framer.frame_start().unwrap_or(0);
+            self.head.extend_from_slice(&src[frame_start..text_start]);
+            src.advance(text_start);
+            self.framer.rebase();
+
+            let (info, compressed) = blob_info(&self.head);
+            self.blob = match BlobDecoder::open(&self.storage, &info, compressed) {
+                Ok(blob) => blob,
+                Err(e) => {
+                    log::warn!("dropping {}::{}.{}, could not store it: {}", info.device, info.property, info.element, e);
+                    None
+                }
+            };
+        }
+
+        let end = src.iter().position(|b| *b == b'<');
+        let text = src.split_to(end.unwrap_or(src.len()));
+        if let Some(blob) = self.blob.as_mut() {
+            if let Err(e) = blob.write_text(&text) {
+                log::warn!("dropping blob: {}", e);
+                self.blob = None;
+            }
+        }
+        if end.is_none() {
+            return false;
+        }
+
+        let stored = self.blob.take().and_then(|blob| match blob.finish() {
+            Ok(stored) => Some(stored),
+            Err(e) => {
+                log::warn!("dropping blob: {}", e);
+                None
+            }
+        });
+        self.stored.push(stored);
+        self.in_blob = false;
+        self.framer.leave_blob_text();
+        true
+    }
+
+    fn parse(&mut self, xml: &str) -> Option<IncomingMsg> {
+        let mut msg = match IncomingMsg::parse(xml) {
+            Ok(msg) => msg,
+            Err(e) => {
+                log::warn!("skipping element: {}", e);
+                return None;
+            }
+        };
+        if let IncomingMsg::SetBlobVector(v) = &mut msg {
+            for (blob, mut stored) in v.blobs.iter_mut().zip(self.stored.drain(..)) {
+                if let Some(stored) = &mut stored {
+                    if (blob.len != 0 && stored.len != blob.len) || stored.size != blob.size {
+                        let mismatch = format!("{}::{}.{} has len {} and size {} but {} and {} bytes were stored",
+                            v.device, v.name, blob.name, blob.len, blob.size, stored.len, stored.size);
+                        log::warn!("{}", mismatch);
+                        stored.mismatch = Some(mismatch);
+                    }
+                }
+                blob.stored = stored;
+            }
+        }
+        Some(msg)
+    }
+}
+
+/// Pulls device, property and element out of `<setBLOBVector ...> ... <oneBLOB ...>`.
+fn blob_info(head: &[u8]) -> (BlobInfo, bool) {
+    let mut info = BlobInfo {
+        device: String::new(),
+        property: String::new(),
+        element: String::new(),
+        format: String::new(),
+        size: 0,
+        timestamp: String::new(),
+    };
+    let mut reader = quick_xml::Reader::from_reader(head);
+    let mut buf = Vec::new();
+    while let Ok(event) = reader.read_event_into(&mut buf) {
+        let start = match event {
+            Event::Start(start) => start,
+            Event::Eof => break,
+            _ => continue,
+        };
+        for attr in start.attributes().flatten() {
+            let value = attr.decode_and_unescape_value(&reader).map(|v| v.into_owned()).unwrap_or_default();
+            match (start.name().as_ref(), attr.key.as_ref()) {
+                (b"oneBLOB", b"name") => info.element = value,
+                (b"oneBLOB", b"format") => info.format = value,
+                (b"oneBLOB", b"size") => info.size = value.trim().parse().unwrap_or(0),
+                (_, b"device") => info.device = value,
+                (_, b"name") => info.property = value,
+                (_, b"timestamp") => info.timestamp = value,
+                _ => {}
+            }
+        }
+        buf.clear();
+    }
+
+    let decoded = decoded_format(&info.format);
+    let compressed = decoded.len() != info.format.len();
+    info.format.truncate(decoded.len());
+    (info, compressed)
+}
+
+impl Default for IncomingMsgCodec {
+    fn default() -> Self {
+        IncomingMsgCodec::new()
+    }
+}
+
+impl Decoder for IncomingMsgCodec {
+    type Item = IncomingMsg;
+    type Error = IndiError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        loop {
+            if self.framer.in_blob_text() {
+                if !self.stream_blob_text(src) {
+                    return Ok(None);
+                }
+                continue;
+            }
+
+            let range = match self.framer.next_frame(src) {
+                Some(range) => range,
+                None if self.framer.in_blob_text() => continue,
+                None => {
+                    let discardable = self.framer.discardable();
+                    if discardable > 0 {
+                        let _ = src.split_to(discardable);
+                        self.framer.discard(discardable);
+                    }
+                    return Ok(None);
+                }
+            };
+
+            let frame = src.split_to(range.end);
+            let mut head = std::mem::take(&mut self.head);
+            let bytes = if head.is_empty() {
+                &frame[range.start..]
+            } else {
+                head.extend_from_slice(&frame[range.start..]);
+                &head[..]
+            };
+            let msg = match std::str::from_utf8(bytes) {
+                Ok(xml) => self.parse(xml),
+                Err(e) => {
+                    log::warn!("skipping element that is not utf8: {}", e);
+                    None
+                }
+            };
+            self.stored.clear();
+            if let Some(msg) = msg {
+                return Ok(Some(msg));
+            }
+        }
+    }
+}
+
+/**
+Decoder for the other direction, turning what clients send a driver into `OutgoingMsg`s.
+
+BLOBs sent to drivers are small, so they are always framed in memory.
+*/
+#[derive(Debug)]
+pub struct OutgoingMsgCodec {
+    framer: IndiXmlFramer,
+}
+
+impl OutgoingMsgCodec {
+    pub fn new() -> OutgoingMsgCodec {
+        OutgoingMsgCodec { framer: IndiXmlFramer::new() }
+    }
+}
+
+impl Default for OutgoingMsgCodec {
+    fn default() -> Self {
+        OutgoingMsgCodec::new()
+    }
+}
+
+impl Decoder for OutgoingMsgCodec {
+    type Item = OutgoingMsg;
+    type Error = IndiError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        loop {
+            let range = match self.framer.next_frame(src) {
+                Some(range) => range,
+                None => {
+                    let discardable = self.framer.discardable();
+                    if discardable > 0 {
+                        let _ = src.split_to(discardable);
+                        self.framer.discard(discardable);
+                    }
+                    return Ok(None);
+                }
+            };
+            let frame = src.split_to(range.end);
+            match std::str::from_utf8(&frame[range.start..]).map(OutgoingMsg::parse) {
+                Ok(Ok(msg)) => return Ok(Some(msg)),
+                Ok(Err(e)) => log::warn!("skipping element: {}", e),
+                Err(e) => log::warn!("skipping element that is not utf8: {}", e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+    use bytes::BytesMut;
+    use tokio_util::codec::Decoder;
+    use crate::indi::blob_stream::{BlobInfo, BlobStorage};
+    use crate::devices::camera::Image;
+    use crate::indi::codec::{IncomingMsgCodec, IndiXmlFramer};
+    use crate::indi::error::IndiError;
+    use crate::indi::IncomingMsg;
+
+    fn frames(chunks: &[&str]) -> Vec<String> {
+        let mut framer = IndiXmlFramer::new();
+        let mut buf = Vec::new();
+        let mut out = Vec::new();
+        for chunk in chunks {
+            buf.extend_from_slice(chunk.as_bytes());
+            while let Some(range) = framer.next_frame(&buf) {
+                out.push(String::from_utf8(buf[range.clone()].to_vec()).unwrap());
+                buf.drain(..range.end);
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn it_frames_top_level_elements() {
+        assert_eq!(
+            frames(&[r#" <delProperty device="a"/> <message device="a" message="x > y"></message>"#]),
+            vec![r#"<delProperty device="a"/>"#, r#"<message device="a" message="x > y"></message>"#]
+        );
+
+        assert_eq!(
+            frames(&["<setText", "Vector a='/>'>", "<oneText name=\"t\"><![CDATA[</oneText>]]></one", "Text><!-- --></setTextVector>"]),
+            vec!["<setTextVector a='/>'><oneText name=\"t\"><![CDATA[</oneText>]]></oneText><!-- --></setTextVector>"]
+        );
+
+        assert_eq!(
+            frames(&["<?xml version=\"1.0\"?>\n<!-- hello -->\n<getProperties version=\"1.7\"/>"]),
+            vec!["<getProperties version=\"1.7\"/>"]
+        );
+    }
+
+    struct SharedVec(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedVec {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn it_streams_blobs_without_buffering_them() -> Result<(), Box<dyn Error>> {
+        let image: Vec<u8> = (0..256 * 1024u32).map(|i| (i % 251) as u8).collect();
+        let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(&image)?;
+        let compressed = encoder.finish()?;
+
+        let xml = format!(
+            r#"<setBLOBVector device="CCD Simulator" name="CCD1" state="Ok" timeout="60" timestamp="2023-02-11T07:16:57">
+                <oneBLOB name="CCD1" size="{}" format=".fits" len="{}">{}</oneBLOB>
+                <oneBLOB name="CCD2" size="{}" format=".fits.z" len="{}">{}</oneBLOB>
+            </setBLOBVector>
+            <delProperty device="CCD Simulator" name="CCD1"/>"#,
+            image.len(), image.len(), base64::encode(&image),
+            image.len(), compressed.len(), base64::encode(&compressed),
+        );
+
+        let written = Arc::new(Mutex::new(Vec::new()));
+        let infos = Arc::new(Mutex::new(Vec::new()));
+        let storage = {
+            let (written, infos) = (written.clone(), infos.clone());
+            BlobStorage::Writer(Arc::new(move |info: &BlobInfo| {
+                infos.lock().unwrap().push(info.clone());
+                Ok(Box::new(SharedVec(written.clone())) as Box<dyn Write + Send>)
+            }))
+        };
+
+        let mut codec = IncomingMsgCodec::with_blob_storage(storage);
+        let mut src = BytesMut::new();
+        let mut msgs = Vec::new();
+        for chunk in xml.as_bytes().chunks(4096) {
+            src.extend_from_slice(chunk);
+            while let Some(msg) = codec.decode(&mut src)? {
+                msgs.push(msg);
+            }
+            assert!(src.len() < 8192);
+        }
+
+        match &msgs[..] {
+            [IncomingMsg::SetBlobVector(v), IncomingMsg::DelProperty(_)] => {
+                assert_eq!(v.blobs[0].value, "");
+                let stored: Vec<_> = v.blobs.iter().map(|b| b.stored.clone().unwrap()).collect();
+                assert_eq!((stored[0].len, stored[0].size), (image.len(), image.len()));
+                assert_eq!((stored[1].len, stored[1].size), (compressed.len(), image.len()));
+            },
+            _ => panic!("unexpected {:?}", msgs.len()),
+        }
+        let infos = infos.lock().unwrap();
+        assert_eq!(infos[1].element, "CCD2");
+        assert_eq!(infos[1].format, ".fits");
+        assert_eq!(infos[1].device, "CCD Simulator");
+        assert_eq!(*written.lock().unwrap(), [image.clone(), image].concat());
+        Ok(())
+    }
+
+    #[test]
+    fn it_rejects_streamed_blobs_that_disagree_with_their_element() -> Result<(), Box<dyn Error>> {
+        let dir = tempfile::tempdir()?;
+        let xml = format!(
+            r#"<setBLOBVector device="CCD Simulator" name="CCD1" state="Ok" timeout="60" timestamp="2023-02-11T07:16:57"><oneBLOB name="CCD1" size="100" format=".fits" len="100">{}</oneBLOB></setBLOBVector>"#,
+            base64::encode([7u8; 60]),
+        );
+        let mut codec = IncomingMsgCodec::with_blob_storage(BlobStorage::Directory(dir.path().to_path_buf()));
+        let mut src = BytesMut::from(xml.as_str());
+        match codec.decode(&mut src)? {
+            Some(IncomingMsg::SetBlobVector(v)) => {
+                let stored = v.blobs[0].stored.clone().unwrap();
+                assert_eq!((stored.len, stored.size), (60, 60));
+                assert!(matches!(stored.check(), Err(IndiError::InvalidData(_))));
+                assert!(matches!(Image::from_blob(&v), Err(IndiError::InvalidData(_))));
+            },
+            msg => panic!("unexpected {:?}", msg),
+        }
+        Ok(())
+    }
+}
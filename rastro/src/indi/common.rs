@@ -1,5 +1,5 @@
 
-#[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Default)]
+#[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Eq, Clone, Copy, Default)]
 pub enum IndiState {
     #[default]
     Idle, 
@@ -9,7 +9,7 @@ pub enum IndiState {
 }
 
 
-#[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq)]
+#[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Eq, Clone, Copy)]
 pub enum IndiPermission {
     #[serde(rename = "ro")] RO,
     #[serde(rename = "rw")] RW,
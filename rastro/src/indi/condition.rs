@@ -0,0 +1,410 @@
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+use crate::indi::error::{IndiError, IndiResult};
+use crate::indi::number_format::parse_number;
+use crate::indi::pattern::PropertyPattern;
+use crate::indi::registry::{DeviceRegistry, PropertyValue, PropertyVector};
+
+/// Element name that stands for the state of the whole vector, as in `Telescope Simulator.EQUATORIAL_EOD_COORD._STATE`.
+pub const STATE_ELEMENT: &str = "_STATE";
+
+/// What an element or a sub expression evaluates to.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Number(f64),
+    Text(String),
+    Bool(bool),
+}
+
+impl Value {
+    /**
+    The value of `element` in `vector`, switches, lights and `_STATE` read as their names
+    (`On`, `Busy`, ...) and BLOBs as their size.
+    */
+    pub fn of(vector: &PropertyVector, element: &str) -> Option<Value> {
+        if element == STATE_ELEMENT {
+            return Some(Value::Text(format!("{:?}", vector.state)));
+        }
+        Some(match &vector.element(element)?.value {
+            PropertyValue::Number { value, .. } => Value::Number(*value),
+            PropertyValue::Switch(value) => Value::Text(format!("{:?}", value)),
+            PropertyValue::Text(value) => Value::Text(value.clone()),
+            PropertyValue::Light(value) => Value::Text(format!("{:?}", value)),
+            PropertyValue::Blob { size, .. } => Value::Number(*size as f64),
+        })
+    }
+
+    fn number(&self) -> Option<f64> {
+        match self {
+            Value::Number(value) => Some(*value),
+            Value::Text(text) => parse_number(text),
+            Value::Bool(_) => None,
+        }
+    }
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Number(value) => write!(f, "{}", value),
+            Value::Text(value) => write!(f, "{:?}", value),
+            Value::Bool(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Or,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+/// Binary operators from the loosest to the tightest binding.
+const PRECEDENCE: &[&[(&str, Op)]] = &[
+    &[("||", Op::Or)],
+    &[("&&", Op::And)],
+    &[("==", Op::Eq), ("!=", Op::Ne), ("<=", Op::Le), (">=", Op::Ge), ("<", Op::Lt), (">", Op::Gt)],
+    &[("+", Op::Add), ("-", Op::Sub)],
+    &[("*", Op::Mul), ("/", Op::Div)],
+];
+
+/// Longest first, so `<=` is not read as `<` and `=`.
+const OPERATORS: &[&str] = &["||", "&&", "==", "!=", "<=", ">=", "=", "<", ">", "!", "(", ")", "+", "-", "*", "/"];
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Literal(Value),
+    Element(PropertyPattern),
+    Not(Box<Expr>),
+    Neg(Box<Expr>),
+    Binary(Op, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Quoted(String),
+    Op(&'static str),
+}
+
+/// Why an expression has no value.
+enum Failure {
+    /// An element it refers to is not defined (yet).
+    Undefined,
+    Invalid(String),
+}
+
+/**
+A boolean expression over element values, in the spirit of `indi_eval`.
+
+Elements are written as `device.property.element` without quotes, device names may contain
+spaces, `_STATE` as the element is the state of the vector. For example
+`Telescope Simulator.EQUATORIAL_EOD_COORD._STATE == Ok` or
+`CCD Simulator.CCD_TEMPERATURE.CCD_TEMPERATURE_VALUE < -9.5 && CCD Simulator.CCD_COOLER.COOLER_ON == On`.
+
+Supports `|| && ! == != < <= > >= + - * /` and parentheses. Numbers may be sexagesimal
+(`12:30:00`), other bare words (`Ok`, `On`) and quoted strings are text, which is compared exactly.
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub struct Condition {
+    source: String,
+    expr: Expr,
+}
+
+impl Condition {
+    /// Every element the condition refers to.
+    pub fn elements(&self) -> Vec<&PropertyPattern> {
+        fn visit<'a>(expr: &'a Expr, found: &mut Vec<&'a PropertyPattern>) {
+            match expr {
+                Expr::Literal(_) => {},
+                Expr::Element(target) => found.push(target),
+                Expr::Not(inner) | Expr::Neg(inner) => visit(inner, found),
+                Expr::Binary(_, left, right) => {
+                    visit(left, found);
+                    visit(right, found);
+                },
+            }
+        }
+        let mut found = Vec::new();
+        visit(&self.expr, &mut found);
+        found
+    }
+
+    /**
+    Evaluates the condition with the element values given by `lookup`.
+
+    `None` while an element it depends on is not defined, unless the rest of the expression
+    decides the outcome anyway (`a || b` with `b` true). Type errors, like ordering text, fail.
+    */
+    pub fn evaluate(&self, lookup: impl Fn(&PropertyPattern) -> Option<Value>) -> IndiResult<Option<bool>> {
+        let result = eval(&self.expr, &lookup).and_then(|value| match value {
+            Value::Bool(value) => Ok(value),
+            value => Err(Failure::Invalid(format!("evaluates to {} instead of true or false", value))),
+        });
+        match result {
+            Ok(value) => Ok(Some(value)),
+            Err(Failure::Undefined) => Ok(None),
+            Err(Failure::Invalid(reason)) => Err(IndiError::InvalidRequest(format!("{}: {}", self.source, reason))),
+        }
+    }
+
+    pub fn evaluate_in(&self, registry: &DeviceRegistry) -> IndiResult<Option<bool>> {
+        self.evaluate(|target| Value::of(registry.property(&target.device, &target.property)?, &target.element))
+    }
+}
+
+impl FromStr for Condition {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let tokens = tokenize(s)?;
+        let mut parser = Parser { tokens, pos: 0 };
+        let expr = parser.binary(0)?;
+        if let Some(token) = parser.tokens.get(parser.pos) {
+            return Err(format!("unexpected {:?} in {}", token, s));
+        }
+        Ok(Condition { source: s.trim().to_string(), expr })
+    }
+}
+
+impl Display for Condition {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = text.trim_start();
+    while !rest.is_empty() {
+        if let Some(op) = OPERATORS.iter().find(|op| rest.starts_with(**op)) {
+            tokens.push(Token::Op(if *op == "=" { "==" } else { op }));
+            rest = &rest[op.len()..];
+        } else if let Some(quote) = rest.strip_prefix(['"', '\'']).map(|_| &rest[..1]) {
+            let end = rest[1..].find(quote).ok_or_else(|| format!("unterminated {} in {}", quote, text))?;
+            tokens.push(Token::Quoted(rest[1..end + 1].to_string()));
+            rest = &rest[end + 2..];
+        } else {
+            //words run up to the next operator, a `-` only ends them after a space so `ASI120MM-S` stays whole
+            let mut end = rest.len();
+            let mut after_space = false;
+            for (i, c) in rest.char_indices() {
+                let ends = match c {
+                    '-' => after_space,
+                    '"' | '\'' => true,
+                    c => OPERATORS.iter().any(|op| op.starts_with(c)),
+                };
+                if ends {
+                    end = i;
+                    break;
+                }
+                after_space = c.is_whitespace();
+            }
+            tokens.push(Token::Word(rest[..end].trim_end().to_string()));
+            rest = &rest[end..];
+        }
+        rest = rest.trim_start();
+    }
+    Ok(tokens)
+}
+
+fn word(word: &str) -> Result<Expr, String> {
+    if let Some(value) = parse_number(word) {
+        return Ok(Expr::Literal(Value::Number(value)));
+    }
+    match word {
+        "true" => return Ok(Expr::Literal(Value::Bool(true))),
+        "false" => return Ok(Expr::Literal(Value::Bool(false))),
+        _ => {},
+    }
+    if !word.contains('.') {
+        return Ok(Expr::Literal(Value::Text(word.to_string())));
+    }
+    //device names may contain dots, property and element names do not
+    let mut parts = word.rsplitn(3, '.');
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(element), Some(property), Some(device)) if ![device, property, element].contains(&"") => {
+            let target = PropertyPattern::new(device, property, element);
+            if target.has_wildcards() {
+                return Err(format!("{} has wildcards, conditions need exact names", word));
+            }
+            Ok(Expr::Element(target))
+        },
+        _ => Err(format!("{} is not device.property.element", word)),
+    }
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn eat(&mut self, op: &str) -> bool {
+        if matches!(self.tokens.get(self.pos), Some(Token::Op(next)) if *next == op) {
+            self.pos += 1;
+            return true;
+        }
+        false
+    }
+
+    fn binary(&mut self, level: usize) -> Result<Expr, String> {
+        if level == PRECEDENCE.len() {
+            return self.unary();
+        }
+        let mut left = self.binary(level + 1)?;
+        'operators: loop {
+            for (symbol, op) in PRECEDENCE[level] {
+                if self.eat(symbol) {
+                    let right = self.binary(level + 1)?;
+                    left = Expr::Binary(*op, Box::new(left), Box::new(right));
+                    continue 'operators;
+                }
+            }
+            return Ok(left);
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        match self.next() {
+            Some(Token::Op("!")) => Ok(Expr::Not(Box::new(self.unary()?))),
+            Some(Token::Op("-")) => Ok(Expr::Neg(Box::new(self.unary()?))),
+            Some(Token::Op("(")) => {
+                let inner = self.binary(0)?;
+                if !self.eat(")") {
+                    return Err("missing )".to_string());
+                }
+                Ok(inner)
+            },
+            Some(Token::Word(text)) => word(&text),
+            Some(Token::Quoted(text)) => Ok(Expr::Literal(Value::Text(text))),
+            Some(Token::Op(op)) => Err(format!("unexpected {}", op)),
+            None => Err("unexpected end of expression".to_string()),
+        }
+    }
+}
+
+fn eval(expr: &Expr, lookup: &dyn Fn(&PropertyPattern) -> Option<Value>) -> Result<Value, Failure> {
+    match expr {
+        Expr::Literal(value) => Ok(value.clone()),
+        Expr::Element(target) => lookup(target).ok_or(Failure::Undefined),
+        Expr::Not(inner) => Ok(Value::Bool(!boolean(eval(inner, lookup)?)?)),
+        Expr::Neg(inner) => Ok(Value::Number(-number(eval(inner, lookup)?)?)),
+        Expr::Binary(op @ (Op::Or | Op::And), left, right) => {
+            //`||` is decided by a true side and `&&` by a false one, even if the other is undefined
+            let decisive = *op == Op::Or;
+            let left = eval(left, lookup).and_then(boolean);
+            match left {
+                Ok(value) if value == decisive => return Ok(Value::Bool(decisive)),
+                Err(Failure::Invalid(reason)) => return Err(Failure::Invalid(reason)),
+                _ => {},
+            }
+            if eval(right, lookup).and_then(boolean)? == decisive {
+                return Ok(Value::Bool(decisive));
+            }
+            left.map(Value::Bool)
+        },
+        Expr::Binary(op, left, right) => {
+            let (left, right) = (eval(left, lookup)?, eval(right, lookup)?);
+            match op {
+                Op::Add => Ok(Value::Number(number(left)? + number(right)?)),
+                Op::Sub => Ok(Value::Number(number(left)? - number(right)?)),
+                Op::Mul => Ok(Value::Number(number(left)? * number(right)?)),
+                Op::Div => Ok(Value::Number(number(left)? / number(right)?)),
+                op => compare(*op, left, right).map(Value::Bool),
+            }
+        },
+    }
+}
+
+fn boolean(value: Value) -> Result<bool, Failure> {
+    match value {
+        Value::Bool(value) => Ok(value),
+        value => Err(Failure::Invalid(format!("{} is not true or false", value))),
+    }
+}
+
+fn number(value: Value) -> Result<f64, Failure> {
+    value.number().ok_or_else(|| Failure::Invalid(format!("{} is not a number", value)))
+}
+
+fn compare(op: Op, left: Value, right: Value) -> Result<bool, Failure> {
+    let ordering = match (&left, &right) {
+        (Value::Number(_), _) | (_, Value::Number(_)) => number(left)?.partial_cmp(&number(right)?),
+        (Value::Text(a), Value::Text(b)) if matches!(op, Op::Eq | Op::Ne) => Some(a.cmp(b)),
+        (Value::Bool(a), Value::Bool(b)) if matches!(op, Op::Eq | Op::Ne) => Some(a.cmp(b)),
+        _ => return Err(Failure::Invalid(format!("cannot compare {} with {} that way", left, right))),
+    };
+    let ordering = match ordering {
+        Some(ordering) => ordering,
+        //NaN compares unequal to everything
+        None => return Ok(op == Op::Ne),
+    };
+    Ok(match op {
+        Op::Eq => ordering.is_eq(),
+        Op::Ne => ordering.is_ne(),
+        Op::Lt => ordering.is_lt(),
+        Op::Le => ordering.is_le(),
+        Op::Gt => ordering.is_gt(),
+        Op::Ge => ordering.is_ge(),
+        _ => unreachable!("not a comparison"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::indi::condition::{Condition, Value};
+    use crate::indi::pattern::PropertyPattern;
+    use crate::indi::registry::DeviceRegistry;
+    use crate::indi::IncomingMsg;
+
+    #[test]
+    fn it_evaluates_conditions() {
+        let mut registry = DeviceRegistry::new();
+        for xml in [
+            r#"<defNumberVector device="CCD Simulator" name="CCD_TEMPERATURE" label="Temperature (C)" group="Main Control" state="Busy" perm="rw" timeout="60" timestamp="2023-01-12T20:51:39">
+                <defNumber name="CCD_TEMPERATURE_VALUE" label="Temperature (C)" format="%5.2f" min="-50" max="50" step="0">-10</defNumber>
+            </defNumberVector>"#,
+            r#"<defSwitchVector device="Telescope Simulator" name="CONNECTION" label="Connection" group="Main Control" state="Ok" perm="rw" rule="OneOfMany" timeout="60" timestamp="2023-01-12T20:51:39">
+                <defSwitch name="CONNECT" label="Connect">On</defSwitch>
+                <defSwitch name="DISCONNECT" label="Disconnect">Off</defSwitch>
+            </defSwitchVector>"#,
+        ] {
+            registry.apply(&IncomingMsg::parse(xml).unwrap());
+        }
+        let check = |text: &str| text.parse::<Condition>().unwrap().evaluate_in(&registry).unwrap();
+
+        assert_eq!(check("CCD Simulator.CCD_TEMPERATURE.CCD_TEMPERATURE_VALUE < -9.5"), Some(true));
+        assert_eq!(check("CCD Simulator.CCD_TEMPERATURE._STATE == Ok"), Some(false));
+        assert_eq!(check("Telescope Simulator.CONNECTION.CONNECT = On && !(Telescope Simulator.CONNECTION._STATE != 'Ok')"), Some(true));
+        assert_eq!(check("CCD Simulator.CCD_TEMPERATURE.CCD_TEMPERATURE_VALUE * 2 + 1 >= -19"), Some(true));
+        assert_eq!(check("Telescope Simulator.EQUATORIAL_EOD_COORD.RA > 12:30"), None);
+        assert_eq!(check("Telescope Simulator.EQUATORIAL_EOD_COORD.RA > 12:30 || CCD Simulator.CCD_TEMPERATURE._STATE == Busy"), Some(true));
+
+        assert!("CCD Simulator.CCD_TEMPERATURE.CCD_TEMPERATURE_VALUE".parse::<Condition>().unwrap().evaluate_in(&registry).is_err());
+        assert!("CCD Simulator.CCD_TEMPERATURE._STATE < Ok".parse::<Condition>().unwrap().evaluate_in(&registry).is_err());
+        assert!("CCD Simulator.CCD_TEMPERATURE < 1".parse::<Condition>().is_err());
+        assert!("(1 < 2".parse::<Condition>().is_err());
+
+        let condition: Condition = "ZWO CCD ASI120MM-S.CCD_TEMPERATURE.CCD_TEMPERATURE_VALUE - 1 > 0".parse().unwrap();
+        assert_eq!(condition.elements(), vec![&PropertyPattern::new("ZWO CCD ASI120MM-S", "CCD_TEMPERATURE", "CCD_TEMPERATURE_VALUE")]);
+        assert_eq!(condition.evaluate(|_| Some(Value::Number(2.0))).unwrap(), Some(true));
+    }
+}
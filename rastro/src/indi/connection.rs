@@ -1,11 +1,12 @@
-use std::error::Error;
-use std::io::{ErrorKind, Write};
+use std::io::Write;
 use std::io::ErrorKind::WouldBlock;
 use std::net::{Shutdown, TcpStream};
-use quick_xml::events::{Event};
-use serde::{Deserialize};
+use bytes::BytesMut;
+use tokio_util::codec::Decoder;
 
-use crate::{IncomingMsg};
+use crate::indi::codec::IncomingMsgCodec;
+use crate::indi::error::{IndiError, IndiResult};
+use crate::indi::{IncomingMsg, OutgoingMsg};
 use crate::config_file::ConnectionSpec;
 
 pub struct IndiConnection {
@@ -29,11 +30,21 @@ struct IndiReaderLoopHandle {
 
 impl IndiReaderLoopHandle {
     fn on_drop(&mut self) {
-        let handle = self.handle.take().unwrap();
+        let handle = match self.handle.take() {
+            Some(handle) => handle,
+            None => return,
+        };
         log::trace!("calling thread blocked for close");
-        self.stream.flush().unwrap();
-        self.stream.shutdown(Shutdown::Both).unwrap();
-        handle.join().unwrap();
+        //the server may already be gone, which is fine as long as the reader stops
+        if let Err(e) = self.stream.flush() {
+            log::debug!("flush on close failed: {}", e);
+        }
+        if let Err(e) = self.stream.shutdown(Shutdown::Both) {
+            log::debug!("shutdown on close failed: {}", e);
+        }
+        if handle.join().is_err() {
+            log::error!("reader thread panicked");
+        }
         log::trace!("calling thread unblocked after close");
     }
 }
@@ -42,117 +53,70 @@ impl Drop for IndiReaderLoopHandle {
     fn drop(&mut self) {self.on_drop()}
 }
 
+/// How much we ask the socket for at a time, BLOBs simply take several reads.
+const READ_CHUNK: usize = 64 * 1024;
+
+/**
+Reads raw bytes and hands complete top level elements to `IncomingMsgCodec`,
+which deserializes each of them straight from the read buffer.
+*/
 struct IndiReaderLoopXMLProcessor {
-    event_reader: quick_xml::Reader<std::io::BufReader<Box<dyn std::io::Read>>>,
-    buff: Vec<u8>,
-    depth: usize,
+    reader: Box<dyn std::io::Read>,
+    buff: BytesMut,
+    codec: IncomingMsgCodec,
 }
 
 impl IndiReaderLoopXMLProcessor {
     fn new<Read>(r: Read) -> IndiReaderLoopXMLProcessor where Read: std::io::Read + 'static {
-        let buff = std::io::BufReader::new(Box::new(r) as Box<dyn std::io::Read>);
-        let mut event_reader = quick_xml::Reader::from_reader(buff);
-        event_reader.trim_text(true);
         IndiReaderLoopXMLProcessor {
-            event_reader,
-            buff: Vec::new(),
-            depth: 0
+            reader: Box::new(r),
+            buff: BytesMut::with_capacity(READ_CHUNK),
+            codec: IncomingMsgCodec::new(),
         }
     }
 
-    fn should_quit(&self, event: &quick_xml::Result<Event>) -> bool {
-        match event {
+    fn should_quit(&self, read: &std::io::Result<usize>) -> bool {
+        match read {
             //we if quit the reader cannot read anymore
-            Ok(Event::Eof) => true,
+            Ok(0) => true,
 
             //or if "someone" (aka ReaderLoopHandle) set the socket as non-blocking or having a read_timeout
-            Err(quick_xml::Error::Io(io)) if io.kind() == WouldBlock => true,
+            Err(io) if io.kind() == WouldBlock => true,
             _ => false
         }
     }
 
-    fn next(&mut self) -> Result<(Option<IncomingMsg>, bool), Box<dyn Error>> {
-        let mut buf = Vec::<u8>::new();
-
-        let event = self.event_reader.read_event_into(&mut buf);
+    fn next(&mut self) -> IndiResult<(Option<IncomingMsg>, bool)> {
+        if let Some(msg) = self.codec.decode(&mut self.buff)? {
+            return Ok((Some(msg), true));
+        }
 
-        return match &event {
+        let len = self.buff.len();
+        self.buff.resize(len + READ_CHUNK, 0);
+        let read = self.reader.read(&mut self.buff[len..]);
+        self.buff.truncate(len + *read.as_ref().unwrap_or(&0));
 
-            Err(_) | Ok(_) if self.should_quit(&event) => Ok((None, false)),
+        return match read {
+            Err(_) | Ok(_) if self.should_quit(&read) => {
+                log::debug!("Read what we could right now");
+                Ok((None, false))
+            },
 
             Err(e) => {
-                log::trace!("unhandled error while reading xml {:?}{:?}", e, e.source());
-                Err(e.clone().into())
+                log::trace!("unhandled error while reading xml {:?}", e);
+                Err(e.into())
             },
 
-            Ok(ref event) => match event {
-                Event::Start(ref _start) => {
-                    self.buff.extend_from_slice("<".as_bytes());
-                    self.buff.extend(&**event);
-                    self.buff.extend_from_slice(">".as_bytes());
-                    self.depth = self.depth + 1;
-                    Ok((None, true))
-                },
-
-                Event::End(ref _end) => {
-                    self.buff.extend_from_slice("</".as_bytes());
-                    self.buff.extend(&**event);
-                    self.buff.extend_from_slice(">".as_bytes());
-
-                    self.depth = self.depth - 1;
-                    match self.depth {
-                        0 => {
-
-                            let msg = {
-                                let str_ref = std::str::from_utf8(&self.buff).unwrap();
-                                let mut de = quick_xml::de::Deserializer::from_str(str_ref);
-                                IncomingMsg::deserialize(&mut de)
-                            };
-
-                            self.buff.clear();
-                            match msg {
-                                Ok(msg) => Ok((Some(msg), true)),
-                                Err(e) => {
-                                    let str_ref = std::str::from_utf8(&self.buff).unwrap();
-                                    log::trace!("could not parse {str_ref}");
-                                    log::trace!("{:?}", e);
-                                    Err(e.into())
-                                }
-                            }
-                        },
-                        _ => Ok((None,  true))
-                    }
-                },
-
-                Event::Text(ref _t) => {
-                    self.buff.extend(&**event);
-                    Ok((None, true))
-                },
-
-                //ignore empty parts
-                Event::Empty(_) => Ok((None, true)),
-
-                Event::Eof => {
-                    log::debug!("Read what we could right now");
-                    Ok((None, false))
-                }
-
-                e => {
-                    log::error!("Unhandled event {:?}", e);
-                    let err = std::io::Error::new(ErrorKind::Unsupported, "Unhandled message").into();
-                    Err(err)
-                }
-            }
+            Ok(_) => Ok((None, true))
         };
     }
 }
 
 impl IndiReaderLoop {
-    fn create(stream: TcpStream, output: std::sync::mpsc::Sender<IncomingMsg>) -> IndiReaderLoopHandle {
-        let unblock_stream = stream.try_clone().unwrap();
-        let thread_stream = stream.try_clone().unwrap();
+    fn create(stream: TcpStream, output: std::sync::mpsc::Sender<IncomingMsg>) -> IndiResult<IndiReaderLoopHandle> {
+        let unblock_stream = stream.try_clone()?;
         let handle = std::thread::spawn(move || {
-            let mut r_loop = IndiReaderLoop { stream: thread_stream, output };
+            let mut r_loop = IndiReaderLoop { stream, output };
 
             log::info!("reader starting");
             let output = r_loop.reader_main();
@@ -163,15 +127,15 @@ impl IndiReaderLoop {
             }
         });
 
-        IndiReaderLoopHandle {
+        Ok(IndiReaderLoopHandle {
             stream: unblock_stream,
             handle: Some(handle)
-        }
+        })
     }
 
-    pub fn reader_main(&mut self) -> Result<(), Box<dyn Error>> {
+    pub fn reader_main(&mut self) -> IndiResult<()> {
 
-        let mut xml_loop = IndiReaderLoopXMLProcessor::new(self.stream.try_clone().unwrap());
+        let mut xml_loop = IndiReaderLoopXMLProcessor::new(self.stream.try_clone()?);
         loop {
             let msg = xml_loop.next();
             match msg {
@@ -179,8 +143,9 @@ impl IndiReaderLoop {
                 Ok((msg, should_continue)) => {
 
                     //send message
-                    msg
-                        .map(|msg| self.output.send(msg));
+                    if let Some(msg) = msg {
+                        let _ = self.output.send(msg);
+                    }
 
                     if !should_continue {
                         break;
@@ -284,30 +249,31 @@ impl IndiReaderLoop {
 
 
 impl IndiConnection {
-    pub fn connect(spec: &ConnectionSpec) -> Result<IndiConnection, Box<dyn Error>> {
-        let connection_str = format!("{}:{}", spec.host, spec.port);
+    pub fn connect(spec: &ConnectionSpec) -> IndiResult<IndiConnection> {
+        let address = spec.address();
 
-        let stream = std::net::TcpStream::connect(&connection_str).unwrap();
+        let stream = std::net::TcpStream::connect(&address)
+            .map_err(|source| IndiError::Connect { address, source })?;
         let (tx, rx) = std::sync::mpsc::channel::<IncomingMsg>();
-        let r_stream = stream.try_clone().unwrap();
+        let r_stream = stream.try_clone()?;
 
         Ok(IndiConnection {
             stream,
-            read_handle: IndiReaderLoop::create(r_stream, tx),
+            read_handle: IndiReaderLoop::create(r_stream, tx)?,
             rx
         })
     }
 
-    pub fn send(&mut self, msg: &IncomingMsg) -> Result<(), Box<dyn Error>> {
-        let str = quick_xml::se::to_string(msg)?;
-        self.stream.write(str.as_bytes())?;
+    pub fn send(&mut self, msg: &OutgoingMsg) -> IndiResult<()> {
+        let str = quick_xml::se::to_string(msg).map_err(IndiError::Serialize)?;
+        self.stream.write_all(str.as_bytes())?;
         Ok(())
     }
 
-    pub fn recv_or_none(&self) -> Result<Option<Box<IncomingMsg>>, Box<dyn Error>> {
+    pub fn recv_or_none(&self) -> IndiResult<Option<Box<IncomingMsg>>> {
         match self.rx.try_recv() {
             Err(std::sync::mpsc::TryRecvError::Empty) => Ok(None),
-            Err(e) => Err(e.into()),
+            Err(std::sync::mpsc::TryRecvError::Disconnected) => Err(IndiError::ChannelClosed),
             Ok(msg) => Ok(Some(Box::new(msg)))
         }
     }
@@ -317,4 +283,82 @@ impl IndiConnection {
     //     Ok(Box::new(msg))
     // }
 
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use std::io::Read;
+    use crate::indi::connection::IndiReaderLoopXMLProcessor;
+    use crate::indi::IncomingMsg;
+
+    /// Hands out the data a few bytes at a time, like a slow socket.
+    struct Trickle {
+        data: Vec<u8>,
+        pos: usize,
+        chunk: usize,
+    }
+
+    impl Read for Trickle {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let n = self.chunk.min(buf.len()).min(self.data.len() - self.pos);
+            buf[..n].copy_from_slice(&self.data[self.pos..self.pos + n]);
+            self.pos += n;
+            Ok(n)
+        }
+    }
+
+    fn read_all(xml: String, chunk: usize) -> Result<Vec<IncomingMsg>, Box<dyn Error>> {
+        let mut processor = IndiReaderLoopXMLProcessor::new(Trickle { data: xml.into_bytes(), pos: 0, chunk });
+        let mut out = Vec::new();
+        loop {
+            let (msg, should_continue) = processor.next()?;
+            out.extend(msg);
+            if !should_continue {
+                return Ok(out);
+            }
+        }
+    }
+
+    #[test]
+    fn it_reads_empty_elements_cdata_and_entities() -> Result<(), Box<dyn Error>> {
+        let msgs = read_all(r#"
+            <message device="CCD Simulator" message="[INFO] T &lt; -10 &amp; cooling" timestamp="2023-02-11T07:16:57"/>
+            <delProperty device="CCD Simulator" name="CCD_TEMPERATURE"/>
+            <setTextVector device="CCD Simulator" name="UPLOAD_SETTINGS" state="Ok" timeout="60" timestamp="2023-02-11T07:16:57">
+                <oneText name="UPLOAD_DIR"><![CDATA[/tmp/<frames>]]></oneText>
+                <oneText name="UPLOAD_PREFIX">IMAGE_&#88;&#88;&#88;</oneText>
+            </setTextVector>
+        "#.to_string(), 7)?;
+
+        assert_eq!(msgs.len(), 3);
+        assert_eq!(msgs[0].to_string(), r#"📝 CCD Simulator "[INFO] T < -10 & cooling""#);
+        match &msgs[1] {
+            IncomingMsg::DelProperty(v) => assert_eq!(v.name.as_deref(), Some("CCD_TEMPERATURE")),
+            msg => panic!("unexpected {:?}", msg),
+        }
+        match &msgs[2] {
+            IncomingMsg::SetTextVector(v) => {
+                assert_eq!(v.texts[0].value, "/tmp/<frames>");
+                assert_eq!(v.texts[1].value, "IMAGE_XXX");
+            },
+            msg => panic!("unexpected {:?}", msg),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn it_reads_large_blobs() -> Result<(), Box<dyn Error>> {
+        let image: Vec<u8> = (0..4 * 1024 * 1024u32).map(|i| (i % 251) as u8).collect();
+        let xml = format!(
+            r#"<setBLOBVector device="CCD Simulator" name="CCD1" state="Ok" timeout="60" timestamp="2023-02-11T07:16:57"><oneBLOB name="CCD1" size="{0}" format=".fits" len="{0}">{1}</oneBLOB></setBLOBVector>"#,
+            image.len(), base64::encode(&image)
+        );
+
+        let msgs = read_all(xml, 1024 * 1024)?;
+        match &msgs[..] {
+            [IncomingMsg::SetBlobVector(v)] => assert_eq!(v.blobs[0].decode()?, image),
+            msgs => panic!("unexpected {:?}", msgs.len()),
+        }
+        Ok(())
+    }
+}
@@ -1,9 +1,12 @@
 #[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq)]
 pub struct DelProperty {
+    #[serde(rename = "@device")]
     pub device: String,
 
+    #[serde(rename = "@name", default)]
     pub name: Option<String>,
 
+    #[serde(rename = "@timestamp", default)]
     pub timestamp: Option<String>,
 
     #[serde(flatten)]
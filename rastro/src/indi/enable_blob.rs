@@ -1,14 +1,35 @@
+use std::collections::BTreeMap;
 
-#[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq)]
+#[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Eq, Clone, Copy)]
 pub enum EnableBLOBValue {
-    #[serde(rename = "None")] None,
+    #[serde(rename = "Never", alias = "None")] Never,
     #[serde(rename = "Only")] Only,
     #[serde(rename = "Also")] Also,
 }
 
-#[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq)]
+#[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Clone)]
 pub struct EnableBLOB {
+    #[serde(rename = "@device", default, skip_serializing_if = "Option::is_none")]
+    pub device: Option<String>,
+
+    #[serde(rename = "@name", default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+
     #[serde(rename = "$text")]
     pub value: EnableBLOBValue
 }
 
+
+/// Whether a client that sent `enabled` wants a message of `device::property`, the most specific `enableBLOB` decides.
+pub(crate) fn wants(enabled: &BTreeMap<(String, Option<String>), EnableBLOBValue>, device: &str, property: Option<&str>, blob: bool) -> bool {
+    let value = enabled
+        .get(&(device.to_string(), property.map(str::to_string)))
+        .or_else(|| enabled.get(&(device.to_string(), None)))
+        .copied()
+        .unwrap_or(EnableBLOBValue::Never);
+    match value {
+        EnableBLOBValue::Never => !blob,
+        EnableBLOBValue::Also => true,
+        EnableBLOBValue::Only => blob,
+    }
+}
@@ -0,0 +1,130 @@
+use std::fmt::{Display, Formatter};
+
+/// How much of an offending element ends up in a `Parse` error, BLOBs can be megabytes.
+const SNIPPET_LEN: usize = 256;
+
+#[derive(Debug)]
+pub enum IndiError {
+    /// Could not reach the server at `address`.
+    Connect { address: String, source: std::io::Error },
+    /// Reading or writing an established connection failed.
+    Io(std::io::Error),
+    /// The server sent an element that does not deserialize, `xml` is the start of it.
+    Parse { xml: String, reason: String },
+    /// A well formed element that is not an INDI message we know.
+    UnknownMessage { tag: String },
+    /// A message could not be turned into xml.
+    Serialize(quick_xml::DeError),
+    /// The connection, or the task reading it, is gone.
+    ChannelClosed,
+    /// Lost the server and not reconnected (yet).
+    NotConnected,
+    Timeout(String),
+    /// A request the server would reject, e.g. writing an undefined or read-only property.
+    InvalidRequest(String),
+    /// A payload that does not match what its element says, e.g. a BLOB of the wrong size.
+    InvalidData(String),
+    /// The driver of `device` does not define the standard `property`.
+    Unsupported { device: String, property: String },
+    /// The driver answered a request with the `Alert` state.
+    Alert { device: String, property: String },
+}
+
+pub type IndiResult<T> = Result<T, IndiError>;
+
+impl IndiError {
+    /// Classifies a failure to deserialize `xml` as an `IncomingMsg` or `OutgoingMsg`.
+    pub(crate) fn parse(xml: &str, e: quick_xml::DeError) -> IndiError {
+        let tag = root_tag(xml);
+        match &e {
+            //attributes like `state` are enums too, only an unknown root tag is an unknown message
+            quick_xml::DeError::Custom(reason) if reason.starts_with(&format!("unknown variant `{}`", tag)) => {
+                IndiError::UnknownMessage { tag: tag.to_string() }
+            },
+            _ => IndiError::Parse { xml: snippet(xml), reason: e.to_string() },
+        }
+    }
+}
+
+fn root_tag(xml: &str) -> &str {
+    let xml = xml.trim_start().trim_start_matches('<');
+    let end = xml.find(|c: char| c.is_whitespace() || c == '>' || c == '/').unwrap_or(xml.len());
+    &xml[..end]
+}
+
+fn snippet(xml: &str) -> String {
+    if xml.len() <= SNIPPET_LEN {
+        return xml.to_string();
+    }
+    let mut end = SNIPPET_LEN;
+    while !xml.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}…", &xml[..end])
+}
+
+impl Display for IndiError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IndiError::Connect { address, source } => write!(f, "could not connect to {}: {}", address, source),
+            IndiError::Io(e) => write!(f, "connection failed: {}", e),
+            IndiError::Parse { xml, reason } => write!(f, "could not parse {}: {}", xml, reason),
+            IndiError::UnknownMessage { tag } => write!(f, "unknown message <{}>", tag),
+            IndiError::Serialize(e) => write!(f, "could not serialize message: {}", e),
+            IndiError::ChannelClosed => write!(f, "connection closed"),
+            IndiError::NotConnected => write!(f, "not connected"),
+            IndiError::Timeout(what) => write!(f, "timed out: {}", what),
+            IndiError::InvalidRequest(what) => write!(f, "invalid request: {}", what),
+            IndiError::InvalidData(what) => write!(f, "invalid data: {}", what),
+            IndiError::Unsupported { device, property } => write!(f, "{} does not support {}", device, property),
+            IndiError::Alert { device, property } => write!(f, "{}::{} failed", device, property),
+        }
+    }
+}
+
+impl std::error::Error for IndiError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            IndiError::Connect { source, .. } => Some(source),
+            IndiError::Io(e) => Some(e),
+            IndiError::Serialize(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for IndiError {
+    fn from(e: std::io::Error) -> Self {
+        IndiError::Io(e)
+    }
+}
+
+impl From<base64::DecodeError> for IndiError {
+    fn from(e: base64::DecodeError) -> Self {
+        IndiError::InvalidData(e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::indi::error::IndiError;
+    use crate::indi::IncomingMsg;
+
+    fn parse(xml: &str) -> IndiError {
+        IndiError::parse(xml, quick_xml::de::from_str::<IncomingMsg>(xml).unwrap_err())
+    }
+
+    #[test]
+    fn it_classifies_parse_errors() {
+        assert!(matches!(
+            parse(r#"<pingRequest uid="1"/>"#),
+            IndiError::UnknownMessage { tag } if tag == "pingRequest"
+        ));
+
+        let long = format!(r#"<setNumberVector device="a" name="b" state="Nope">{}</setNumberVector>"#, "x".repeat(1000));
+        match parse(&long) {
+            IndiError::Parse { xml, .. } => assert!(xml.starts_with(r#"<setNumberVector device="a""#) && xml.len() < 300),
+            e => panic!("unexpected {:?}", e),
+        }
+    }
+}
@@ -1,7 +1,23 @@
 
-#[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq)]
+#[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Clone)]
 pub struct GetProperties {
     #[serde(rename = "@version")]
-    pub version: String
+    pub version: String,
+
+    #[serde(rename = "@device", default, skip_serializing_if = "Option::is_none")]
+    pub device: Option<String>,
+
+    #[serde(rename = "@name", default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+}
+
+impl Default for GetProperties {
+    fn default() -> Self {
+        GetProperties {
+            version: "1.7".to_string(),
+            device: None,
+            name: None,
+        }
+    }
 }
 
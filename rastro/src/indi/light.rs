@@ -55,4 +55,54 @@ impl std::fmt::Display for DefLightValue {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{} {}", self.value, self.name)
     }
-}
\ No newline at end of file
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq)]
+pub struct SetLightValue {
+    #[serde(rename = "@name")]
+    pub name: String,
+
+    #[serde(rename = "$text")]
+    pub value: IndiState,
+
+    #[serde(flatten)]
+    extra: std::collections::HashMap<String, String>,
+}
+
+/// Lights are read only, so unlike the other `set*Vector`s this one has no timeout.
+#[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq)]
+pub struct SetLightVector {
+    #[serde(rename = "@name")]
+    pub name: String,
+    #[serde(rename = "@state")]
+    pub state: IndiState,
+    #[serde(rename = "@device")]
+    pub device: String,
+    #[serde(rename = "@timestamp")]
+    pub timestamp: String,
+
+    #[serde(rename = "oneLight", default)]
+    pub lights: Vec<SetLightValue>,
+
+    #[serde(flatten)]
+    extra: std::collections::HashMap<String, String>,
+}
+
+impl std::fmt::Display for SetLightVector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{} {}::{}(Light)", self.state, self.device, self.name)?;
+        for v in &self.lights {
+            writeln!(f, "\t{}", v)?;
+        }
+        if !self.extra.is_empty() {
+            writeln!(f, "{:?}", self.extra)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for SetLightValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.value, self.name)
+    }
+}
@@ -2,9 +2,12 @@
 
 #[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq)]
 pub struct Message {
-    device: String,
-    message: String,
-    timestamp: String,
+    #[serde(rename = "@device", default)]
+    pub device: String,
+    #[serde(rename = "@message", default)]
+    pub message: String,
+    #[serde(rename = "@timestamp", default)]
+    pub timestamp: String,
 
     #[serde(flatten)]
     extra: std::collections::HashMap<String, String>,
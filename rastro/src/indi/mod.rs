@@ -3,14 +3,27 @@ use std::fmt::{Debug, Display, Formatter};
 pub mod common;
 pub mod switch;
 pub mod number;
+pub mod number_format;
 pub mod text;
 pub mod message;
 pub mod light;
 pub mod del;
 pub mod blob;
+pub mod blob_stream;
 pub mod connection;
+pub mod async_connection;
+pub mod codec;
+pub mod client;
+pub mod reconnect;
 pub mod get_properties;
 pub mod enable_blob;
+pub mod registry;
+pub mod error;
+pub mod session;
+pub mod pattern;
+pub mod condition;
+pub mod recording;
+pub mod replay;
 
 #[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq)]
 pub enum IncomingMsg {
@@ -33,6 +46,8 @@ pub enum IncomingMsg {
 
     #[serde(rename = "defLightVector")]
     DefLightVector(light::DefLightVector),
+    #[serde(rename = "setLightVector")]
+    SetLightVector(light::SetLightVector),
 
     #[serde(rename = "defBLOBVector")]
     DefBlobVector(blob::DefBlobVector),
@@ -46,13 +61,6 @@ pub enum IncomingMsg {
     DelProperty(del::DelProperty),
 
 
-    #[serde(rename = "getProperties")]
-    GetProperties(get_properties::GetProperties),
-
-    #[serde(rename = "enableBLOB")]
-    EnableBLOB(enable_blob::EnableBLOB),
-
-
     #[serde(
         //alias = "getProperties",
         alias = "newNumberVector",
@@ -66,6 +74,32 @@ pub enum IncomingMsg {
     //Unparsed(String)
 }
 
+impl IncomingMsg {
+    /// Deserializes one top level element.
+    pub fn parse(xml: &str) -> error::IndiResult<IncomingMsg> {
+        quick_xml::de::from_str(xml).map_err(|e| error::IndiError::parse(xml, e))
+    }
+
+    /// The device and property this message is about, the property is `None` for device wide messages.
+    pub fn target(&self) -> Option<(&str, Option<&str>)> {
+        match self {
+            IncomingMsg::DefSwitchVector(v) => Some((&v.device, Some(&v.name))),
+            IncomingMsg::SetSwitchVector(v) => Some((&v.device, Some(&v.name))),
+            IncomingMsg::DefTextVector(v) => Some((&v.device, Some(&v.name))),
+            IncomingMsg::SetTextVector(v) => Some((&v.device, Some(&v.name))),
+            IncomingMsg::DefNumberVector(v) => Some((&v.device, Some(&v.name))),
+            IncomingMsg::SetNumberVector(v) => Some((&v.device, Some(&v.name))),
+            IncomingMsg::DefLightVector(v) => Some((&v.device, Some(&v.name))),
+            IncomingMsg::SetLightVector(v) => Some((&v.device, Some(&v.name))),
+            IncomingMsg::DefBlobVector(v) => Some((&v.device, Some(&v.name))),
+            IncomingMsg::SetBlobVector(v) => Some((&v.device, Some(&v.name))),
+            IncomingMsg::DelProperty(v) => Some((&v.device, v.name.as_deref())),
+            IncomingMsg::Message(v) => Some((&v.device, None)),
+            IncomingMsg::Unparsed(_) => None,
+        }
+    }
+}
+
 impl Display for IncomingMsg {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -86,13 +120,53 @@ impl Display for IncomingMsg {
             IncomingMsg::SetBlobVector(v) => Display::fmt(v, f),
             IncomingMsg::SetTextVector(v) => Display::fmt(v, f),
             IncomingMsg::SetNumberVector(v) => Display::fmt(v, f),
+            IncomingMsg::SetLightVector(v) => Display::fmt(v, f),
 
-            //One offs
-            IncomingMsg::GetProperties(v) => Debug::fmt(v, f),
-            IncomingMsg::EnableBLOB(v) => Debug::fmt(v, f),
+            IncomingMsg::Unparsed(v) => Debug::fmt(v, f),
+        }
+    }
+}
 
+#[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Clone)]
+pub enum OutgoingMsg {
+    #[serde(rename = "getProperties")]
+    GetProperties(get_properties::GetProperties),
 
-            IncomingMsg::Unparsed(v) => Debug::fmt(v, f),
+    #[serde(rename = "enableBLOB")]
+    EnableBLOB(enable_blob::EnableBLOB),
+
+    #[serde(rename = "newSwitchVector")]
+    NewSwitchVector(switch::NewSwitchVector),
+
+    #[serde(rename = "newTextVector")]
+    NewTextVector(text::NewTextVector),
+
+    #[serde(rename = "newNumberVector")]
+    NewNumberVector(number::NewNumberVector),
+
+    #[serde(rename = "newBLOBVector")]
+    NewBlobVector(blob::NewBlobVector),
+}
+
+impl OutgoingMsg {
+    /// Deserializes one top level element, as a driver receives it.
+    pub fn parse(xml: &str) -> error::IndiResult<OutgoingMsg> {
+        quick_xml::de::from_str(xml).map_err(|e| error::IndiError::parse(xml, e))
+    }
+}
+
+impl Display for OutgoingMsg {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            //New
+            OutgoingMsg::NewSwitchVector(v) => Display::fmt(v, f),
+            OutgoingMsg::NewTextVector(v) => Display::fmt(v, f),
+            OutgoingMsg::NewNumberVector(v) => Display::fmt(v, f),
+            OutgoingMsg::NewBlobVector(v) => Display::fmt(v, f),
+
+            //One offs
+            OutgoingMsg::GetProperties(v) => Debug::fmt(v, f),
+            OutgoingMsg::EnableBLOB(v) => Debug::fmt(v, f),
         }
     }
 }
@@ -102,9 +176,14 @@ impl Display for IncomingMsg {
 mod test {
     use quick_xml::DeError;
     use crate::indi::blob::SetBlobVector;
+    use crate::indi::enable_blob::{EnableBLOB, EnableBLOBValue};
+    use crate::indi::get_properties::GetProperties;
+    use crate::indi::number::{NewNumberValue, NewNumberVector};
+    use crate::indi::OutgoingMsg;
 
 
-    use crate::indi::switch::DefSwitchVector;
+    use crate::indi::switch::{DefSwitchVector, IndiSwitch, NewSwitchValue, NewSwitchVector};
+    use crate::indi::text::{NewTextValue, NewTextVector};
 
     #[test]
     fn it_parses() -> Result<(), DeError>{
@@ -138,4 +217,57 @@ mod test {
 
         Ok(())
     }
+
+    #[test]
+    fn it_serializes_new_vectors() -> Result<(), DeError> {
+        let msg = OutgoingMsg::NewSwitchVector(NewSwitchVector {
+            device: "CCD Simulator".to_string(),
+            name: "CONNECTION".to_string(),
+            timestamp: None,
+            switches: vec![
+                NewSwitchValue { name: "CONNECT".to_string(), value: IndiSwitch::On },
+                NewSwitchValue { name: "DISCONNECT".to_string(), value: IndiSwitch::Off },
+            ]
+        });
+        assert_eq!(
+            quick_xml::se::to_string(&msg)?,
+            r#"<newSwitchVector device="CCD Simulator" name="CONNECTION"><oneSwitch name="CONNECT">On</oneSwitch><oneSwitch name="DISCONNECT">Off</oneSwitch></newSwitchVector>"#
+        );
+
+        let msg = OutgoingMsg::NewNumberVector(NewNumberVector {
+            device: "CCD Simulator".to_string(),
+            name: "CCD_EXPOSURE".to_string(),
+            timestamp: Some("2023-02-11T07:16:57".to_string()),
+            numbers: vec![NewNumberValue { name: "CCD_EXPOSURE_VALUE".to_string(), value: 1.5 }]
+        });
+        assert_eq!(
+            quick_xml::se::to_string(&msg)?,
+            r#"<newNumberVector device="CCD Simulator" name="CCD_EXPOSURE" timestamp="2023-02-11T07:16:57"><oneNumber name="CCD_EXPOSURE_VALUE">1.5</oneNumber></newNumberVector>"#
+        );
+
+        let msg = OutgoingMsg::NewTextVector(NewTextVector {
+            device: "CCD Simulator".to_string(),
+            name: "UPLOAD_SETTINGS".to_string(),
+            timestamp: None,
+            texts: vec![NewTextValue { name: "UPLOAD_DIR".to_string(), value: "/tmp/a&b".to_string() }]
+        });
+        assert_eq!(
+            quick_xml::se::to_string(&msg)?,
+            r#"<newTextVector device="CCD Simulator" name="UPLOAD_SETTINGS"><oneText name="UPLOAD_DIR">/tmp/a&amp;b</oneText></newTextVector>"#
+        );
+
+        let msg = OutgoingMsg::GetProperties(GetProperties::default());
+        assert_eq!(quick_xml::se::to_string(&msg)?, r#"<getProperties version="1.7"/>"#);
+
+        let msg = OutgoingMsg::GetProperties(GetProperties { device: Some("CCD Simulator".to_string()), ..GetProperties::default() });
+        assert_eq!(quick_xml::se::to_string(&msg)?, r#"<getProperties version="1.7" device="CCD Simulator"/>"#);
+
+        let msg = OutgoingMsg::EnableBLOB(EnableBLOB {
+            device: Some("Guide Simulator".to_string()),
+            name: Some("CCD1".to_string()),
+            value: EnableBLOBValue::Never
+        });
+        assert_eq!(quick_xml::se::to_string(&msg)?, r#"<enableBLOB device="Guide Simulator" name="CCD1">Never</enableBLOB>"#);
+        Ok(())
+    }
 }
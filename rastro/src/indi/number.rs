@@ -1,4 +1,5 @@
 use super::common::{IndiState, IndiPermission};
+use super::number_format::{deserialize_number, format_number};
 
 
 #[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq)]
@@ -8,15 +9,15 @@ pub struct DefNumberValue {
     pub name: String,
     #[serde(rename = "@label")]
     pub label: String,
-    #[serde(rename = "$text")]
+    #[serde(rename = "$text", deserialize_with = "deserialize_number")]
     pub value: f64,
     #[serde(rename = "@format")]
     pub format: String,
-    #[serde(rename = "@min")]
+    #[serde(rename = "@min", deserialize_with = "deserialize_number")]
     pub min: f64,
-    #[serde(rename = "@max")]
+    #[serde(rename = "@max", deserialize_with = "deserialize_number")]
     pub max: f64,
-    #[serde(rename = "@step")]
+    #[serde(rename = "@step", deserialize_with = "deserialize_number")]
     pub step: f64,
 
     #[serde(flatten)]
@@ -65,7 +66,15 @@ impl std::fmt::Display for DefNumberVector {
 
 impl std::fmt::Display for DefNumberValue {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{} = {} | [{}, {}], Δ{}, {}", self.name, self.value, self.min, self.max, self.step, self.format)
+        write!(
+            f, "{} = {} | [{}, {}], Δ{}, {}",
+            self.name,
+            format_number(&self.format, self.value),
+            format_number(&self.format, self.min),
+            format_number(&self.format, self.max),
+            self.step,
+            self.format
+        )
     }
 }
 
@@ -75,8 +84,8 @@ pub struct SetNumberValue {
     #[serde(rename = "@name")]
     pub name: String,
 
-    #[serde(rename = "$text")]
-    pub value: String,
+    #[serde(rename = "$text", deserialize_with = "deserialize_number")]
+    pub value: f64,
 
     #[serde(flatten)]
     extra: std::collections::HashMap<String, String>,
@@ -123,3 +132,42 @@ impl std::fmt::Display for SetNumberValue {
         write!(f, "{} = {}", self.name, self.value)
     }
 }
+
+
+#[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Clone)]
+pub struct NewNumberValue {
+    #[serde(rename = "@name")]
+    pub name: String,
+
+    #[serde(rename = "$text", deserialize_with = "deserialize_number")]
+    pub value: f64,
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Clone)]
+pub struct NewNumberVector {
+    #[serde(rename = "@device")]
+    pub device: String,
+    #[serde(rename = "@name")]
+    pub name: String,
+    #[serde(rename = "@timestamp", default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<String>,
+
+    #[serde(rename = "oneNumber", default)]
+    pub numbers: Vec<NewNumberValue>,
+}
+
+impl std::fmt::Display for NewNumberVector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "➡ {}::{}(numbers)", self.device, self.name)?;
+        for v in &self.numbers {
+            writeln!(f, "\t{}", v)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for NewNumberValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} = {}", self.name, self.value)
+    }
+}
@@ -0,0 +1,393 @@
+use std::fmt::{Display, Formatter};
+
+/**
+The `format` attribute of a number element.
+
+INDI uses printf style formats (`%g`, `%5.2f`, `%.3e`, ...) plus its own `%<w>.<f>m`
+sexagesimal form where `f` selects the precision:
+
+| f | rendering     |
+|---|---------------|
+| 9 | `dd:mm:ss.ss` |
+| 8 | `dd:mm:ss.s`  |
+| 6 | `dd:mm:ss`    |
+| 5 | `dd:mm.m`     |
+| 3 | `dd:mm`       |
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub struct NumberFormat {
+    prefix: String,
+    spec: Spec,
+    suffix: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Spec {
+    Printf {
+        left: bool,
+        plus: bool,
+        space: bool,
+        zero: bool,
+        alternate: bool,
+        width: usize,
+        precision: Option<usize>,
+        conversion: char,
+    },
+    Sexagesimal {
+        width: usize,
+        fraction_base: u32,
+    },
+}
+
+impl NumberFormat {
+    pub fn parse(format: &str) -> Option<NumberFormat> {
+        let start = format.find('%')?;
+        let (prefix, rest) = format.split_at(start);
+        let mut chars = rest[1..].char_indices().peekable();
+
+        let (mut left, mut plus, mut space, mut zero, mut alternate) = (false, false, false, false, false);
+        while let Some((_, c)) = chars.peek() {
+            match c {
+                '-' => left = true,
+                '+' => plus = true,
+                ' ' => space = true,
+                '0' => zero = true,
+                '#' => alternate = true,
+                _ => break,
+            }
+            chars.next();
+        }
+
+        let mut width = 0;
+        while let Some((_, c)) = chars.peek().filter(|(_, c)| c.is_ascii_digit()) {
+            width = width * 10 + c.to_digit(10)? as usize;
+            chars.next();
+        }
+
+        let mut precision = None;
+        if let Some((_, '.')) = chars.peek() {
+            chars.next();
+            let mut p = 0;
+            while let Some((_, c)) = chars.peek().filter(|(_, c)| c.is_ascii_digit()) {
+                p = p * 10 + c.to_digit(10)? as usize;
+                chars.next();
+            }
+            precision = Some(p);
+        }
+
+        //length modifiers carry no meaning for a double
+        while let Some((_, 'l' | 'h' | 'L')) = chars.peek() {
+            chars.next();
+        }
+
+        let (idx, conversion) = chars.next()?;
+        let suffix = rest[1 + idx + conversion.len_utf8()..].to_string();
+
+        let spec = match conversion {
+            'm' => {
+                //libindi reads the flags as part of the width, "%010.6m" is a width of 10
+                let fraction_base = match precision {
+                    Some(9) => 360000,
+                    Some(8) => 36000,
+                    Some(6) => 3600,
+                    Some(5) => 600,
+                    _ => 60,
+                };
+                Spec::Sexagesimal {
+                    width: width.saturating_sub(precision.unwrap_or(0)),
+                    fraction_base,
+                }
+            },
+            'f' | 'F' | 'e' | 'E' | 'g' | 'G' | 'd' | 'i' | 'u' | 'x' | 'X' | 'o' => Spec::Printf {
+                left, plus, space, zero, alternate, width, precision, conversion
+            },
+            _ => return None,
+        };
+
+        Some(NumberFormat { prefix: prefix.to_string(), spec, suffix })
+    }
+
+    pub fn is_sexagesimal(&self) -> bool {
+        matches!(self.spec, Spec::Sexagesimal { .. })
+    }
+
+    pub fn format(&self, value: f64) -> String {
+        let body = match &self.spec {
+            Spec::Sexagesimal { width, fraction_base } => format_sexagesimal(value, *width, *fraction_base),
+            Spec::Printf { left, plus, space, zero, alternate, width, precision, conversion } => {
+                let digits = match conversion {
+                    'f' | 'F' => format!("{:.*}", precision.unwrap_or(6), value.abs()),
+                    'e' | 'E' => format_exponent(value.abs(), precision.unwrap_or(6)),
+                    'g' | 'G' => format_general(value.abs(), precision.unwrap_or(6), *alternate),
+                    'x' => format!("{:x}", value.abs().round() as u64),
+                    'X' => format!("{:X}", value.abs().round() as u64),
+                    'o' => format!("{:o}", value.abs().round() as u64),
+                    _ => format!("{}", value.abs().round() as u64),
+                };
+                let digits = if conversion.is_ascii_uppercase() { digits.to_uppercase() } else { digits };
+                let sign = if value.is_sign_negative() && value != 0.0 {
+                    "-"
+                } else if *plus {
+                    "+"
+                } else if *space {
+                    " "
+                } else {
+                    ""
+                };
+                pad(sign, &digits, *width, *left, *zero && value.is_finite())
+            }
+        };
+        format!("{}{}{}", self.prefix, body, self.suffix)
+    }
+}
+
+impl Display for NumberFormat {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.prefix)?;
+        match &self.spec {
+            Spec::Sexagesimal { width, fraction_base } => {
+                let precision = match fraction_base {
+                    360000 => 9,
+                    36000 => 8,
+                    3600 => 6,
+                    600 => 5,
+                    _ => 3,
+                };
+                write!(f, "%{}.{}m", width + precision, precision)?;
+            },
+            Spec::Printf { left, plus, space, zero, alternate, width, precision, conversion } => {
+                write!(f, "%")?;
+                for (set, flag) in [(left, '-'), (plus, '+'), (space, ' '), (zero, '0'), (alternate, '#')] {
+                    if *set {
+                        write!(f, "{}", flag)?;
+                    }
+                }
+                if *width > 0 {
+                    write!(f, "{}", width)?;
+                }
+                if let Some(precision) = precision {
+                    write!(f, ".{}", precision)?;
+                }
+                write!(f, "{}", conversion)?;
+            }
+        }
+        write!(f, "{}", self.suffix)
+    }
+}
+
+fn pad(sign: &str, digits: &str, width: usize, left: bool, zero: bool) -> String {
+    let len = sign.len() + digits.len();
+    if len >= width {
+        format!("{}{}", sign, digits)
+    } else if left {
+        format!("{}{}{}", sign, digits, " ".repeat(width - len))
+    } else if zero {
+        format!("{}{}{}", sign, "0".repeat(width - len), digits)
+    } else {
+        format!("{}{}{}", " ".repeat(width - len), sign, digits)
+    }
+}
+
+/// C style `%e`, the exponent always has a sign and at least two digits.
+fn format_exponent(value: f64, precision: usize) -> String {
+    let rust = format!("{:.*e}", precision, value);
+    match rust.split_once('e') {
+        Some((mantissa, exponent)) => {
+            let exponent: i32 = exponent.parse().unwrap_or(0);
+            format!("{}e{}{:02}", mantissa, if exponent < 0 { '-' } else { '+' }, exponent.abs())
+        },
+        None => rust,
+    }
+}
+
+/// C style `%g`, the shorter of `%f` and `%e` at `precision` significant digits.
+fn format_general(value: f64, precision: usize, alternate: bool) -> String {
+    if !value.is_finite() {
+        return format!("{}", value);
+    }
+    let precision = precision.max(1);
+    let exponent = if value == 0.0 {
+        0
+    } else {
+        let rounded = format!("{:.*e}", precision - 1, value);
+        rounded.split_once('e').and_then(|(_, e)| e.parse::<i32>().ok()).unwrap_or(0)
+    };
+
+    let formatted = if exponent < -4 || exponent >= precision as i32 {
+        format_exponent(value, precision - 1)
+    } else {
+        format!("{:.*}", (precision as i32 - 1 - exponent) as usize, value)
+    };
+
+    if alternate {
+        return formatted;
+    }
+    match formatted.split_once('e') {
+        Some((mantissa, exponent)) => format!("{}e{}", strip_zeros(mantissa), exponent),
+        None => strip_zeros(&formatted).to_string(),
+    }
+}
+
+fn strip_zeros(s: &str) -> &str {
+    if s.contains('.') {
+        s.trim_end_matches('0').trim_end_matches('.')
+    } else {
+        s
+    }
+}
+
+fn format_sexagesimal(value: f64, width: usize, fraction_base: u32) -> String {
+    let negative = value < 0.0;
+    let n = (value.abs() * fraction_base as f64 + 0.5) as u64;
+    let base = fraction_base as u64;
+    let degrees = n / base;
+    let fraction = n % base;
+
+    let whole = if negative {
+        format!("{:>width$}", format!("-{}", degrees), width = width)
+    } else {
+        format!("{:>width$}", degrees, width = width)
+    };
+
+    let rest = match fraction_base {
+        600 => format!(":{:02}.{}", fraction / 10, fraction % 10),
+        3600 => format!(":{:02}:{:02}", fraction / 60, fraction % 60),
+        36000 => {
+            let seconds = fraction % 600;
+            format!(":{:02}:{:02}.{}", fraction / 600, seconds / 10, seconds % 10)
+        },
+        360000 => {
+            let seconds = fraction % 6000;
+            format!(":{:02}:{:02}.{:02}", fraction / 6000, seconds / 100, seconds % 100)
+        },
+        _ => format!(":{:02}", fraction),
+    };
+    format!("{}{}", whole, rest)
+}
+
+/**
+Parses a number as sent by a driver or typed by a user.
+
+Accepts plain decimals as well as sexagesimal `d:m:s`, `d m s` or `d;m;s` with any
+number of components, each of which may carry a fraction.
+*/
+pub fn parse_number(text: &str) -> Option<f64> {
+    let text = text.trim();
+    if text.is_empty() {
+        return None;
+    }
+    if let Ok(value) = text.parse::<f64>() {
+        return Some(value);
+    }
+
+    let (negative, unsigned) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text.strip_prefix('+').unwrap_or(text)),
+    };
+
+    let mut value = 0.0;
+    let mut scale = 1.0;
+    let mut parts = 0;
+    for part in unsigned.split(|c: char| c == ':' || c == ';' || c.is_whitespace()).filter(|p| !p.is_empty()) {
+        let part: f64 = part.parse().ok()?;
+        if part < 0.0 || parts >= 3 {
+            return None;
+        }
+        value += part / scale;
+        scale *= 60.0;
+        parts += 1;
+    }
+    if parts == 0 {
+        return None;
+    }
+    Some(if negative { -value } else { value })
+}
+
+pub fn format_number(format: &str, value: f64) -> String {
+    match NumberFormat::parse(format) {
+        Some(format) => format.format(value),
+        None => value.to_string(),
+    }
+}
+
+pub(crate) fn deserialize_number<'de, D>(deserializer: D) -> Result<f64, D::Error> where D: serde::Deserializer<'de> {
+    let text = <String as serde::Deserialize>::deserialize(deserializer)?;
+    parse_number(&text).ok_or_else(|| serde::de::Error::custom(format!("invalid number {:?}", text)))
+}
+
+#[cfg(test)]
+mod tests {
+    use quick_xml::DeError;
+    use crate::indi::number::{DefNumberVector, SetNumberVector};
+    use crate::indi::number_format::{format_number, parse_number, NumberFormat};
+
+    #[test]
+    fn it_formats_printf() {
+        assert_eq!(format_number("%g", 0.0001), "0.0001");
+        assert_eq!(format_number("%g", 0.00001), "1e-05");
+        assert_eq!(format_number("%g", 1500000.0), "1.5e+06");
+        assert_eq!(format_number("%g", 20.0), "20");
+        assert_eq!(format_number("%5.2f", -9.5), "-9.50");
+        assert_eq!(format_number("%6.2f", 1.23456), "  1.23");
+        assert_eq!(format_number("%-6.1f|", 1.23456), "1.2   |");
+        assert_eq!(format_number("%06.1f", -1.23456), "-001.2");
+        assert_eq!(format_number("%.3e", 12345.0), "1.234e+04");
+        assert_eq!(format_number("%4.0f", 1.0), "   1");
+        assert_eq!(format_number("%d", 7.0), "7");
+        assert_eq!(format_number("%.2f C", 20.0), "20.00 C");
+        assert_eq!(format_number("nonsense", 1.5), "1.5");
+    }
+
+    #[test]
+    fn it_formats_sexagesimal() {
+        assert_eq!(format_number("%010.6m", 12.5), "  12:30:00");
+        assert_eq!(format_number("%010.6m", -0.5), "  -0:30:00");
+        assert_eq!(format_number("%9.6m", 89.999999), " 90:00:00");
+        assert_eq!(format_number("%11.8m", 5.0 + 6.0 / 60.0 + 7.26 / 3600.0), "  5:06:07.3");
+        assert_eq!(format_number("%12.9m", 5.0 + 6.0 / 60.0 + 7.25 / 3600.0), "  5:06:07.25");
+        assert_eq!(format_number("%8.5m", 1.0 + 1.5 / 60.0), "  1:01.5");
+        assert_eq!(format_number("%6.3m", -1.5), " -1:30");
+        assert!(NumberFormat::parse("%010.6m").unwrap().is_sexagesimal());
+        assert_eq!(NumberFormat::parse("%010.6m").unwrap().to_string(), "%10.6m");
+        assert_eq!(NumberFormat::parse("%-6.1f").unwrap().to_string(), "%-6.1f");
+    }
+
+    #[test]
+    fn it_parses_numbers() {
+        assert_eq!(parse_number(" 1.5 "), Some(1.5));
+        assert_eq!(parse_number("-1e3"), Some(-1000.0));
+        assert_eq!(parse_number("  12:30:00"), Some(12.5));
+        assert_eq!(parse_number("-0:30:00"), Some(-0.5));
+        assert_eq!(parse_number("12 30 36"), Some(12.51));
+        assert_eq!(parse_number("1;01.5"), Some(1.025));
+        assert_eq!(parse_number(""), None);
+        assert_eq!(parse_number("12:ab"), None);
+    }
+
+    #[test]
+    fn it_parses_sexagesimal_vectors() -> Result<(), DeError> {
+        let def = quick_xml::de::from_str::<DefNumberVector>(r#"
+            <defNumberVector device="Telescope Simulator" name="EQUATORIAL_EOD_COORD" label="Eq. Coordinates" group="Main Control" state="Idle" perm="rw" timeout="60" timestamp="2023-01-12T20:51:39">
+                <defNumber name="RA" label="RA (hh:mm:ss)" format="%010.6m" min="0" max="24" step="0">
+                    23:55:52
+                </defNumber>
+                <defNumber name="DEC" label="DEC (dd:mm:ss)" format="%010.6m" min="-90" max="90" step="0">
+                    -89:52:00
+                </defNumber>
+            </defNumberVector>
+        "#)?;
+        assert!((def.numbers[0].value - (23.0 + 55.0 / 60.0 + 52.0 / 3600.0)).abs() < 1e-9);
+        assert!((def.numbers[1].value + (89.0 + 52.0 / 60.0)).abs() < 1e-9);
+        assert_eq!(format_number(&def.numbers[0].format, def.numbers[0].value), "  23:55:52");
+
+        let set = quick_xml::de::from_str::<SetNumberVector>(r#"
+            <setNumberVector device="Telescope Simulator" name="EQUATORIAL_EOD_COORD" state="Busy" timeout="60" timestamp="2023-01-12T20:51:40">
+                <oneNumber name="RA">12:30:00</oneNumber>
+                <oneNumber name="DEC">4.5</oneNumber>
+            </setNumberVector>
+        "#)?;
+        assert_eq!(set.numbers[0].value, 12.5);
+        assert_eq!(set.numbers[1].value, 4.5);
+        Ok(())
+    }
+}
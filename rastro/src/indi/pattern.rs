@@ -0,0 +1,119 @@
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+use crate::indi::registry::{DeviceRegistry, PropertyElement, PropertyVector};
+
+/**
+Addresses elements as `device.property.element`, like `indi_getprop`.
+
+Each part may use `*` and `?` wildcards, missing trailing parts match everything,
+so `CCD Simulator.*` is every element of every property of that device.
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PropertyPattern {
+    pub device: String,
+    pub property: String,
+    pub element: String,
+}
+
+impl PropertyPattern {
+    pub fn new(device: &str, property: &str, element: &str) -> PropertyPattern {
+        PropertyPattern { device: device.to_string(), property: property.to_string(), element: element.to_string() }
+    }
+
+    pub fn has_wildcards(&self) -> bool {
+        [&self.device, &self.property, &self.element].iter().any(|p| p.contains(['*', '?']))
+    }
+
+    pub fn matches_property(&self, device: &str, property: &str) -> bool {
+        glob_match(&self.device, device) && glob_match(&self.property, property)
+    }
+
+    pub fn matches(&self, device: &str, property: &str, element: &str) -> bool {
+        self.matches_property(device, property) && glob_match(&self.element, element)
+    }
+
+    /// Every matching element in `registry`, ordered by device, property and element definition.
+    pub fn find<'a>(&self, registry: &'a DeviceRegistry) -> Vec<(&'a PropertyVector, &'a PropertyElement)> {
+        registry
+            .devices()
+            .filter(|d| glob_match(&self.device, &d.name))
+            .flat_map(|d| d.properties.values())
+            .filter(|p| glob_match(&self.property, &p.name))
+            .flat_map(|p| p.elements.iter().map(move |e| (p, e)))
+            .filter(|(_, e)| glob_match(&self.element, &e.name))
+            .collect()
+    }
+}
+
+impl FromStr for PropertyPattern {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() {
+            return Err("expected device.property.element".to_string());
+        }
+        let mut parts = s.splitn(3, '.');
+        let mut next = || parts.next().filter(|p| !p.is_empty()).unwrap_or("*");
+        Ok(PropertyPattern::new(next(), next(), next()))
+    }
+}
+
+impl Display for PropertyPattern {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}.{}", self.device, self.property, self.element)
+    }
+}
+
+/// Shell style matching of `*` (any run of characters) and `?` (a single character).
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            },
+            Some(c) if *c == '?' || *c == text[t] => {
+                p += 1;
+                t += 1;
+            },
+            _ => match backtrack {
+                //let the last `*` swallow one more character
+                Some((star, matched)) => {
+                    backtrack = Some((star, matched + 1));
+                    p = star + 1;
+                    t = matched + 1;
+                },
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::indi::pattern::{glob_match, PropertyPattern};
+
+    #[test]
+    fn it_matches_wildcards() {
+        assert!(glob_match("*", ""));
+        assert!(glob_match("CCD*", "CCD Simulator"));
+        assert!(glob_match("*_VALUE", "CCD_TEMPERATURE_VALUE"));
+        assert!(glob_match("CCD?", "CCD1"));
+        assert!(glob_match("*a*b", "xaxxab"));
+        assert!(!glob_match("CCD?", "CCD12"));
+        assert!(!glob_match("Telescope*", "CCD Simulator"));
+
+        let pattern: PropertyPattern = "CCD Simulator.CCD_TEMPERATURE".parse().unwrap();
+        assert_eq!(pattern, PropertyPattern::new("CCD Simulator", "CCD_TEMPERATURE", "*"));
+        assert!(pattern.has_wildcards());
+        assert!(pattern.matches("CCD Simulator", "CCD_TEMPERATURE", "CCD_TEMPERATURE_VALUE"));
+        assert!(!"a.b.c".parse::<PropertyPattern>().unwrap().has_wildcards());
+    }
+}
@@ -0,0 +1,79 @@
+use std::time::Duration;
+
+/**
+How `IndiClient` behaves when the server goes away.
+
+Delays grow from `initial_delay` by `multiplier` per failed attempt, capped at `max_delay`.
+After a reconnect, vectors the server has not defined again once it has been quiet for `resync_quiet` are dropped.
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReconnectPolicy {
+    pub enabled: bool,
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+    pub multiplier: f64,
+    /// `None` keeps trying forever.
+    pub max_attempts: Option<usize>,
+    /// How long the server may go without defining a property before the resync counts as answered.
+    pub resync_quiet: Duration,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        ReconnectPolicy {
+            enabled: true,
+            initial_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+            multiplier: 2.0,
+            max_attempts: None,
+            resync_quiet: Duration::from_secs(2),
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    pub fn never() -> ReconnectPolicy {
+        ReconnectPolicy { enabled: false, ..ReconnectPolicy::default() }
+    }
+
+    /// The delay before `attempt` (counting from 0), or `None` once we should give up.
+    pub fn delay(&self, attempt: usize) -> Option<Duration> {
+        if !self.enabled || self.max_attempts.map(|max| attempt >= max).unwrap_or(false) {
+            return None;
+        }
+        let factor = self.multiplier.max(1.0).powi(attempt.min(i32::MAX as usize) as i32);
+        let delay = self.initial_delay.as_secs_f64() * factor;
+        Some(Duration::from_secs_f64(delay.min(self.max_delay.as_secs_f64())))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    Connected,
+    /// Lost the server, `attempt` reconnects have failed so far.
+    Reconnecting { attempt: usize },
+    /// Gave up, or reconnecting is disabled.
+    Closed,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use crate::indi::reconnect::ReconnectPolicy;
+
+    #[test]
+    fn it_backs_off() {
+        let policy = ReconnectPolicy {
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(5),
+            max_attempts: Some(4),
+            ..ReconnectPolicy::default()
+        };
+        assert_eq!(policy.delay(0), Some(Duration::from_secs(1)));
+        assert_eq!(policy.delay(1), Some(Duration::from_secs(2)));
+        assert_eq!(policy.delay(2), Some(Duration::from_secs(4)));
+        assert_eq!(policy.delay(3), Some(Duration::from_secs(5)));
+        assert_eq!(policy.delay(4), None);
+        assert_eq!(ReconnectPolicy::never().delay(0), None);
+    }
+}
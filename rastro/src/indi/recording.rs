@@ -0,0 +1,251 @@
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, ReadBuf};
+use tokio::sync::{mpsc, oneshot};
+
+use crate::indi::codec::IndiXmlFramer;
+use crate::indi::error::{IndiError, IndiResult};
+use crate::indi::{IncomingMsg, OutgoingMsg};
+
+/// Which way a recorded message went, seen from the client.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    Received,
+    Sent,
+}
+
+/// One line of a session file.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Record {
+    /// Seconds since the UNIX epoch.
+    pub time: f64,
+    pub direction: Direction,
+    /// The element as it went over the wire.
+    pub xml: String,
+}
+
+impl Record {
+    /// Parses a received message, e.g. to check the parsers against real driver traffic.
+    pub fn incoming(&self) -> IndiResult<IncomingMsg> {
+        IncomingMsg::parse(&self.xml)
+    }
+
+    pub fn outgoing(&self) -> IndiResult<OutgoingMsg> {
+        OutgoingMsg::parse(&self.xml)
+    }
+}
+
+/**
+Appends every message of a connection to a session file, one JSON `Record` per line.
+
+Received bytes are framed into elements the same way the connection does, so a record is one
+whole message however the socket split it. BLOB payloads are left out, their elements keep the
+`size`, `len` and `format` they were sent with. Records are written by a blocking task, failing to
+write is logged and does not break the connection.
+*/
+pub struct Recorder {
+    path: PathBuf,
+    jobs: mpsc::UnboundedSender<Job>,
+    received: Mutex<(IndiXmlFramer, Vec<u8>)>,
+}
+
+enum Job {
+    Write(Record),
+    Flush(oneshot::Sender<()>),
+}
+
+impl Recorder {
+    /// Records to the end of `path`, so a reconnecting client keeps one file.
+    pub fn open(path: &Path) -> IndiResult<Recorder> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let (jobs, queued) = mpsc::unbounded_channel();
+        let written = path.to_path_buf();
+        tokio::task::spawn_blocking(move || write_records(&written, file, queued));
+        Ok(Recorder { path: path.to_path_buf(), jobs, received: Mutex::new((IndiXmlFramer::streaming_blobs(), Vec::new())) })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn sent(&self, xml: &str) {
+        self.write(Direction::Sent, xml);
+    }
+
+    /// Takes the next bytes read from the server, records the messages they complete.
+    pub fn received(&self, bytes: &[u8]) {
+        let mut received = self.received.lock().unwrap();
+        let (framer, buffer) = &mut *received;
+        buffer.extend_from_slice(bytes);
+        loop {
+            if framer.in_blob_text() {
+                let text = framer.scanned();
+                match buffer[text..].iter().position(|b| *b == b'<') {
+                    Some(end) => {
+                        buffer.drain(text..text + end);
+                        framer.leave_blob_text();
+                    },
+                    None => {
+                        buffer.truncate(text);
+                        break;
+                    },
+                }
+            }
+            match framer.next_frame(buffer) {
+                Some(range) => {
+                    self.write(Direction::Received, &String::from_utf8_lossy(&buffer[range.clone()]));
+                    buffer.drain(..range.end);
+                },
+                None if framer.in_blob_text() => continue,
+                None => break,
+            }
+        }
+        let discardable = framer.discardable();
+        if discardable > 0 {
+            buffer.drain(..discardable);
+            framer.discard(discardable);
+        }
+    }
+
+    /// Waits until what was recorded so far is in the file.
+    pub async fn flush(&self) {
+        let (done, flushed) = oneshot::channel();
+        if self.jobs.send(Job::Flush(done)).is_ok() {
+            let _ = flushed.await;
+        }
+    }
+
+    fn write(&self, direction: Direction, xml: &str) {
+        let time = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs_f64()).unwrap_or(0.0);
+        let record = Record { time, direction, xml: xml.to_string() };
+        if self.jobs.send(Job::Write(record)).is_err() {
+            log::warn!("could not record to {}, its writer is gone", self.path.display());
+        }
+    }
+}
+
+/// Writes queued records until the `Recorder` is dropped, runs on a blocking thread.
+fn write_records(path: &Path, mut file: File, mut jobs: mpsc::UnboundedReceiver<Job>) {
+    while let Some(job) = jobs.blocking_recv() {
+        let record = match job {
+            Job::Write(record) => record,
+            Job::Flush(done) => {
+                let _ = done.send(());
+                continue;
+            },
+        };
+        let result = serde_json::to_string(&record)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+            .and_then(|mut line| {
+                line.push('\n');
+                file.write_all(line.as_bytes())
+            });
+        if let Err(e) = result {
+            log::warn!("could not record to {}: {}", path.display(), e);
+        }
+    }
+}
+
+/// The records of the session file at `path`, without a last line cut short.
+pub fn read(path: &Path) -> IndiResult<Vec<Record>> {
+    read_json_lines(path)
+}
+
+/// One value per line of the file at `path`, tolerating a last line cut short by a crash.
+pub(crate) fn read_json_lines<T: DeserializeOwned>(path: &Path) -> IndiResult<Vec<T>> {
+    let lines: Vec<String> = BufReader::new(File::open(path)?).lines().collect::<Result<_, _>>()?;
+    let mut values = Vec::with_capacity(lines.len());
+    for (i, line) in lines.iter().enumerate().filter(|(_, l)| !l.trim().is_empty()) {
+        match serde_json::from_str(line) {
+            Ok(value) => values.push(value),
+            Err(e) if i + 1 == lines.len() => log::warn!("ignoring the incomplete last line of {}: {}", path.display(), e),
+            Err(e) => return Err(IndiError::InvalidData(format!("{} line {}: {}", path.display(), i + 1, e))),
+        }
+    }
+    Ok(values)
+}
+
+/// A reader that hands what it reads to a `Recorder` too.
+pub(crate) struct Tap<R> {
+    reader: R,
+    recorder: Option<Arc<Recorder>>,
+}
+
+impl<R> Tap<R> {
+    pub(crate) fn new(reader: R, recorder: Option<Arc<Recorder>>) -> Tap<R> {
+        Tap { reader, recorder }
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for Tap<R> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<std::io::Result<()>> {
+        let before = buf.filled().len();
+        let result = Pin::new(&mut self.reader).poll_read(cx, buf);
+        if let (Poll::Ready(Ok(())), Some(recorder)) = (&result, &self.recorder) {
+            recorder.received(&buf.filled()[before..]);
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use std::io::Write;
+    use crate::indi::recording::{read, Direction, Recorder};
+    use crate::indi::IncomingMsg;
+
+    #[tokio::test]
+    async fn it_records_whole_messages() -> Result<(), Box<dyn Error>> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("night.session");
+        let recorder = Recorder::open(&path)?;
+        recorder.sent(r#"<getProperties version="1.7"/>"#);
+        //the socket splits messages anywhere
+        recorder.received(b"\n<delProperty device=\"CCD Simulator\" name=\"CCD_TEMP");
+        recorder.received(b"ERATURE\"/>\n<message device=\"CCD Simulator\" message=\"a > b\"/><setSwitchVector");
+        recorder.flush().await;
+        drop(recorder);
+        std::fs::OpenOptions::new().append(true).open(&path)?.write_all(br#"{"time":1"#)?;
+
+        let records = read(&path)?;
+        assert_eq!(records.iter().map(|r| r.direction).collect::<Vec<_>>(), [Direction::Sent, Direction::Received, Direction::Received]);
+        assert!(records.windows(2).all(|w| w[0].time <= w[1].time));
+        assert_eq!(records[1].xml, r#"<delProperty device="CCD Simulator" name="CCD_TEMPERATURE"/>"#);
+        assert!(matches!(records[2].incoming()?, IncomingMsg::Message(_)));
+        assert!(records[0].outgoing().is_ok());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_leaves_blob_payloads_out() -> Result<(), Box<dyn Error>> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("night.session");
+        let recorder = Recorder::open(&path)?;
+        let payload = base64::encode(vec![7u8; 30000]);
+        recorder.received(br#"<setBLOBVector device="CCD Simulator" name="CCD1" state="Ok" timeout="60" timestamp="2023-02-11T07:16:57"><oneBLOB name="CCD1" size="30000" format=".fits" len="30000">"#);
+        for chunk in payload.as_bytes().chunks(4096) {
+            recorder.received(chunk);
+        }
+        recorder.received(b"</oneBLOB></setBLOBVector><message message=\"exposure done\"/>");
+        recorder.flush().await;
+
+        let records = read(&path)?;
+        assert_eq!(records.len(), 2);
+        assert!(records[0].xml.ends_with(r#"len="30000"></oneBLOB></setBLOBVector>"#));
+        match records[0].incoming()? {
+            IncomingMsg::SetBlobVector(v) => assert_eq!((v.blobs[0].size, v.blobs[0].value.as_str()), (30000, "")),
+            msg => panic!("unexpected {}", msg),
+        }
+        assert!(matches!(records[1].incoming()?, IncomingMsg::Message(_)));
+        Ok(())
+    }
+}
@@ -0,0 +1,610 @@
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+
+use super::blob_stream::StoredBlob;
+use super::common::{IndiPermission, IndiState};
+use super::number_format::format_number;
+use super::switch::{IndiSwitch, IndiSwitchOptions};
+use super::IncomingMsg;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PropertyValue {
+    Number {
+        value: f64,
+        format: String,
+        min: f64,
+        max: f64,
+        step: f64,
+    },
+    Switch(IndiSwitch),
+    Text(String),
+    Light(IndiState),
+    Blob {
+        format: String,
+        size: usize,
+        value: String,
+        /// Where the payload went when it was streamed instead of kept in `value`.
+        stored: Option<StoredBlob>,
+    },
+}
+
+impl Display for PropertyValue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PropertyValue::Number { value, format, .. } => write!(f, "{}", format_number(format, *value)),
+            PropertyValue::Switch(value) => write!(f, "{:?}", value),
+            PropertyValue::Text(value) => write!(f, "{}", value),
+            PropertyValue::Light(value) => write!(f, "{:?}", value),
+            PropertyValue::Blob { format, size, .. } => write!(f, "{} bytes ({})", size, format),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PropertyElement {
+    pub name: String,
+    pub label: String,
+    pub value: PropertyValue,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PropertyKind {
+    Number,
+    Switch,
+    Text,
+    Light,
+    Blob,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PropertyVector {
+    pub device: String,
+    pub name: String,
+    pub kind: PropertyKind,
+    pub label: String,
+    pub group: String,
+    pub state: IndiState,
+    /// Lights are read only and carry no `perm` attribute.
+    pub perm: Option<IndiPermission>,
+    /// Only switch vectors have a rule.
+    pub rule: Option<IndiSwitchOptions>,
+    pub timeout: f64,
+    pub timestamp: String,
+    /// Elements in the order the driver defined them.
+    pub elements: Vec<PropertyElement>,
+    /// Set while the connection is down, cleared once the driver defines or updates it again.
+    pub stale: bool,
+}
+
+impl PropertyValue {
+    pub fn number(value: f64, format: &str, min: f64, max: f64, step: f64) -> PropertyValue {
+        PropertyValue::Number { value, format: format.to_string(), min, max, step }
+    }
+
+    /// An empty BLOB, drivers fill it with `PropertyVector::set_blob`.
+    pub fn blob() -> PropertyValue {
+        PropertyValue::Blob { format: String::new(), size: 0, value: String::new(), stored: None }
+    }
+}
+
+impl PropertyVector {
+    /**
+    An `Idle` vector without elements, for drivers to define.
+
+    Lights are read only, the other kinds start out `rw` and switches `OneOfMany`.
+    */
+    pub fn new(device: &str, name: &str, kind: PropertyKind, label: &str, group: &str) -> PropertyVector {
+        PropertyVector {
+            device: device.to_string(),
+            name: name.to_string(),
+            kind,
+            label: label.to_string(),
+            group: group.to_string(),
+            state: IndiState::Idle,
+            perm: if kind == PropertyKind::Light { None } else { Some(IndiPermission::RW) },
+            rule: if kind == PropertyKind::Switch { Some(IndiSwitchOptions::OneOfMany) } else { None },
+            timeout: 60.0,
+            timestamp: String::new(),
+            elements: Vec::new(),
+            stale: false,
+        }
+    }
+
+    pub fn with_perm(mut self, perm: IndiPermission) -> PropertyVector {
+        self.perm = Some(perm);
+        self
+    }
+
+    pub fn with_rule(mut self, rule: IndiSwitchOptions) -> PropertyVector {
+        self.rule = Some(rule);
+        self
+    }
+
+    pub fn with_element(mut self, name: &str, label: &str, value: PropertyValue) -> PropertyVector {
+        self.elements.push(PropertyElement { name: name.to_string(), label: label.to_string(), value });
+        self
+    }
+
+    pub fn element(&self, name: &str) -> Option<&PropertyElement> {
+        self.elements.iter().find(|e| e.name == name)
+    }
+
+    pub fn element_mut(&mut self, name: &str) -> Option<&mut PropertyElement> {
+        self.elements.iter_mut().find(|e| e.name == name)
+    }
+
+    /// Sets the number `element`, returns false if there is none.
+    pub fn set_number(&mut self, element: &str, to: f64) -> bool {
+        match self.element_mut(element) {
+            Some(PropertyElement { value: PropertyValue::Number { value, .. }, .. }) => {
+                *value = to;
+                true
+            },
+            _ => false,
+        }
+    }
+
+    /**
+    Sets the switch `element`, returns false if there is none.
+
+    Turning a switch on turns the others off, unless the rule is `AnyOfMany`.
+    */
+    pub fn set_switch(&mut self, element: &str, to: IndiSwitch) -> bool {
+        if self.element(element).is_none() {
+            return false;
+        }
+        let exclusive = to == IndiSwitch::On && self.rule != Some(IndiSwitchOptions::AnyOfMany);
+        for e in &mut self.elements {
+            if let PropertyValue::Switch(value) = &mut e.value {
+                if e.name == element {
+                    *value = to;
+                } else if exclusive {
+                    *value = IndiSwitch::Off;
+                }
+            }
+        }
+        true
+    }
+
+    /// Sets the text `element`, returns false if there is none.
+    pub fn set_text(&mut self, element: &str, to: &str) -> bool {
+        match self.element_mut(element) {
+            Some(PropertyElement { value: PropertyValue::Text(value), .. }) => {
+                *value = to.to_string();
+                true
+            },
+            _ => false,
+        }
+    }
+
+    /// Sets the light `element`, returns false if there is none.
+    pub fn set_light(&mut self, element: &str, to: IndiState) -> bool {
+        match self.element_mut(element) {
+            Some(PropertyElement { value: PropertyValue::Light(value), .. }) => {
+                *value = to;
+                true
+            },
+            _ => false,
+        }
+    }
+
+    /// Sets the BLOB `element` to `data`, `format` is like `.fits`. Returns false if there is none.
+    pub fn set_blob(&mut self, element: &str, format: &str, data: &[u8]) -> bool {
+        match self.element_mut(element) {
+            Some(PropertyElement { value: PropertyValue::Blob { format: f, size, value, stored }, .. }) => {
+                *f = format.to_string();
+                *size = data.len();
+                *value = base64::encode(data);
+                *stored = None;
+                true
+            },
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Device {
+    pub name: String,
+    pub properties: BTreeMap<String, PropertyVector>,
+}
+
+/**
+Current state of every device and property vector seen on a connection,
+kept up to date by feeding it the `IncomingMsg`s as they arrive.
+*/
+#[derive(Debug, Default)]
+pub struct DeviceRegistry {
+    devices: BTreeMap<String, Device>,
+}
+
+impl DeviceRegistry {
+    pub fn new() -> DeviceRegistry {
+        DeviceRegistry::default()
+    }
+
+    pub fn devices(&self) -> impl Iterator<Item = &Device> {
+        self.devices.values()
+    }
+
+    pub fn device(&self, device: &str) -> Option<&Device> {
+        self.devices.get(device)
+    }
+
+    pub fn property(&self, device: &str, property: &str) -> Option<&PropertyVector> {
+        self.devices.get(device)?.properties.get(property)
+    }
+
+    pub fn element(&self, device: &str, property: &str, element: &str) -> Option<&PropertyElement> {
+        self.property(device, property)?.element(element)
+    }
+
+    pub fn value(&self, device: &str, property: &str, element: &str) -> Option<&PropertyValue> {
+        self.element(device, property, element).map(|e| &e.value)
+    }
+
+    pub fn number(&self, device: &str, property: &str, element: &str) -> Option<f64> {
+        match self.value(device, property, element)? {
+            PropertyValue::Number { value, .. } => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn switch(&self, device: &str, property: &str, element: &str) -> Option<IndiSwitch> {
+        match self.value(device, property, element)? {
+            PropertyValue::Switch(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn text(&self, device: &str, property: &str, element: &str) -> Option<&str> {
+        match self.value(device, property, element)? {
+            PropertyValue::Text(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn light(&self, device: &str, property: &str, element: &str) -> Option<IndiState> {
+        match self.value(device, property, element)? {
+            PropertyValue::Light(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    /// Applies a def/set/del message, returns true if the registry changed.
+    pub fn apply(&mut self, msg: &IncomingMsg) -> bool {
+        match msg {
+            IncomingMsg::DefNumberVector(v) => self.define(PropertyVector {
+                device: v.device.clone(),
+                name: v.name.clone(),
+                kind: PropertyKind::Number,
+                label: v.label.clone(),
+                group: v.group.clone(),
+                state: v.state,
+                perm: Some(v.perm),
+                rule: None,
+                timeout: v.timeout,
+                timestamp: v.timestamp.clone(),
+                elements: v.numbers.iter().map(|n| PropertyElement {
+                    name: n.name.clone(),
+                    label: n.label.clone(),
+                    value: PropertyValue::Number {
+                        value: n.value,
+                        format: n.format.clone(),
+                        min: n.min,
+                        max: n.max,
+                        step: n.step,
+                    },
+                }).collect(),
+                stale: false,
+            }),
+
+            IncomingMsg::DefSwitchVector(v) => self.define(PropertyVector {
+                device: v.device.clone(),
+                name: v.name.clone(),
+                kind: PropertyKind::Switch,
+                label: v.label.clone(),
+                group: v.group.clone(),
+                state: v.state,
+                perm: Some(v.perm),
+                rule: Some(v.rule),
+                timeout: v.timeout,
+                timestamp: v.timestamp.clone(),
+                elements: v.switches.iter().map(|s| PropertyElement {
+                    name: s.name.clone(),
+                    label: s.label.clone(),
+                    value: PropertyValue::Switch(s.value),
+                }).collect(),
+                stale: false,
+            }),
+
+            IncomingMsg::DefTextVector(v) => self.define(PropertyVector {
+                device: v.device.clone(),
+                name: v.name.clone(),
+                kind: PropertyKind::Text,
+                label: v.label.clone(),
+                group: v.group.clone(),
+                state: v.state,
+                perm: Some(v.perm),
+                rule: None,
+                timeout: v.timeout,
+                timestamp: v.timestamp.clone(),
+                elements: v.texts.iter().map(|t| PropertyElement {
+                    name: t.name.clone(),
+                    label: t.label.clone(),
+                    value: PropertyValue::Text(t.value.clone()),
+                }).collect(),
+                stale: false,
+            }),
+
+            IncomingMsg::DefLightVector(v) => self.define(PropertyVector {
+                device: v.device.clone(),
+                name: v.name.clone(),
+                kind: PropertyKind::Light,
+                label: v.label.clone(),
+                group: v.group.clone(),
+                state: v.state,
+                perm: None,
+                rule: None,
+                timeout: 0.0,
+                timestamp: v.timestamp.clone(),
+                elements: v.lights.iter().map(|l| PropertyElement {
+                    name: l.name.clone(),
+                    label: l.label.clone(),
+                    value: PropertyValue::Light(l.value),
+                }).collect(),
+                stale: false,
+            }),
+
+            IncomingMsg::DefBlobVector(v) => self.define(PropertyVector {
+                device: v.device.clone(),
+                name: v.name.clone(),
+                kind: PropertyKind::Blob,
+                label: v.label.clone(),
+                group: v.group.clone(),
+                state: v.state,
+                perm: Some(v.perm),
+                rule: None,
+                timeout: v.timeout,
+                timestamp: v.timestamp.clone(),
+                elements: v.blobs.iter().map(|b| PropertyElement {
+                    name: b.name.clone(),
+                    label: b.label.clone(),
+                    value: PropertyValue::Blob { format: String::new(), size: 0, value: String::new(), stored: None },
+                }).collect(),
+                stale: false,
+            }),
+
+            IncomingMsg::SetNumberVector(v) => {
+                self.update(&v.device, &v.name, v.state, v.timeout, &v.timestamp, |p| {
+                    for n in &v.numbers {
+                        match p.element_mut(&n.name) {
+                            Some(PropertyElement { value: PropertyValue::Number { value, .. }, .. }) => *value = n.value,
+                            _ => log::warn!("{}::{} has no number {}", v.device, v.name, n.name),
+                        }
+                    }
+                })
+            },
+
+            IncomingMsg::SetSwitchVector(v) => {
+                self.update(&v.device, &v.name, v.state, v.timeout, &v.timestamp, |p| {
+                    for s in &v.switches {
+                        match p.element_mut(&s.name) {
+                            Some(PropertyElement { value: PropertyValue::Switch(value), .. }) => *value = s.value,
+                            _ => log::warn!("{}::{} has no switch {}", v.device, v.name, s.name),
+                        }
+                    }
+                })
+            },
+
+            IncomingMsg::SetTextVector(v) => {
+                self.update(&v.device, &v.name, v.state, v.timeout, &v.timestamp, |p| {
+                    for t in &v.texts {
+                        match p.element_mut(&t.name) {
+                            Some(PropertyElement { value: PropertyValue::Text(value), .. }) => value.clone_from(&t.value),
+                            _ => log::warn!("{}::{} has no text {}", v.device, v.name, t.name),
+                        }
+                    }
+                })
+            },
+
+            IncomingMsg::SetLightVector(v) => {
+                self.update(&v.device, &v.name, v.state, 0.0, &v.timestamp, |p| {
+                    for l in &v.lights {
+                        if !p.set_light(&l.name, l.value) {
+                            log::warn!("{}::{} has no light {}", v.device, v.name, l.name);
+                        }
+                    }
+                })
+            },
+
+            IncomingMsg::SetBlobVector(v) => {
+                self.update(&v.device, &v.name, v.state, v.timeout, &v.timestamp, |p| {
+                    for b in &v.blobs {
+                        match p.element_mut(&b.name) {
+                            Some(PropertyElement { value: PropertyValue::Blob { format, size, value, stored }, .. }) => {
+                                format.clone_from(&b.format);
+                                *size = b.size;
+                                value.clone_from(&b.value);
+                                stored.clone_from(&b.stored);
+                            },
+                            _ => log::warn!("{}::{} has no blob {}", v.device, v.name, b.name),
+                        }
+                    }
+                })
+            },
+
+            IncomingMsg::DelProperty(v) => match &v.name {
+                Some(name) => self.devices
+                    .get_mut(&v.device)
+                    .and_then(|d| d.properties.remove(name))
+                    .is_some(),
+                None => self.devices.remove(&v.device).is_some(),
+            },
+
+            IncomingMsg::Message(_) | IncomingMsg::Unparsed(_) => false,
+        }
+    }
+
+    /// Marks everything as stale, e.g. because the connection to the server was lost.
+    pub fn mark_stale(&mut self) {
+        for device in self.devices.values_mut() {
+            for property in device.properties.values_mut() {
+                property.stale = true;
+            }
+        }
+    }
+
+    /// Removes the vectors still stale, and devices left without any, returning how many vectors went.
+    pub fn drop_stale(&mut self) -> usize {
+        let mut dropped = 0;
+        for device in self.devices.values_mut() {
+            let before = device.properties.len();
+            device.properties.retain(|_, property| !property.stale);
+            dropped += before - device.properties.len();
+        }
+        self.devices.retain(|_, device| !device.properties.is_empty());
+        dropped
+    }
+
+    /// Adds `vector`, replacing one of the same name, as a def message would.
+    pub fn define(&mut self, vector: PropertyVector) -> bool {
+        let device = self.devices.entry(vector.device.clone()).or_insert_with(|| Device {
+            name: vector.device.clone(),
+            properties: BTreeMap::new(),
+        });
+        device.properties.insert(vector.name.clone(), vector);
+        true
+    }
+
+    fn update<F>(&mut self, device: &str, property: &str, state: IndiState, timeout: f64, timestamp: &str, apply: F) -> bool
+        where F: FnOnce(&mut PropertyVector) {
+        match self.devices.get_mut(device).and_then(|d| d.properties.get_mut(property)) {
+            None => {
+                log::warn!("set for undefined property {}::{}", device, property);
+                false
+            },
+            Some(p) => {
+                p.stale = false;
+                p.state = state;
+                p.timeout = timeout;
+                p.timestamp = timestamp.to_string();
+                apply(p);
+                true
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use quick_xml::DeError;
+    use crate::indi::common::IndiState;
+    use crate::indi::IncomingMsg;
+    use crate::indi::registry::DeviceRegistry;
+    use crate::indi::switch::IndiSwitch;
+
+    fn apply(registry: &mut DeviceRegistry, xml: &str) -> Result<bool, DeError> {
+        let msg = quick_xml::de::from_str::<IncomingMsg>(xml)?;
+        Ok(registry.apply(&msg))
+    }
+
+    #[test]
+    fn it_tracks_def_set_and_del() -> Result<(), DeError> {
+        let mut registry = DeviceRegistry::new();
+
+        apply(&mut registry, r#"
+            <defNumberVector device="CCD Simulator" name="CCD_TEMPERATURE" label="Temperature (C)" group="Main Control" state="Idle" perm="rw" timeout="60" timestamp="2023-01-12T20:51:39">
+                <defNumber name="CCD_TEMPERATURE_VALUE" label="Temperature (C)" format="%5.2f" min="-50" max="50" step="0">20</defNumber>
+            </defNumberVector>
+        "#)?;
+        apply(&mut registry, r#"
+            <defSwitchVector device="CCD Simulator" name="CONNECTION" label="Connection" group="Main Control" state="Idle" perm="rw" rule="OneOfMany" timeout="60" timestamp="2023-01-12T20:51:39">
+                <defSwitch name="CONNECT" label="Connect">Off</defSwitch>
+                <defSwitch name="DISCONNECT" label="Disconnect">On</defSwitch>
+            </defSwitchVector>
+        "#)?;
+        assert_eq!(registry.number("CCD Simulator", "CCD_TEMPERATURE", "CCD_TEMPERATURE_VALUE"), Some(20.0));
+        assert_eq!(registry.switch("CCD Simulator", "CONNECTION", "CONNECT"), Some(IndiSwitch::Off));
+
+        assert!(apply(&mut registry, r#"
+            <setNumberVector device="CCD Simulator" name="CCD_TEMPERATURE" state="Busy" timeout="30" timestamp="2023-01-12T20:52:00">
+                <oneNumber name="CCD_TEMPERATURE_VALUE">-9.5</oneNumber>
+            </setNumberVector>
+        "#)?);
+        let p = registry.property("CCD Simulator", "CCD_TEMPERATURE").unwrap();
+        assert_eq!(p.state, IndiState::Busy);
+        assert_eq!(p.timeout, 30.0);
+        assert_eq!(p.group, "Main Control");
+        assert_eq!(registry.number("CCD Simulator", "CCD_TEMPERATURE", "CCD_TEMPERATURE_VALUE"), Some(-9.5));
+
+        assert!(!apply(&mut registry, r#"
+            <setNumberVector device="CCD Simulator" name="UNKNOWN" state="Ok" timeout="60" timestamp="2023-01-12T20:52:00">
+                <oneNumber name="VALUE">1</oneNumber>
+            </setNumberVector>
+        "#)?);
+
+        assert!(apply(&mut registry, r#"<delProperty device="CCD Simulator" name="CCD_TEMPERATURE"></delProperty>"#)?);
+        assert!(registry.property("CCD Simulator", "CCD_TEMPERATURE").is_none());
+        assert!(registry.property("CCD Simulator", "CONNECTION").is_some());
+
+        assert!(apply(&mut registry, r#"<delProperty device="CCD Simulator"></delProperty>"#)?);
+        assert!(registry.device("CCD Simulator").is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn it_drops_what_stays_stale() -> Result<(), DeError> {
+        let mut registry = DeviceRegistry::new();
+        let temperature = r#"
+            <defNumberVector device="CCD Simulator" name="CCD_TEMPERATURE" label="Temperature (C)" group="Main Control" state="Idle" perm="rw" timeout="60" timestamp="2023-01-12T20:51:39">
+                <defNumber name="CCD_TEMPERATURE_VALUE" label="Temperature (C)" format="%5.2f" min="-50" max="50" step="0">20</defNumber>
+            </defNumberVector>
+        "#;
+        apply(&mut registry, temperature)?;
+        apply(&mut registry, r#"
+            <defSwitchVector device="CCD Simulator" name="CONNECTION" label="Connection" group="Main Control" state="Idle" perm="rw" rule="OneOfMany" timeout="60" timestamp="2023-01-12T20:51:39">
+                <defSwitch name="CONNECT" label="Connect">Off</defSwitch>
+                <defSwitch name="DISCONNECT" label="Disconnect">On</defSwitch>
+            </defSwitchVector>
+        "#)?;
+        apply(&mut registry, r#"
+            <defTextVector device="Guide Simulator" name="DRIVER_INFO" label="Driver Info" group="General Info" state="Idle" perm="ro" timeout="60" timestamp="2023-01-12T20:51:39">
+                <defText name="DRIVER_NAME" label="Name">Guide Simulator</defText>
+            </defTextVector>
+        "#)?;
+
+        registry.mark_stale();
+        apply(&mut registry, temperature)?;
+        assert!(registry.property("CCD Simulator", "CONNECTION").unwrap().stale);
+
+        assert_eq!(registry.drop_stale(), 2);
+        assert!(!registry.property("CCD Simulator", "CCD_TEMPERATURE").unwrap().stale);
+        assert!(registry.property("CCD Simulator", "CONNECTION").is_none());
+        assert!(registry.device("Guide Simulator").is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn it_updates_lights() -> Result<(), DeError> {
+        let mut registry = DeviceRegistry::new();
+        apply(&mut registry, r#"
+            <defLightVector device="Telescope Simulator" name="TELESCOPE_STATUS" label="Status" group="Main Control" state="Idle" timestamp="2023-01-12T20:51:39">
+                <defLight name="SCOPE_IDLE" label="Idle">Ok</defLight>
+                <defLight name="SCOPE_SLEWING" label="Slewing">Idle</defLight>
+            </defLightVector>
+        "#)?;
+
+        assert!(apply(&mut registry, r#"
+            <setLightVector device="Telescope Simulator" name="TELESCOPE_STATUS" state="Busy" timestamp="2023-01-12T20:52:00">
+                <oneLight name="SCOPE_IDLE">Idle</oneLight>
+                <oneLight name="SCOPE_SLEWING">Busy</oneLight>
+            </setLightVector>
+        "#)?);
+        let p = registry.property("Telescope Simulator", "TELESCOPE_STATUS").unwrap();
+        assert_eq!((p.state, p.timestamp.as_str()), (IndiState::Busy, "2023-01-12T20:52:00"));
+        assert_eq!(registry.light("Telescope Simulator", "TELESCOPE_STATUS", "SCOPE_IDLE"), Some(IndiState::Idle));
+        assert_eq!(registry.light("Telescope Simulator", "TELESCOPE_STATUS", "SCOPE_SLEWING"), Some(IndiState::Busy));
+        Ok(())
+    }
+}
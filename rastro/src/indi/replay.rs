@@ -0,0 +1,197 @@
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use futures::StreamExt;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::time::Instant;
+use tokio_util::codec::FramedRead;
+
+use crate::indi::async_connection::serve_clients;
+use crate::indi::codec::OutgoingMsgCodec;
+use crate::indi::error::{IndiError, IndiResult};
+use crate::indi::recording::{self, Direction, Record};
+use crate::indi::OutgoingMsg;
+
+/// How long a checked replay waits for the client to send what it sent when recorded.
+const SENT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/**
+Plays a recorded session back to clients, as if it were the server that was recorded.
+
+Received messages go out byte for byte, spaced like they were recorded divided by `speed`.
+With `check`, the replay also waits for the client to send each recorded request and fails on
+anything else, the clock restarting once it did, so a slow client does not get a burst.
+*/
+#[derive(Clone)]
+pub struct Replay {
+    records: Arc<Vec<Record>>,
+    speed: f64,
+    check: bool,
+}
+
+impl Replay {
+    pub fn new(records: Vec<Record>) -> Replay {
+        Replay { records: Arc::new(records), speed: 1.0, check: false }
+    }
+
+    pub fn load(path: &Path) -> IndiResult<Replay> {
+        Ok(Replay::new(recording::read(path)?))
+    }
+
+    /// How many times faster than recorded, `f64::INFINITY` plays without pauses.
+    pub fn with_speed(mut self, speed: f64) -> Replay {
+        self.speed = speed;
+        self
+    }
+
+    pub fn with_check(mut self, check: bool) -> Replay {
+        self.check = check;
+        self
+    }
+
+    pub fn records(&self) -> &[Record] {
+        &self.records
+    }
+
+    /// Replays to every client that connects to `listener`, each from the start.
+    pub async fn serve_tcp(&self, listener: TcpListener) -> IndiResult<()> {
+        serve_clients(listener, |reader, writer| {
+            let replay = self.clone();
+            async move { replay.serve(reader, writer).await }
+        }).await
+    }
+
+    /// Replays the session to one client, returns once it is gone.
+    pub async fn serve<R, W>(&self, reader: R, mut writer: W) -> IndiResult<()>
+        where R: AsyncRead + Unpin, W: AsyncWrite + Unpin {
+        let mut requests = FramedRead::new(reader, OutgoingMsgCodec::new());
+        let mut anchor: Option<(Instant, f64)> = None;
+        for (i, record) in self.records.iter().enumerate() {
+            let (started, time) = *anchor.get_or_insert((Instant::now(), record.time));
+            match record.direction {
+                Direction::Received => {
+                    let due = started + Duration::from_secs_f64(((record.time - time) / self.speed).max(0.0));
+                    loop {
+                        tokio::select! {
+                            _ = tokio::time::sleep_until(due) => break,
+                            //when checking, requests wait for the record they are compared with
+                            request = requests.next(), if !self.check => match request {
+                                None => return Ok(()),
+                                Some(request) => log::debug!("client sent {}", request?),
+                            },
+                        }
+                    }
+                    writer.write_all(record.xml.as_bytes()).await?;
+                    writer.flush().await?;
+                },
+                Direction::Sent if self.check => {
+                    let expected = record.outgoing()?;
+                    let request = tokio::time::timeout(SENT_TIMEOUT, requests.next()).await
+                        .map_err(|_| IndiError::Timeout(format!("record {}, the client did not send {}", i + 1, expected)))?;
+                    match request {
+                        None => return Err(IndiError::ChannelClosed),
+                        Some(request) => {
+                            let request = request?;
+                            if !same(&request, &expected) {
+                                return Err(IndiError::InvalidRequest(format!("record {}, expected {} but the client sent {}", i + 1, expected, request)));
+                            }
+                        },
+                    }
+                    anchor = Some((Instant::now(), record.time));
+                },
+                Direction::Sent => {},
+            }
+        }
+
+        //the session is over, the client stays connected as long as it likes
+        while let Some(request) = requests.next().await {
+            log::debug!("client sent {}", request?);
+        }
+        Ok(())
+    }
+}
+
+/// Whether two requests ask for the same, clients stamp them with the time they were sent.
+fn same(a: &OutgoingMsg, b: &OutgoingMsg) -> bool {
+    let unstamped = |msg: &OutgoingMsg| {
+        let mut msg = msg.clone();
+        match &mut msg {
+            OutgoingMsg::NewSwitchVector(v) => v.timestamp = None,
+            OutgoingMsg::NewTextVector(v) => v.timestamp = None,
+            OutgoingMsg::NewNumberVector(v) => v.timestamp = None,
+            OutgoingMsg::NewBlobVector(v) => v.timestamp = None,
+            OutgoingMsg::GetProperties(_) | OutgoingMsg::EnableBLOB(_) => {},
+        }
+        msg
+    };
+    unstamped(a) == unstamped(b)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use std::time::Duration;
+    use crate::config_file::ConnectionSpec;
+    use crate::devices::focuser::Focuser;
+    use crate::indi::error::IndiError;
+    use crate::indi::recording::{read, Direction};
+    use crate::indi::replay::Replay;
+    use crate::indi::session::Session;
+    use crate::simulator::{spawn, FocuserSimulator};
+
+    const FOCUSER: &str = "Focuser Simulator";
+
+    async fn focus(session: &Session, position: u32) -> Result<(), IndiError> {
+        session.get_properties(None, None).await?;
+        session.wait_for_property(FOCUSER, "FOCUS_BACKLASH_STEPS", Duration::from_secs(5)).await?;
+        Focuser::new(session, FOCUSER).move_to(position).await
+    }
+
+    #[tokio::test]
+    async fn it_records_and_replays_a_session() -> Result<(), Box<dyn Error + Send + Sync>> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("focus.session");
+        let mut simulator = FocuserSimulator::new(FOCUSER);
+        simulator.speed = 5000.0;
+        let (_, mut spec) = spawn("focuser", simulator).await?;
+        spec.record = Some(path.clone());
+        {
+            let session = Session::connect(&[spec]).await?;
+            focus(&session, 51000).await?;
+            session.flush_recordings().await;
+        }
+
+        let records = read(&path)?;
+        assert!(records.iter().any(|r| r.direction == Direction::Sent));
+        for record in records.iter().filter(|r| r.direction == Direction::Received) {
+            record.incoming()?;
+        }
+
+        //the replay answers the same requests the way the simulator did
+        let replay = Replay::load(&path)?.with_speed(f64::INFINITY).with_check(true);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let spec = ConnectionSpec::new("replay", "127.0.0.1", listener.local_addr()?.port() as usize);
+        let served = tokio::spawn(async move {
+            let (socket, _) = listener.accept().await?;
+            let (reader, writer) = socket.into_split();
+            replay.serve(reader, writer).await?;
+            let (socket, _) = listener.accept().await?;
+            let (reader, writer) = socket.into_split();
+            replay.serve(reader, writer).await
+        });
+        {
+            let session = Session::connect(std::slice::from_ref(&spec)).await?;
+            focus(&session, 51000).await?;
+            assert_eq!(Focuser::new(&session, FOCUSER).position()?, 51000);
+        }
+
+        //and tells a client asking for something else
+        let session = Session::connect(&[spec]).await?;
+        let client = tokio::spawn(async move { focus(&session, 40000).await });
+        let served = tokio::time::timeout(Duration::from_secs(5), served).await??;
+        assert!(matches!(served, Err(IndiError::InvalidRequest(reason)) if reason.contains("ABS_FOCUS_POSITION")));
+        client.abort();
+        Ok(())
+    }
+}
@@ -0,0 +1,392 @@
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+use crate::config_file::ConnectionSpec;
+use crate::indi::client::IndiClient;
+use crate::indi::common::IndiState;
+use crate::indi::condition::{Condition, Value};
+use crate::indi::enable_blob::EnableBLOBValue;
+use crate::indi::error::{IndiError, IndiResult};
+use crate::indi::reconnect::ConnectionState;
+use crate::indi::registry::PropertyVector;
+use crate::indi::switch::IndiSwitch;
+use crate::indi::IncomingMsg;
+
+/// Something that happened on one of the connections of a `Session`, tagged with its name.
+#[derive(Debug, Clone)]
+pub enum SessionEvent {
+    Message { connection: String, msg: Arc<IncomingMsg> },
+    State { connection: String, state: ConnectionState },
+}
+
+impl SessionEvent {
+    pub fn connection(&self) -> &str {
+        match self {
+            SessionEvent::Message { connection, .. } => connection,
+            SessionEvent::State { connection, .. } => connection,
+        }
+    }
+}
+
+/**
+Every configured INDI server at once, presented as one observatory.
+
+Each connection is an `IndiClient` of its own, their events are merged into one stream tagged with
+the connection name. Devices are looked up across all connections, so a mount on one host and a
+camera on another are addressed the same way. Should two servers have a device of the same name,
+the connection listed first wins.
+*/
+pub struct Session {
+    clients: Vec<(String, IndiClient)>,
+    /// What the clients were connected with, empty for `Session::new`.
+    specs: Vec<ConnectionSpec>,
+    events: broadcast::Sender<SessionEvent>,
+    forwarders: Vec<JoinHandle<()>>,
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        for forwarder in &self.forwarders {
+            forwarder.abort();
+        }
+    }
+}
+
+impl Session {
+    /**
+    Connects to all `specs` concurrently.
+
+    Each connection uses the reconnect policy and BLOB storage of its spec, `start` applies the rest.
+    A server that cannot be reached yet is retried by that policy, connecting only fails for one
+    whose spec does not reconnect.
+    */
+    pub async fn connect(specs: &[ConnectionSpec]) -> IndiResult<Session> {
+        let pending: Vec<_> = specs
+            .iter()
+            .map(|spec| {
+                let spec = spec.clone();
+                tokio::spawn(async move {
+                    IndiClient::connect_with_storage(&spec, spec.reconnect_policy(), spec.blob_storage()).await
+                })
+            })
+            .collect();
+
+        let mut clients = Vec::with_capacity(specs.len());
+        for (spec, client) in specs.iter().zip(pending) {
+            let client = match client.await.map_err(|_| IndiError::ChannelClosed)? {
+                Ok(client) => client,
+                Err(e) if spec.reconnect_policy().enabled => {
+                    log::warn!("[{}] {}, retrying in the background", spec.name, e);
+                    IndiClient::disconnected(spec, spec.reconnect_policy(), spec.blob_storage())
+                },
+                Err(e) => return Err(e),
+            };
+            clients.push((spec.name.clone(), client));
+        }
+        let mut session = Session::new(clients);
+        session.specs = specs.to_vec();
+        Ok(session)
+    }
+
+    /// A session over already connected clients, named by the first element of each pair.
+    pub fn new(clients: Vec<(String, IndiClient)>) -> Session {
+        let (events, _) = broadcast::channel(1024);
+        let forwarders = clients
+            .iter()
+            .map(|(name, client)| tokio::spawn(forward(name.clone(), client, events.clone())))
+            .collect();
+        Session { clients, specs: Vec::new(), events, forwarders }
+    }
+
+    /**
+    Sends each connection's `blob_mode` and asks for all properties, then turns on the
+    `CONNECTION` switch of the `auto_connect` devices as they show up.
+
+    Devices that do not appear within `timeout` are logged and skipped.
+    */
+    pub async fn start(&self, timeout: Duration) -> IndiResult<()> {
+        for spec in &self.specs {
+            if let Some(client) = self.client(&spec.name) {
+                //both are remembered and sent once a connection that is down comes up
+                match client.enable_blob(spec.blob_mode, None, None).await.and(client.get_properties(None, None).await) {
+                    Ok(()) | Err(IndiError::NotConnected) => {},
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+
+        for spec in &self.specs {
+            let client = match self.client(&spec.name) {
+                Some(client) => client,
+                None => continue,
+            };
+            for device in &spec.auto_connect {
+                if let Err(e) = client.wait_for_property(device, "CONNECTION", timeout).await {
+                    log::warn!("[{}] not connecting {}: {}", spec.name, device, e);
+                    continue;
+                }
+                if client.registry().switch(device, "CONNECTION", "CONNECT") == Some(IndiSwitch::On) {
+                    continue;
+                }
+                match client.set_switch(device, "CONNECTION", &[("CONNECT", IndiSwitch::On)]).await? {
+                    IndiState::Ok => log::info!("[{}] connected {}", spec.name, device),
+                    state => log::warn!("[{}] connecting {} ended {:?}", spec.name, device, state),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn connections(&self) -> impl Iterator<Item = &str> {
+        self.clients.iter().map(|(name, _)| name.as_str())
+    }
+
+    pub fn client(&self, connection: &str) -> Option<&IndiClient> {
+        self.clients.iter().find(|(name, _)| name == connection).map(|(_, client)| client)
+    }
+
+    /// Events of every connection received after this call.
+    pub fn subscribe(&self) -> broadcast::Receiver<SessionEvent> {
+        self.events.subscribe()
+    }
+
+    /// `(connection, device)` for every known device, in connection order.
+    pub fn devices(&self) -> Vec<(String, String)> {
+        self.clients
+            .iter()
+            .flat_map(|(name, client)| {
+                client.registry().devices().map(|d| (name.clone(), d.name.clone())).collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    /// The connection serving `device`.
+    pub fn connection_for(&self, device: &str) -> Option<&str> {
+        self.clients
+            .iter()
+            .find(|(_, client)| client.registry().device(device).is_some())
+            .map(|(name, _)| name.as_str())
+    }
+
+    pub fn client_for(&self, device: &str) -> IndiResult<&IndiClient> {
+        self.clients
+            .iter()
+            .find(|(_, client)| client.registry().device(device).is_some())
+            .map(|(_, client)| client)
+            .ok_or_else(|| IndiError::InvalidRequest(format!("no connection has a device {}", device)))
+    }
+
+    pub fn property(&self, device: &str, property: &str) -> Option<PropertyVector> {
+        self.clients
+            .iter()
+            .find_map(|(_, client)| client.registry().property(device, property).cloned())
+    }
+
+    /// Waits until every recording connection wrote what it recorded so far.
+    pub async fn flush_recordings(&self) {
+        for (_, client) in &self.clients {
+            client.flush_recording().await;
+        }
+    }
+
+    /// Sent to every connection, a device only answers on the connection that has it.
+    pub async fn get_properties(&self, device: Option<&str>, name: Option<&str>) -> IndiResult<()> {
+        for (_, client) in &self.clients {
+            client.get_properties(device, name).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn enable_blob(&self, value: EnableBLOBValue, device: Option<&str>, name: Option<&str>) -> IndiResult<()> {
+        for (_, client) in &self.clients {
+            client.enable_blob(value, device, name).await?;
+        }
+        Ok(())
+    }
+
+    /// Waits until any connection has defined `device.property`.
+    pub async fn wait_for_property(&self, device: &str, property: &str, timeout: Duration) -> IndiResult<()> {
+        let mut events = self.subscribe();
+        let deadline = Instant::now() + timeout;
+        loop {
+            if self.property(device, property).is_some() {
+                return Ok(());
+            }
+            match tokio::time::timeout_at(deadline, events.recv()).await {
+                Err(_) => return Err(IndiError::Timeout(format!("{}::{} was never defined", device, property))),
+                Ok(Err(broadcast::error::RecvError::Closed)) => return Err(IndiError::ChannelClosed),
+                Ok(_) => {}
+            }
+        }
+    }
+
+    /// `condition` over the devices of every connection, `None` while an element it needs is not defined.
+    pub fn evaluate(&self, condition: &Condition) -> IndiResult<Option<bool>> {
+        condition.evaluate(|target| {
+            self.clients.iter().find_map(|(_, client)| {
+                Value::of(client.registry().property(&target.device, &target.property)?, &target.element)
+            })
+        })
+    }
+
+    /// Waits until `condition` holds, checking it again after every event of any connection.
+    pub async fn wait_until(&self, condition: &Condition, timeout: Duration) -> IndiResult<()> {
+        let mut events = self.subscribe();
+        let deadline = Instant::now() + timeout;
+        loop {
+            if self.evaluate(condition)? == Some(true) {
+                return Ok(());
+            }
+            match tokio::time::timeout_at(deadline, events.recv()).await {
+                Err(_) => return Err(IndiError::Timeout(format!("{} did not become true", condition))),
+                Ok(Err(broadcast::error::RecvError::Closed)) => return Err(IndiError::ChannelClosed),
+                Ok(_) => {}
+            }
+        }
+    }
+
+    pub async fn set_number(&self, device: &str, property: &str, values: &[(&str, f64)]) -> IndiResult<IndiState> {
+        self.client_for(device)?.set_number(device, property, values).await
+    }
+
+    pub async fn set_switch(&self, device: &str, property: &str, values: &[(&str, IndiSwitch)]) -> IndiResult<IndiState> {
+        self.client_for(device)?.set_switch(device, property, values).await
+    }
+
+    pub async fn set_text(&self, device: &str, property: &str, values: &[(&str, &str)]) -> IndiResult<IndiState> {
+        self.client_for(device)?.set_text(device, property, values).await
+    }
+}
+
+/// Subscribes right away, so nothing after `Session::new` is missed, and forwards in the returned future.
+fn forward(connection: String, client: &IndiClient, events: broadcast::Sender<SessionEvent>) -> impl std::future::Future<Output = ()> {
+    let mut updates = client.subscribe();
+    let mut state = client.connection_state();
+    async move {
+        loop {
+            tokio::select! {
+                msg = updates.recv() => match msg {
+                    Ok(msg) => {
+                        let _ = events.send(SessionEvent::Message { connection: connection.clone(), msg });
+                    },
+                    Err(broadcast::error::RecvError::Lagged(n)) => log::warn!("{} dropped {} messages", connection, n),
+                    Err(broadcast::error::RecvError::Closed) => break,
+                },
+                changed = state.changed() => {
+                    if changed.is_err() {
+                        break;
+                    }
+                    let state = *state.borrow();
+                    let _ = events.send(SessionEvent::State { connection: connection.clone(), state });
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use crate::config_file::ConnectionSpec;
+    use crate::indi::common::IndiState;
+    use crate::indi::reconnect::ConnectionState;
+    use crate::indi::session::{Session, SessionEvent};
+
+    /// Defines one number on `device`, then answers a single `newNumberVector` with `Ok`.
+    async fn server(name: &str, device: &'static str) -> Result<(ConnectionSpec, tokio::task::JoinHandle<String>), Box<dyn Error + Send + Sync>> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let mut spec = ConnectionSpec::new(name, "127.0.0.1", listener.local_addr()?.port() as usize);
+        spec.reconnect.enabled = Some(false);
+        let handle = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            socket.write_all(format!(r#"
+                <defNumberVector device="{}" name="VALUE" label="Value" group="Main Control" state="Idle" perm="rw" timeout="1" timestamp="2023-01-12T20:51:39">
+                    <defNumber name="VALUE" label="Value" format="%g" min="0" max="100" step="0">1</defNumber>
+                </defNumberVector>
+            "#, device).as_bytes()).await.unwrap();
+
+            let mut received = vec![0u8; 1024];
+            let n = socket.read(&mut received).await.unwrap();
+            socket.write_all(format!(r#"
+                <setNumberVector device="{}" name="VALUE" state="Ok" timeout="1" timestamp="2023-01-12T20:51:40">
+                    <oneNumber name="VALUE">2</oneNumber>
+                </setNumberVector>
+            "#, device).as_bytes()).await.unwrap();
+            //keep the connection up until the client is done
+            let _ = socket.read(&mut received[n..]).await;
+            String::from_utf8(received[..n].to_vec()).unwrap()
+        });
+        Ok((spec, handle))
+    }
+
+    #[tokio::test]
+    async fn it_starts_with_a_connection_down() -> Result<(), Box<dyn Error + Send + Sync>> {
+        let (mount_spec, mount) = server("mount-host", "Telescope Simulator").await?;
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let mut camera_spec = ConnectionSpec::new("camera-host", "127.0.0.1", listener.local_addr()?.port() as usize);
+        camera_spec.reconnect.initial_delay_ms = Some(10);
+        camera_spec.reconnect.max_delay_ms = Some(10);
+        drop(listener);
+
+        let session = Session::connect(&[mount_spec, camera_spec.clone()]).await?;
+        session.start(Duration::from_secs(1)).await?;
+        session.wait_for_property("Telescope Simulator", "VALUE", Duration::from_secs(5)).await?;
+        let camera = session.client("camera-host").unwrap();
+        assert!(matches!(*camera.connection_state().borrow(), ConnectionState::Reconnecting { .. }));
+
+        //the camera host comes up later and gets asked for its properties
+        let listener = TcpListener::bind(("127.0.0.1", camera_spec.port as u16)).await?;
+        let (mut socket, _) = tokio::time::timeout(Duration::from_secs(5), listener.accept()).await??;
+        let mut received = vec![0u8; 1024];
+        let mut n = 0;
+        while !String::from_utf8_lossy(&received[..n]).contains("getProperties") {
+            n += socket.read(&mut received[n..]).await?;
+        }
+        let mut state = camera.connection_state();
+        while *state.borrow() != ConnectionState::Connected {
+            tokio::time::timeout(Duration::from_secs(5), state.changed()).await??;
+        }
+        drop(session);
+        mount.abort();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_merges_devices_of_all_connections() -> Result<(), Box<dyn Error + Send + Sync>> {
+        let (mount_spec, mount) = server("mount-host", "Telescope Simulator").await?;
+        let (camera_spec, camera) = server("camera-host", "CCD Simulator").await?;
+
+        let session = Session::connect(&[mount_spec, camera_spec]).await?;
+        let mut events = session.subscribe();
+        session.wait_for_property("Telescope Simulator", "VALUE", Duration::from_secs(5)).await?;
+        session.wait_for_property("CCD Simulator", "VALUE", Duration::from_secs(5)).await?;
+
+        assert_eq!(session.connection_for("CCD Simulator"), Some("camera-host"));
+        assert_eq!(session.connection_for("Telescope Simulator"), Some("mount-host"));
+        assert!(session.devices().contains(&("camera-host".to_string(), "CCD Simulator".to_string())));
+
+        assert_eq!(session.set_number("CCD Simulator", "VALUE", &[("VALUE", 2.0)]).await?, IndiState::Ok);
+        assert!(session.set_number("Focuser Simulator", "VALUE", &[]).await.is_err());
+
+        tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                if let SessionEvent::Message { connection, .. } = events.recv().await.unwrap() {
+                    if connection == "camera-host" {
+                        break;
+                    }
+                }
+            }
+        }).await?;
+
+        drop(session);
+        assert!(camera.await?.contains(r#"<newNumberVector device="CCD Simulator" name="VALUE">"#));
+        assert_eq!(mount.await?, "");
+        Ok(())
+    }
+}
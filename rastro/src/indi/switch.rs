@@ -1,13 +1,13 @@
 use super::common::{IndiState, IndiPermission};
 
 
-#[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq)]
+#[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Eq, Clone, Copy)]
 pub enum IndiSwitchOptions {
     AnyOfMany, OneOfMany, AtMostOne
 }
 
 
-#[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq)]
+#[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Eq, Clone, Copy)]
 pub enum IndiSwitch {
     On,
     Off,
@@ -88,7 +88,7 @@ pub struct SetSwitchValue {
     pub name: String,
 
     #[serde(rename = "$text")]
-    pub value: String,
+    pub value: IndiSwitch,
 
     #[serde(flatten)]
     extra: std::collections::HashMap<String, String>,
@@ -132,6 +132,45 @@ impl std::fmt::Display for SetSwitchVector {
 
 impl std::fmt::Display for SetSwitchValue {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{} = {}", self.name, self.value)
+        write!(f, "{} = {:?}", self.name, self.value)
+    }
+}
+
+
+#[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Clone)]
+pub struct NewSwitchValue {
+    #[serde(rename = "@name")]
+    pub name: String,
+
+    #[serde(rename = "$text")]
+    pub value: IndiSwitch,
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Clone)]
+pub struct NewSwitchVector {
+    #[serde(rename = "@device")]
+    pub device: String,
+    #[serde(rename = "@name")]
+    pub name: String,
+    #[serde(rename = "@timestamp", default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<String>,
+
+    #[serde(rename = "oneSwitch", default)]
+    pub switches: Vec<NewSwitchValue>,
+}
+
+impl std::fmt::Display for NewSwitchVector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "➡ {}::{}(switch)", self.device, self.name)?;
+        for v in &self.switches {
+            writeln!(f, "\t{}", v)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for NewSwitchValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} = {:?}", self.name, self.value)
     }
 }
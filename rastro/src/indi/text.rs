@@ -9,7 +9,7 @@ pub struct DefTextValue {
     #[serde(rename = "@label")]
     pub label: String,
 
-    #[serde(alias = "$text")]
+    #[serde(alias = "$text", default)]
     pub value: String,
 
     #[serde(flatten)]
@@ -68,7 +68,7 @@ pub struct SetTextValue {
     #[serde(alias = "@name")]
     pub name: String,
 
-    #[serde(rename = "$text")]
+    #[serde(rename = "$text", default)]
     pub value: String,
 
     #[serde(flatten)]
@@ -117,3 +117,41 @@ impl std::fmt::Display for SetTextValue {
     }
 }
 
+
+#[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Clone)]
+pub struct NewTextValue {
+    #[serde(rename = "@name")]
+    pub name: String,
+
+    #[serde(rename = "$text", default)]
+    pub value: String,
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Clone)]
+pub struct NewTextVector {
+    #[serde(rename = "@device")]
+    pub device: String,
+    #[serde(rename = "@name")]
+    pub name: String,
+    #[serde(rename = "@timestamp", default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<String>,
+
+    #[serde(rename = "oneText", default)]
+    pub texts: Vec<NewTextValue>,
+}
+
+impl std::fmt::Display for NewTextVector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "➡ {}::{}(text)", self.device, self.name)?;
+        for v in &self.texts {
+            writeln!(f, "\t{}", v)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for NewTextValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} = {}", self.name, self.value)
+    }
+}
@@ -0,0 +1,7 @@
+pub mod indi;
+pub mod config_file;
+pub mod sequence;
+pub mod devices;
+pub mod driver;
+pub mod simulator;
+pub mod hub;
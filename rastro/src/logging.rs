@@ -0,0 +1,59 @@
+use std::sync::Mutex;
+use log::{Log, Metadata, Record};
+use tokio::sync::mpsc;
+
+/// Takes the log records while the dashboard owns the terminal.
+static PANE: Mutex<Option<mpsc::UnboundedSender<String>>> = Mutex::new(None);
+
+/// Logs like `pretty_env_logger` to stderr, or to the dashboard while it runs.
+struct Logger {
+    stderr: Box<dyn Log>,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.stderr.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        match PANE.lock().unwrap().as_ref() {
+            Some(pane) => {
+                let _ = pane.send(format!("{} {}: {}", record.level(), record.target(), record.args()));
+            },
+            None => self.stderr.log(record),
+        }
+    }
+
+    fn flush(&self) {
+        self.stderr.flush()
+    }
+}
+
+/// Filters by `RUST_LOG` like `pretty_env_logger::init`.
+pub fn init() {
+    let mut builder = pretty_env_logger::formatted_builder();
+    if let Ok(filters) = std::env::var("RUST_LOG") {
+        builder.parse_filters(&filters);
+    }
+    let stderr = builder.build();
+    log::set_max_level(stderr.filter());
+    log::set_boxed_logger(Box::new(Logger { stderr: Box::new(stderr) })).expect("the logger is set once");
+}
+
+/// Sends log records to the returned receiver instead of stderr, until the guard is dropped.
+pub fn to_pane() -> (PaneGuard, mpsc::UnboundedReceiver<String>) {
+    let (pane, records) = mpsc::unbounded_channel();
+    *PANE.lock().unwrap() = Some(pane);
+    (PaneGuard, records)
+}
+
+pub struct PaneGuard;
+
+impl Drop for PaneGuard {
+    fn drop(&mut self) {
+        *PANE.lock().unwrap() = None;
+    }
+}
@@ -1,13 +1,19 @@
 use std::error::Error;
+use std::time::Duration;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, Ordering};
-use crate::config_file::ConfigFile;
-use crate::indi::connection::{IndiConnection};
-use crate::indi::enable_blob::{EnableBLOB, EnableBLOBValue};
-use crate::indi::get_properties::GetProperties;
-use crate::indi::IncomingMsg;
-mod indi;
-mod config_file;
+use clap::Parser;
+use rastro::config_file::ConfigFile;
+use rastro::indi::session::Session;
+
+mod cli;
+mod dashboard;
+mod logging;
+
+use cli::{Cli, Command};
+
+/// How long `auto_connect` devices get to show up.
+const AUTO_CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
 
 struct App {
     quit: Arc<AtomicBool>
@@ -15,7 +21,7 @@ struct App {
 
 impl App {
     fn new() -> App {
-        pretty_env_logger::init();
+        logging::init();
         log::info!("starting");
         let quit = Arc::new(AtomicBool::new(false));
 
@@ -47,61 +53,44 @@ impl Drop for App {
 }
 
 
-//#[tokio::main(flavor = "multi_thread", worker_threads=8)]
-fn main() -> Result<(), Box<dyn Error>>{
+#[tokio::main(flavor = "multi_thread", worker_threads=8)]
+async fn main() -> Result<(), Box<dyn Error + Send + Sync>>{
 
+    let args = Cli::parse();
     let app = App::new();
 
-    let config = ConfigFile::load_default()?;
-    for connection_spec in &config.connections {
-
-        let mut conn_control = IndiConnection::connect(connection_spec)?;
-        let mut conn_blob = IndiConnection::connect(connection_spec)?;
-
-        conn_blob.send(&IncomingMsg::EnableBLOB(EnableBLOB {value: EnableBLOBValue::Only}))?;
-        conn_control.send(&IncomingMsg::EnableBLOB(EnableBLOB {value: EnableBLOBValue::None}))?;
-
-        conn_control.send(&IncomingMsg::GetProperties(GetProperties {version: "1.7".to_string()}))?;
-        conn_blob.send(&IncomingMsg::GetProperties(GetProperties {version: "1.7".to_string()}))?;
-
-
-        let mut counter = 0;
-        while !app.should_quit() {
-            let msg_control = conn_control.recv_or_none()?;
-            match msg_control {
-                None => {
-                    log::trace!("sleeping");
-                    std::thread::sleep(std::time::Duration::from_millis(100));
-                },
-                Some(_msg) => {
-                    log::trace!("getting messages");
-                    log::debug!("{}", _msg);
-                    while let Some(_msg) = conn_control.recv_or_none()? {
-                        log::debug!("{}", _msg);
-                    }
-                    log::trace!("got messages");
-                }
-            }
-
-            let msg_blob = conn_blob.recv_or_none()?;
-            match msg_blob {
-                None => {
-                    log::trace!("no blob");
-                },
-                Some(_msg) => {
-                    log::trace!("getting blob");
-                    log::debug!("{}", _msg);
-                    while let Some(_msg) = conn_blob.recv_or_none()? {
-                        log::debug!("{}", _msg);
-                    }
-                    log::trace!("got blobs");
-                }
-            }
-
-
-            counter = counter + 1;
-        }
+    //a replay serves, there is nothing to connect to
+    if let Some(Command::Replay(replay)) = &args.command {
+        return cli::replay(replay).await;
     }
 
-    Ok(())
+    let mut connections = match &args.server {
+        Some(server) => vec![cli::server_spec(server)?],
+        None => ConfigFile::load(args.config.as_deref())?.connections(args.profile.as_deref())?,
+    };
+    if let Some(record) = &args.record {
+        match connections.as_mut_slice() {
+            [connection] => connection.record = Some(record.clone()),
+            _ => return Err(format!("--record takes a single connection, there are {}", connections.len()).into()),
+        }
+    }
+    //read the plan before connecting, so mistakes in it show right away
+    let plan = match &args.command {
+        Some(Command::Sequence(sequence)) => Some(cli::plan(sequence)?),
+        _ => None,
+    };
+    if let Some(Command::Hub(hub)) = &args.command {
+        return cli::hub(&connections, hub).await;
+    }
+    let session = Session::connect(&connections).await?;
+
+    match args.command.unwrap_or(Command::Run) {
+        Command::Run => dashboard::run(&session, AUTO_CONNECT_TIMEOUT, || app.should_quit()).await,
+        Command::Get(get) => cli::get(&session, &get).await,
+        Command::Set(set) => cli::set(&session, &set).await,
+        Command::Watch(watch) => cli::watch(&session, &watch).await,
+        Command::Wait(wait) => cli::wait(&session, &wait).await,
+        Command::Sequence(sequence) => cli::sequence(&session, plan.unwrap(), &sequence, AUTO_CONNECT_TIMEOUT).await,
+        Command::Replay(_) | Command::Hub(_) => unreachable!(),
+    }
 }
@@ -0,0 +1,202 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{File, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+
+use crate::indi::error::{IndiError, IndiResult};
+use crate::indi::recording::read_json_lines;
+use crate::sequence::{Captured, Frame, Plan};
+
+/// What to do with a frame whose exposure was started but never saved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ResumeMode {
+    #[default]
+    Retake,
+    Skip,
+}
+
+/// One line of the journal.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Entry {
+    /// Always the first line, resuming with a different plan is refused.
+    Plan { plan: Plan },
+    Started { frame: Frame },
+    Completed {
+        frame: Frame,
+        path: PathBuf,
+        timestamp: String,
+        /// `device.property.element` to value, as the devices were when the frame arrived.
+        properties: BTreeMap<String, String>,
+    },
+    Skipped { frame: Frame },
+}
+
+impl Entry {
+    pub fn completed(captured: &Captured) -> Entry {
+        Entry::Completed {
+            frame: captured.frame,
+            path: captured.path.clone(),
+            timestamp: captured.timestamp.clone(),
+            properties: captured.properties.clone(),
+        }
+    }
+}
+
+/**
+Append-only record of a running `Plan`, one JSON object per line.
+
+Every entry is synced to disk before the sequencer moves on, so after a crash the journal tells
+which frames were saved and which exposure was in progress. A line cut short by the crash is
+ignored when the journal is opened again.
+*/
+pub struct Journal {
+    path: PathBuf,
+    file: File,
+    /// Indices of frames that were saved, and still exist, or skipped.
+    done: BTreeSet<usize>,
+    /// Indices of frames that were started but not finished.
+    partial: BTreeSet<usize>,
+}
+
+impl Journal {
+    /// Opens the journal at `path` to resume `plan`, or starts a new one if there is none.
+    pub fn open(path: &Path, plan: &Plan) -> IndiResult<Journal> {
+        let entries = match Journal::read(path) {
+            Ok(entries) => entries,
+            Err(IndiError::Io(e)) if e.kind() == ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e),
+        };
+        match entries.first() {
+            Some(Entry::Plan { plan: journaled }) if journaled == plan => {},
+            Some(Entry::Plan { .. }) => {
+                return Err(IndiError::InvalidRequest(format!("{} is the journal of a different plan", path.display())));
+            },
+            Some(_) => return Err(IndiError::InvalidData(format!("{} does not start with a plan", path.display()))),
+            None => {},
+        }
+
+        //drop a line cut short, so the next entry starts on a line of its own
+        if let Ok(bytes) = std::fs::read(path) {
+            let complete = bytes.iter().rposition(|b| *b == b'\n').map_or(0, |i| i + 1);
+            if complete < bytes.len() {
+                OpenOptions::new().write(true).open(path)?.set_len(complete as u64)?;
+            }
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let mut journal = Journal { path: path.to_path_buf(), file, done: BTreeSet::new(), partial: BTreeSet::new() };
+        for entry in &entries {
+            match entry {
+                Entry::Plan { .. } => {},
+                Entry::Started { frame } => {
+                    journal.partial.insert(frame.index);
+                },
+                Entry::Completed { frame, path, .. } => {
+                    journal.partial.remove(&frame.index);
+                    if path.exists() {
+                        journal.done.insert(frame.index);
+                    } else {
+                        log::warn!("{} is gone, taking frame {} again", path.display(), frame.index + 1);
+                    }
+                },
+                Entry::Skipped { frame } => {
+                    journal.partial.remove(&frame.index);
+                    journal.done.insert(frame.index);
+                },
+            }
+        }
+        if entries.is_empty() {
+            journal.record(&Entry::Plan { plan: plan.clone() })?;
+        }
+        Ok(journal)
+    }
+
+    /// Starts over, dropping whatever the journal at `path` recorded.
+    pub fn create(path: &Path, plan: &Plan) -> IndiResult<Journal> {
+        match std::fs::remove_file(path) {
+            Err(e) if e.kind() != ErrorKind::NotFound => return Err(e.into()),
+            _ => {},
+        }
+        Journal::open(path, plan)
+    }
+
+    /// The entries of the journal at `path`, without a last line cut short.
+    pub fn read(path: &Path) -> IndiResult<Vec<Entry>> {
+        read_json_lines(path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Saved, with the file still in place, or skipped.
+    pub fn is_done(&self, frame: &Frame) -> bool {
+        self.done.contains(&frame.index)
+    }
+
+    /// Started in an earlier run but never saved.
+    pub fn is_partial(&self, frame: &Frame) -> bool {
+        self.partial.contains(&frame.index)
+    }
+
+    pub fn done(&self) -> usize {
+        self.done.len()
+    }
+
+    pub fn record(&mut self, entry: &Entry) -> IndiResult<()> {
+        let mut line = serde_json::to_string(entry).map_err(|e| IndiError::InvalidData(e.to_string()))?;
+        line.push('\n');
+        self.file.write_all(line.as_bytes())?;
+        self.file.sync_data()?;
+        match entry {
+            Entry::Plan { .. } => {},
+            Entry::Started { frame } => {
+                self.partial.insert(frame.index);
+            },
+            Entry::Completed { frame, .. } | Entry::Skipped { frame } => {
+                self.partial.remove(&frame.index);
+                self.done.insert(frame.index);
+            },
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::error::Error;
+    use std::io::Write;
+    use crate::sequence::journal::{Entry, Journal};
+    use crate::sequence::Plan;
+
+    #[test]
+    fn it_resumes_from_the_journal() -> Result<(), Box<dyn Error>> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("m42.journal");
+        let plan = Plan::new("CCD Simulator", vec!["3x1s".parse()?]);
+        let frames = plan.frames();
+        let saved = dir.path().join("frame1.fits");
+        std::fs::write(&saved, b"SIMPLE")?;
+
+        let mut journal = Journal::open(&path, &plan)?;
+        journal.record(&Entry::Started { frame: frames[0] })?;
+        journal.record(&Entry::Completed { frame: frames[0], path: saved, timestamp: String::new(), properties: BTreeMap::new() })?;
+        journal.record(&Entry::Started { frame: frames[1] })?;
+        drop(journal);
+        //a crash in the middle of writing a line
+        std::fs::OpenOptions::new().append(true).open(&path)?.write_all(br#"{"event":"sta"#)?;
+
+        let mut journal = Journal::open(&path, &plan)?;
+        journal.record(&Entry::Skipped { frame: frames[2] })?;
+        let journal = Journal::open(&path, &plan)?;
+        assert!(journal.is_done(&frames[0]) && journal.is_done(&frames[2]));
+        assert!(!journal.is_done(&frames[1]) && journal.is_partial(&frames[1]));
+
+        let other = Plan::new("CCD Simulator", vec!["4x1s".parse()?]);
+        assert!(Journal::open(&path, &other).is_err());
+        assert_eq!(Journal::create(&path, &other)?.done(), 0);
+        Ok(())
+    }
+}
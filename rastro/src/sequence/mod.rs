@@ -0,0 +1,537 @@
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::fs::OpenOptions;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use serde::{Deserialize, Serialize};
+
+use crate::config_file::ConfigError;
+use crate::devices::camera::Camera;
+use crate::devices::FilterWheel;
+use crate::indi::blob::SetBlobVector;
+use crate::indi::blob_stream::file_name_part;
+use crate::indi::enable_blob::EnableBLOBValue;
+use crate::indi::error::{IndiError, IndiResult};
+use crate::indi::session::Session;
+
+pub mod journal;
+
+pub use crate::devices::camera::FrameType;
+use journal::{Entry, Journal, ResumeMode};
+
+/// Properties of the camera and filter wheel recorded with every frame.
+const SNAPSHOT: &[&str] = &[
+    "CCD_EXPOSURE", "CCD_FRAME_TYPE", "CCD_BINNING", "CCD_FRAME", "CCD_GAIN", "CCD_OFFSET", "CCD_CONTROLS",
+    "CCD_TEMPERATURE", "FILTER_SLOT",
+];
+
+/// Fields of the `directory` and `filename` templates.
+const FIELDS: &[&str] = &["target", "camera", "type", "filter", "exposure", "bin", "gain", "step", "frame", "index", "date", "time"];
+
+fn default_directory() -> String {
+    ".".to_string()
+}
+
+fn default_filename() -> String {
+    "{type}_{filter}_{exposure}s_{index:04}".to_string()
+}
+
+/**
+`count` exposures of `exposure` seconds with the same camera settings.
+
+Settings left out keep whatever the devices are set to. Written in a plan as a table, or on the
+command line as `20x300s, filter Ha, bin 1, gain 100`.
+*/
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Step {
+    pub count: usize,
+    pub exposure: f64,
+    #[serde(default)]
+    pub frame_type: FrameType,
+    /// Name from `FILTER_NAME` or slot number.
+    pub filter: Option<String>,
+    pub binning: Option<u32>,
+    pub gain: Option<f64>,
+    pub offset: Option<f64>,
+}
+
+impl FromStr for Step {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split(',').map(str::trim);
+        let head = parts.next().unwrap_or_default();
+        let (count, exposure) = head
+            .split_once(['x', 'X', '×'])
+            .ok_or_else(|| format!("{} is not <count>x<seconds>", head))?;
+        let count = count.trim().parse().map_err(|_| format!("{} is not a frame count", count))?;
+        let exposure = exposure.trim().trim_end_matches('s').trim();
+        let exposure = exposure.parse().map_err(|_| format!("{} is not an exposure in seconds", exposure))?;
+
+        let mut step = Step { count, exposure, frame_type: FrameType::Light, filter: None, binning: None, gain: None, offset: None };
+        for part in parts {
+            let (key, value) = part.split_once(' ').map(|(k, v)| (k, v.trim())).unwrap_or((part, ""));
+            let number = |value: &str| value.parse::<f64>().map_err(|_| format!("{} is not a number in {}", value, part));
+            match key.to_ascii_lowercase().as_str() {
+                "filter" if !value.is_empty() => step.filter = Some(value.to_string()),
+                "bin" | "binning" => step.binning = Some(value.parse().map_err(|_| format!("{} is not a binning", value))?),
+                "gain" => step.gain = Some(number(value)?),
+                "offset" => step.offset = Some(number(value)?),
+                "light" if value.is_empty() => step.frame_type = FrameType::Light,
+                "bias" if value.is_empty() => step.frame_type = FrameType::Bias,
+                "dark" if value.is_empty() => step.frame_type = FrameType::Dark,
+                "flat" if value.is_empty() => step.frame_type = FrameType::Flat,
+                _ => return Err(format!("unknown setting {}", part)),
+            }
+        }
+        Ok(step)
+    }
+}
+
+impl Display for Step {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}x{}s", self.count, self.exposure)?;
+        if self.frame_type != FrameType::Light {
+            write!(f, ", {}", format!("{:?}", self.frame_type).to_lowercase())?;
+        }
+        if let Some(filter) = &self.filter {
+            write!(f, ", filter {}", filter)?;
+        }
+        if let Some(binning) = self.binning {
+            write!(f, ", bin {}", binning)?;
+        }
+        if let Some(gain) = self.gain {
+            write!(f, ", gain {}", gain)?;
+        }
+        if let Some(offset) = self.offset {
+            write!(f, ", offset {}", offset)?;
+        }
+        Ok(())
+    }
+}
+
+/**
+An exposure plan, usually loaded from a TOML file:
+
+```toml
+camera = "CCD Simulator"
+filter_wheel = "Filter Simulator"
+target = "M42"
+directory = "~/frames/{target}/{date}"
+
+[[steps]]
+count = 20
+exposure = 300
+filter = "Ha"
+binning = 1
+gain = 100
+```
+
+`directory` and `filename` are templates over `target`, `camera`, `type`, `filter`, `exposure`,
+`bin`, `gain`, `step`, `frame` (within the step), `index` (within the plan), `date` and `time`
+(of the frame), numbers can be zero padded as in `{index:04}`. The extension follows the
+format of the BLOB.
+*/
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Plan {
+    pub camera: String,
+    pub filter_wheel: Option<String>,
+    #[serde(default)]
+    pub target: String,
+    #[serde(default = "default_directory")]
+    pub directory: String,
+    #[serde(default = "default_filename")]
+    pub filename: String,
+    pub steps: Vec<Step>,
+}
+
+impl Plan {
+    pub fn new(camera: &str, steps: Vec<Step>) -> Plan {
+        Plan {
+            camera: camera.to_string(),
+            filter_wheel: None,
+            target: String::new(),
+            directory: default_directory(),
+            filename: default_filename(),
+            steps,
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Plan, ConfigError> {
+        let text = std::fs::read_to_string(path).map_err(|source| ConfigError::Io { path: path.to_path_buf(), source })?;
+        let plan: Plan = toml::from_str(&text).map_err(|source| ConfigError::Parse { path: path.to_path_buf(), source })?;
+        plan.validate()?;
+        Ok(plan)
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let invalid = |what: String| Err(ConfigError::Invalid(format!("plan: {}", what)));
+        if self.camera.trim().is_empty() {
+            return invalid("camera is empty".to_string());
+        }
+        if self.steps.is_empty() {
+            return invalid("there are no steps".to_string());
+        }
+        for (i, step) in self.steps.iter().enumerate() {
+            if step.count == 0 {
+                return invalid(format!("step {} ({}) takes no frames", i + 1, step));
+            }
+            if step.exposure.is_nan() || step.exposure < 0.0 {
+                return invalid(format!("step {} ({}) has no valid exposure", i + 1, step));
+            }
+            if step.filter.is_some() && self.filter_wheel.is_none() {
+                return invalid(format!("step {} ({}) sets a filter but there is no filter_wheel", i + 1, step));
+            }
+        }
+        for template in [&self.directory, &self.filename] {
+            render(template, |name| FIELDS.contains(&name).then(String::new)).map_err(|e| ConfigError::Invalid(format!("plan: {}", e)))?;
+        }
+        Ok(())
+    }
+
+    /// Every frame of the plan in order.
+    pub fn frames(&self) -> Vec<Frame> {
+        self.steps
+            .iter()
+            .enumerate()
+            .flat_map(|(step, s)| (0..s.count).map(move |frame| (step, frame)))
+            .enumerate()
+            .map(|(index, (step, frame))| Frame { step, frame, index })
+            .collect()
+    }
+}
+
+/// Position of a frame in a plan, all counted from 0.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Frame {
+    pub step: usize,
+    pub frame: usize,
+    pub index: usize,
+}
+
+/// A frame that was taken and saved.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Captured {
+    pub frame: Frame,
+    pub path: PathBuf,
+    /// Of the `setBLOBVector` that carried the frame.
+    pub timestamp: String,
+    /// `device.property.element` to value, as the devices were when the frame arrived.
+    pub properties: BTreeMap<String, String>,
+}
+
+/**
+Replaces `{name}` and `{name:0N}` in `template` with what `field` returns for `name`.
+
+Values have path separators replaced, so they cannot leave the directory.
+*/
+pub fn render(template: &str, field: impl Fn(&str) -> Option<String>) -> Result<String, String> {
+    let mut out = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        let end = rest[start..].find('}').ok_or_else(|| format!("unclosed {{ in {}", template))? + start;
+        let spec = &rest[start + 1..end];
+        let (name, width) = match spec.split_once(':') {
+            Some((name, width)) => (name, Some(width.trim_start_matches('0').parse::<usize>().map_err(|_| format!("{{{}}} has a bad width", spec))?)),
+            None => (spec, None),
+        };
+        let value = field(name).ok_or_else(|| format!("{{{}}} is not a known field", name))?;
+        let value = value.replace(['/', '\\'], "_");
+        match width {
+            Some(width) => out.push_str(&format!("{:0>width$}", value, width = width)),
+            None => out.push_str(&value),
+        }
+        rest = &rest[end + 1..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+/**
+Runs a `Plan` against the devices of a `Session`.
+
+For each step it sets frame type, binning, gain, offset and filter, then for each frame sets
+`CCD_EXPOSURE`, waits for the `setBLOBVector` of the camera and saves it. Works with BLOBs kept
+in memory as well as with BLOBs streamed to a `blob_dir`, which are moved into place.
+*/
+pub struct Sequencer<'a> {
+    session: &'a Session,
+    plan: Plan,
+    journal: Option<(Journal, ResumeMode)>,
+}
+
+impl<'a> Sequencer<'a> {
+    pub fn new(session: &'a Session, plan: Plan) -> Sequencer<'a> {
+        Sequencer { session, plan, journal: None }
+    }
+
+    /**
+    Records progress in `journal` and leaves out the frames it already has, `mode` decides
+    about an exposure that was interrupted.
+    */
+    pub fn with_journal(mut self, journal: Journal, mode: ResumeMode) -> Sequencer<'a> {
+        self.journal = Some((journal, mode));
+        self
+    }
+
+    /// Frames that are left to take.
+    pub fn remaining(&self) -> Vec<Frame> {
+        let frames = self.plan.frames();
+        match &self.journal {
+            Some((journal, _)) => frames.into_iter().filter(|f| !journal.is_done(f)).collect(),
+            None => frames,
+        }
+    }
+
+    pub fn plan(&self) -> &Plan {
+        &self.plan
+    }
+
+    /// Takes the `remaining` frames of the plan, calling `progress` after each one is saved.
+    pub async fn run(&mut self, mut progress: impl FnMut(&Captured)) -> IndiResult<Vec<Captured>> {
+        self.session.enable_blob(EnableBLOBValue::Also, Some(&self.plan.camera), None).await?;
+        let mut captured = Vec::new();
+        let mut configured = None;
+        for frame in self.remaining() {
+            if let Some((journal, ResumeMode::Skip)) = &mut self.journal {
+                if journal.is_partial(&frame) {
+                    log::info!("skipping the interrupted frame {}", frame.index + 1);
+                    journal.record(&Entry::Skipped { frame })?;
+                    continue;
+                }
+            }
+            if configured != Some(frame.step) {
+                self.configure(&self.plan.steps[frame.step]).await?;
+                configured = Some(frame.step);
+            }
+
+            self.record(&Entry::Started { frame })?;
+            let frame = self.capture(frame).await?;
+            self.record(&Entry::completed(&frame))?;
+            progress(&frame);
+            captured.push(frame);
+        }
+        Ok(captured)
+    }
+
+    fn record(&mut self, entry: &Entry) -> IndiResult<()> {
+        match &mut self.journal {
+            Some((journal, _)) => journal.record(entry),
+            None => Ok(()),
+        }
+    }
+
+    /// Applies the settings of `step`.
+    pub async fn configure(&self, step: &Step) -> IndiResult<()> {
+        let camera = Camera::new(self.session, &self.plan.camera);
+        log::info!("{}: {}", self.plan.camera, step);
+        camera.set_frame_type(step.frame_type).await?;
+        if let Some(binning) = step.binning {
+            camera.set_binning(binning, binning).await?;
+        }
+        if let Some(gain) = step.gain {
+            camera.set_gain(gain).await?;
+        }
+        if let Some(offset) = step.offset {
+            camera.set_offset(offset).await?;
+        }
+        if let Some(filter) = &step.filter {
+            let wheel = self.plan.filter_wheel.as_deref()
+                .ok_or_else(|| IndiError::InvalidRequest(format!("{} needs a filter wheel", step)))?;
+            FilterWheel::new(self.session, wheel).select(filter).await?;
+        }
+        Ok(())
+    }
+
+    /// Exposes one frame with the current settings and saves it.
+    pub async fn capture(&self, frame: Frame) -> IndiResult<Captured> {
+        let camera = self.plan.camera.as_str();
+        let step = &self.plan.steps[frame.step];
+
+        log::info!("{}: frame {}/{} of {}", camera, frame.frame + 1, step.count, step);
+        let blob = Camera::new(self.session, camera).capture(step.exposure).await?;
+
+        let properties = self.snapshot();
+        let path = self.save(frame, &blob)?;
+        log::info!("{}: saved {}", camera, path.display());
+        Ok(Captured { frame, path, timestamp: blob.timestamp.clone(), properties })
+    }
+
+    /// Current values of the `SNAPSHOT` properties the camera and filter wheel have.
+    fn snapshot(&self) -> BTreeMap<String, String> {
+        let devices = std::iter::once(&self.plan.camera).chain(self.plan.filter_wheel.as_ref());
+        let mut properties = BTreeMap::new();
+        for device in devices {
+            for vector in SNAPSHOT.iter().filter_map(|name| self.session.property(device, name)) {
+                for element in &vector.elements {
+                    properties.insert(format!("{}.{}.{}", device, vector.name, element.name), element.value.to_string().trim().to_string());
+                }
+            }
+        }
+        properties
+    }
+
+    fn save(&self, frame: Frame, vector: &SetBlobVector) -> IndiResult<PathBuf> {
+        let blob = vector.blobs.iter().find(|b| b.size > 0).ok_or_else(|| IndiError::InvalidData("the image is empty".to_string()))?;
+        let step = &self.plan.steps[frame.step];
+        let (date, time) = vector.timestamp.split_once('T').unwrap_or((&vector.timestamp, ""));
+        let field = |name: &str| Some(match name {
+            "target" => self.plan.target.clone(),
+            "camera" => self.plan.camera.clone(),
+            "type" => format!("{:?}", step.frame_type),
+            "filter" => step.filter.clone().unwrap_or_else(|| "none".to_string()),
+            "exposure" => step.exposure.to_string(),
+            "bin" => step.binning.map(|b| b.to_string()).unwrap_or_default(),
+            "gain" => step.gain.map(|g| g.to_string()).unwrap_or_default(),
+            "step" => (frame.step + 1).to_string(),
+            "frame" => (frame.frame + 1).to_string(),
+            "index" => (frame.index + 1).to_string(),
+            "date" => date.to_string(),
+            "time" => time.split('.').next().unwrap_or_default().replace(':', ""),
+            _ => return None,
+        });
+        let invalid = |e: String| IndiError::InvalidRequest(format!("plan: {}", e));
+        let directory = expand_home(&render(&self.plan.directory, field).map_err(invalid)?);
+        let stem = render(&self.plan.filename, field).map_err(invalid)?;
+        std::fs::create_dir_all(&directory)?;
+
+        if let Some(stored) = &blob.stored {
+            stored.check()?;
+        }
+        let stored = blob.stored.as_ref().and_then(|s| s.path.as_ref());
+        let data = match stored {
+            Some(_) => None,
+            None => Some(blob.decode()?),
+        };
+
+        let extension = file_name_part(blob.decoded_format().trim_start_matches('.'));
+        let mut attempt = 0;
+        let path = loop {
+            let name = if attempt == 0 { stem.clone() } else { format!("{}-{}", stem, attempt) };
+            let path = directory.join(format!("{}.{}", name, extension));
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(_) => break path,
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => attempt += 1,
+                Err(e) => return Err(e.into()),
+            }
+        };
+
+        match (stored, data) {
+            (Some(stored), _) => {
+                if std::fs::rename(stored, &path).is_err() {
+                    //another file system
+                    std::fs::copy(stored, &path)?;
+                    std::fs::remove_file(stored)?;
+                }
+            },
+            (None, data) => std::fs::write(&path, data.unwrap_or_default())?,
+        }
+        Ok(path)
+    }
+}
+
+fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), dirs::home_dir()) {
+        (Some(rest), Some(home)) => home.join(rest),
+        _ => PathBuf::from(path),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use std::path::Path;
+    use std::time::Duration;
+    use crate::indi::session::Session;
+    use crate::indi::blob::SetBlobVector;
+    use crate::sequence::{render, Frame, FrameType, Plan, Sequencer, Step};
+    use crate::simulator::{spawn, CcdSimulator};
+
+    #[test]
+    fn it_parses_plans() {
+        let step: Step = "20 × 300 s, filter Ha, bin 1, gain 100".parse().unwrap();
+        assert_eq!((step.count, step.exposure, step.frame_type), (20, 300.0, FrameType::Light));
+        assert_eq!((step.filter.as_deref(), step.binning, step.gain), (Some("Ha"), Some(1), Some(100.0)));
+        assert_eq!(step.to_string(), "20x300s, filter Ha, bin 1, gain 100");
+        assert_eq!("10x0.5s, dark".parse::<Step>().unwrap().frame_type, FrameType::Dark);
+        assert!("20 frames".parse::<Step>().is_err());
+        assert!("1x1s, iso 800".parse::<Step>().is_err());
+
+        let plan: Plan = toml::from_str(r#"
+            camera = "CCD Simulator"
+            filter_wheel = "Filter Simulator"
+            target = "M42"
+            directory = "frames/{target}"
+            filename = "{filter}_{index:03}"
+
+            [[steps]]
+            count = 2
+            exposure = 300
+            filter = "Ha"
+
+            [[steps]]
+            count = 1
+            exposure = 0
+            frame_type = "Bias"
+        "#).unwrap();
+        plan.validate().unwrap();
+        let frames = plan.frames();
+        assert_eq!(frames.len(), 3);
+        assert_eq!((frames[2].step, frames[2].frame, frames[2].index), (1, 0, 2));
+        assert!(Plan::new("CCD Simulator", vec!["1x1s, filter L".parse().unwrap()]).validate().is_err());
+        assert!(Plan::load(Path::new("does/not/exist.toml")).is_err());
+
+        let field = |name: &str| match name {
+            "target" => Some("M42/Orion".to_string()),
+            "index" => Some("7".to_string()),
+            _ => None,
+        };
+        assert_eq!(render("{target}_{index:04}.fits", field).unwrap(), "M42_Orion_0007.fits");
+        assert!(render("{nope}", field).is_err());
+        assert!(render("{index", field).is_err());
+    }
+
+    #[tokio::test]
+    async fn it_runs_plans_against_the_simulator() -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut simulator = CcdSimulator::new("CCD Simulator");
+        (simulator.width, simulator.height) = (320, 240);
+        let (_, spec) = spawn("ccd", simulator).await?;
+        let session = Session::connect(&[spec]).await?;
+        session.get_properties(None, None).await?;
+        session.wait_for_property("CCD Simulator", "CCD1", Duration::from_secs(5)).await?;
+
+        let directory = tempfile::tempdir()?;
+        let mut plan = Plan::new("CCD Simulator", vec!["2x0.2s, dark, bin 2, gain 50".parse()?]);
+        plan.directory = directory.path().to_string_lossy().into_owned();
+        let mut saved = 0;
+        let captured = Sequencer::new(&session, plan).run(|_| saved += 1).await?;
+        assert_eq!((captured.len(), saved), (2, 2));
+        for frame in &captured {
+            assert!(std::fs::read(&frame.path)?.starts_with(b"SIMPLE  ="));
+        }
+        assert_eq!(captured[1].properties.get("CCD Simulator.CCD_GAIN.GAIN").map(String::as_str), Some("50"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_keeps_hostile_formats_in_the_directory() -> Result<(), Box<dyn Error + Send + Sync>> {
+        let session = Session::connect(&[]).await?;
+        let directory = tempfile::tempdir()?;
+        let mut plan = Plan::new("CCD Simulator", vec!["1x1s, dark".parse()?]);
+        plan.directory = directory.path().join("frames").to_string_lossy().into_owned();
+        let vector: SetBlobVector = quick_xml::de::from_str(&format!(
+            r#"<setBLOBVector device="CCD Simulator" name="CCD1" state="Ok" timeout="60" timestamp="2023-02-11T07:16:57"><oneBLOB name="CCD1" size="3" format="/../x" len="3">{}</oneBLOB></setBLOBVector>"#,
+            base64::encode(b"abc"),
+        ))?;
+
+        let path = Sequencer::new(&session, plan).save(Frame { step: 0, frame: 0, index: 0 }, &vector)?;
+        assert_eq!(path.parent(), Some(directory.path().join("frames").as_path()));
+        assert!(path.file_name().unwrap().to_string_lossy().ends_with("._.._x"));
+        assert_eq!(std::fs::read(path)?, b"abc");
+        Ok(())
+    }
+}
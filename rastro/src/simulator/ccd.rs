@@ -0,0 +1,474 @@
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use async_trait::async_trait;
+
+use crate::driver::{timestamp, DriverHandle, IndiDriver, Request};
+use crate::indi::common::{IndiPermission, IndiState};
+use crate::indi::error::{IndiError, IndiResult};
+use crate::indi::registry::{PropertyKind, PropertyValue, PropertyVector};
+use crate::indi::switch::{IndiSwitch, IndiSwitchOptions};
+use crate::simulator::fits::{self, Value};
+use crate::simulator::{connection, is_on, number, switches, text, tick_every};
+
+const CCD_EXPOSURE: &str = "CCD_EXPOSURE";
+const CCD_ABORT_EXPOSURE: &str = "CCD_ABORT_EXPOSURE";
+const CCD_FRAME: &str = "CCD_FRAME";
+const CCD_FRAME_RESET: &str = "CCD_FRAME_RESET";
+const CCD_FRAME_TYPE: &str = "CCD_FRAME_TYPE";
+const CCD_BINNING: &str = "CCD_BINNING";
+const CCD_GAIN: &str = "CCD_GAIN";
+const CCD_OFFSET: &str = "CCD_OFFSET";
+const CCD_COOLER: &str = "CCD_COOLER";
+const CCD_TEMPERATURE: &str = "CCD_TEMPERATURE";
+const UPLOAD_MODE: &str = "UPLOAD_MODE";
+const UPLOAD_SETTINGS: &str = "UPLOAD_SETTINGS";
+const CCD1: &str = "CCD1";
+
+/// °C the sensor warms up to without the cooler.
+const AMBIENT: f64 = 20.0;
+/// Seeds the star field, so it stays put from frame to frame.
+const STARS: u64 = 0x5eed;
+
+/// Exposure running on the sensor, in seconds.
+#[derive(Debug, Clone, Copy)]
+struct Exposure {
+    left: f64,
+    length: f64,
+}
+
+struct Sensor {
+    device: String,
+    width: u32,
+    height: u32,
+    cooling_rate: f64,
+    exposure: Option<Exposure>,
+    frames: u64,
+    temperature: f64,
+    setpoint: f64,
+}
+
+/**
+A cooled camera with a 16 bit sensor.
+
+Exposures count down `Busy` and end with a FITS frame, sent as the `CCD1` BLOB and or saved
+following `UPLOAD_MODE` and `UPLOAD_SETTINGS`. Light frames show a fixed field of stars, all frames
+have a pedestal from the offset, dark current that halves every 6°C and read noise, scaled by the
+gain. Setting `CCD_TEMPERATURE` turns the cooler on and ramps at `cooling_rate`, `Busy` until the
+sensor gets there.
+*/
+pub struct CcdSimulator {
+    name: String,
+    /// Sensor size in pixels.
+    pub width: u32,
+    pub height: u32,
+    /// °C per second the sensor cools or warms.
+    pub cooling_rate: f64,
+    sensor: Option<Arc<Mutex<Sensor>>>,
+}
+
+impl CcdSimulator {
+    pub fn new(name: &str) -> CcdSimulator {
+        CcdSimulator { name: name.to_string(), width: 1280, height: 1024, cooling_rate: 1.0, sensor: None }
+    }
+
+    fn sensor(&self) -> IndiResult<&Arc<Mutex<Sensor>>> {
+        self.sensor.as_ref().ok_or_else(|| IndiError::InvalidRequest(format!("{} is not started", self.name)))
+    }
+}
+
+#[async_trait]
+impl IndiDriver for CcdSimulator {
+    async fn init(&mut self, driver: &DriverHandle) -> IndiResult<()> {
+        let name = self.name.as_str();
+        let (width, height) = (self.width as f64, self.height as f64);
+        driver.define(connection(name));
+        driver.define(PropertyVector::new(name, CCD_EXPOSURE, PropertyKind::Number, "Expose", "Main Control")
+            .with_element("CCD_EXPOSURE_VALUE", "Duration (s)", PropertyValue::number(1.0, "%5.2f", 0.0, 3600.0, 1.0)));
+        driver.define(switches(name, CCD_ABORT_EXPOSURE, "Abort", "Main Control", &[("ABORT", "Abort")], "")
+            .with_rule(IndiSwitchOptions::AtMostOne));
+        driver.define(PropertyVector::new(name, CCD_FRAME, PropertyKind::Number, "Frame", "Image Settings")
+            .with_element("X", "Left", PropertyValue::number(0.0, "%4.0f", 0.0, width - 1.0, 1.0))
+            .with_element("Y", "Top", PropertyValue::number(0.0, "%4.0f", 0.0, height - 1.0, 1.0))
+            .with_element("WIDTH", "Width", PropertyValue::number(width, "%4.0f", 1.0, width, 1.0))
+            .with_element("HEIGHT", "Height", PropertyValue::number(height, "%4.0f", 1.0, height, 1.0)));
+        driver.define(switches(name, CCD_FRAME_RESET, "Frame Values", "Image Settings", &[("RESET", "Reset")], "")
+            .with_rule(IndiSwitchOptions::AtMostOne));
+        driver.define(PropertyVector::new(name, CCD_BINNING, PropertyKind::Number, "Binning", "Image Settings")
+            .with_element("HOR_BIN", "X", PropertyValue::number(1.0, "%2.0f", 1.0, 4.0, 1.0))
+            .with_element("VER_BIN", "Y", PropertyValue::number(1.0, "%2.0f", 1.0, 4.0, 1.0)));
+        driver.define(switches(
+            name, CCD_FRAME_TYPE, "Type", "Image Settings",
+            &[("FRAME_LIGHT", "Light"), ("FRAME_BIAS", "Bias"), ("FRAME_DARK", "Dark"), ("FRAME_FLAT", "Flat")],
+            "FRAME_LIGHT",
+        ));
+        driver.define(PropertyVector::new(name, CCD_GAIN, PropertyKind::Number, "Gain", "Main Control")
+            .with_element("GAIN", "value", PropertyValue::number(0.0, "%.f", 0.0, 300.0, 1.0)));
+        driver.define(PropertyVector::new(name, CCD_OFFSET, PropertyKind::Number, "Offset", "Main Control")
+            .with_element("OFFSET", "value", PropertyValue::number(10.0, "%.f", 0.0, 100.0, 1.0)));
+        driver.define(switches(name, CCD_COOLER, "Cooler", "Main Control", &[("COOLER_ON", "ON"), ("COOLER_OFF", "OFF")], "COOLER_OFF"));
+        driver.define(PropertyVector::new(name, CCD_TEMPERATURE, PropertyKind::Number, "Temperature", "Main Control")
+            .with_element("CCD_TEMPERATURE_VALUE", "Temperature (C)", PropertyValue::number(AMBIENT, "%5.2f", -50.0, 50.0, 0.0)));
+        driver.define(switches(
+            name, UPLOAD_MODE, "Upload", "Options",
+            &[("UPLOAD_CLIENT", "Client"), ("UPLOAD_LOCAL", "Local"), ("UPLOAD_BOTH", "Both")],
+            "UPLOAD_CLIENT",
+        ));
+        driver.define(PropertyVector::new(name, UPLOAD_SETTINGS, PropertyKind::Text, "Upload Settings", "Options")
+            .with_element("UPLOAD_DIR", "Dir", PropertyValue::Text(std::env::temp_dir().to_string_lossy().into_owned()))
+            .with_element("UPLOAD_PREFIX", "Prefix", PropertyValue::Text("IMAGE_XXX".to_string())));
+        driver.define(PropertyVector::new(name, CCD1, PropertyKind::Blob, "Image Data", "Image Info")
+            .with_perm(IndiPermission::RO)
+            .with_element(CCD1, "Image", PropertyValue::blob()));
+
+        let sensor = Arc::new(Mutex::new(Sensor {
+            device: name.to_string(),
+            width: self.width,
+            height: self.height,
+            cooling_rate: self.cooling_rate,
+            exposure: None,
+            frames: 0,
+            temperature: AMBIENT,
+            setpoint: AMBIENT,
+        }));
+        tick_every(&sensor, driver, tick);
+        self.sensor = Some(sensor);
+        Ok(())
+    }
+
+    async fn new_switch(&mut self, driver: &DriverHandle, request: Request<IndiSwitch>) -> IndiResult<()> {
+        let on = |element: &str| request.value(element) == Some(&IndiSwitch::On);
+        let mut sensor = self.sensor()?.lock().unwrap();
+        let device = request.device.as_str();
+        match request.property.as_str() {
+            CCD_ABORT_EXPOSURE if on("ABORT") => {
+                if sensor.exposure.take().is_some() {
+                    driver.update_with_message(device, CCD_EXPOSURE, Some("exposure aborted"), |v| {
+                        v.set_number("CCD_EXPOSURE_VALUE", 0.0);
+                        v.state = IndiState::Alert;
+                    })?;
+                }
+                driver.update(device, CCD_ABORT_EXPOSURE, |v| {
+                    v.set_switch("ABORT", IndiSwitch::Off);
+                    v.state = IndiState::Ok;
+                })
+            },
+            CCD_FRAME_RESET if on("RESET") => {
+                let (width, height) = (sensor.width as f64, sensor.height as f64);
+                driver.update(device, CCD_FRAME, |v| {
+                    v.set_number("X", 0.0);
+                    v.set_number("Y", 0.0);
+                    v.set_number("WIDTH", width);
+                    v.set_number("HEIGHT", height);
+                    v.state = IndiState::Ok;
+                })?;
+                driver.update(device, CCD_BINNING, |v| {
+                    v.set_number("HOR_BIN", 1.0);
+                    v.set_number("VER_BIN", 1.0);
+                    v.state = IndiState::Ok;
+                })?;
+                driver.update(device, CCD_FRAME_RESET, |v| {
+                    v.set_switch("RESET", IndiSwitch::Off);
+                    v.state = IndiState::Ok;
+                })
+            },
+            CCD_COOLER if on("COOLER_OFF") => {
+                driver.accept(&request)?;
+                driver.update(device, CCD_TEMPERATURE, |v| v.state = IndiState::Idle)
+            },
+            _ => driver.accept(&request),
+        }
+    }
+
+    async fn new_number(&mut self, driver: &DriverHandle, request: Request<f64>) -> IndiResult<()> {
+        let mut sensor = self.sensor()?.lock().unwrap();
+        let device = request.device.as_str();
+        let value = |element: &str, property: &str| request.value(element).copied().unwrap_or_else(|| number(driver, device, property, element));
+        match request.property.as_str() {
+            CCD_EXPOSURE => {
+                let seconds = value("CCD_EXPOSURE_VALUE", CCD_EXPOSURE);
+                if sensor.exposure.is_some() {
+                    return Err(IndiError::InvalidRequest("an exposure is running".to_string()));
+                }
+                if !(0.0..=3600.0).contains(&seconds) {
+                    return Err(IndiError::InvalidRequest(format!("cannot expose for {}s", seconds)));
+                }
+                sensor.exposure = Some(Exposure { left: seconds, length: seconds });
+                driver.update(device, CCD_EXPOSURE, |v| {
+                    v.set_number("CCD_EXPOSURE_VALUE", seconds);
+                    v.state = IndiState::Busy;
+                })
+            },
+            CCD_FRAME => {
+                let (x, y, width, height) = (value("X", CCD_FRAME), value("Y", CCD_FRAME), value("WIDTH", CCD_FRAME), value("HEIGHT", CCD_FRAME));
+                if x < 0.0 || y < 0.0 || width < 1.0 || height < 1.0 || x + width > sensor.width as f64 || y + height > sensor.height as f64 {
+                    return Err(IndiError::InvalidRequest(format!("{}x{} at {},{} is off the sensor", width, height, x, y)));
+                }
+                driver.accept(&request)
+            },
+            CCD_BINNING => {
+                let (horizontal, vertical) = (value("HOR_BIN", CCD_BINNING), value("VER_BIN", CCD_BINNING));
+                if !(1.0..=4.0).contains(&horizontal) || !(1.0..=4.0).contains(&vertical) {
+                    return Err(IndiError::InvalidRequest(format!("cannot bin {}x{}", horizontal, vertical)));
+                }
+                driver.accept(&request)
+            },
+            CCD_TEMPERATURE => {
+                let setpoint = value("CCD_TEMPERATURE_VALUE", CCD_TEMPERATURE);
+                if !(-50.0..=50.0).contains(&setpoint) {
+                    return Err(IndiError::InvalidRequest(format!("cannot cool to {}°C", setpoint)));
+                }
+                sensor.setpoint = setpoint;
+                driver.update(device, CCD_COOLER, |v| {
+                    v.set_switch("COOLER_ON", IndiSwitch::On);
+                    v.state = IndiState::Ok;
+                })?;
+                let state = if sensor.temperature == setpoint { IndiState::Ok } else { IndiState::Busy };
+                driver.update(device, CCD_TEMPERATURE, |v| v.state = state)
+            },
+            _ => driver.accept(&request),
+        }
+    }
+}
+
+fn tick(sensor: &mut Sensor, driver: &DriverHandle, seconds: f64) {
+    cool(sensor, driver, seconds);
+    let exposure = match sensor.exposure {
+        Some(exposure) => exposure,
+        None => return,
+    };
+    let device = sensor.device.clone();
+    let left = exposure.left - seconds;
+    if left > 0.0 {
+        sensor.exposure = Some(Exposure { left, ..exposure });
+        let _ = driver.update(&device, CCD_EXPOSURE, |v| {
+            v.set_number("CCD_EXPOSURE_VALUE", left);
+        });
+        return;
+    }
+
+    sensor.exposure = None;
+    sensor.frames += 1;
+    let state = match read_out(sensor, driver, exposure.length) {
+        Ok(()) => IndiState::Ok,
+        Err(e) => {
+            log::warn!("{}: {}", device, e);
+            driver.message(&device, &format!("saving the frame failed: {}", e));
+            IndiState::Alert
+        },
+    };
+    let _ = driver.update(&device, CCD_EXPOSURE, |v| {
+        v.set_number("CCD_EXPOSURE_VALUE", 0.0);
+        v.state = state;
+    });
+}
+
+/// Moves the temperature towards the setpoint with the cooler on, towards ambient without.
+fn cool(sensor: &mut Sensor, driver: &DriverHandle, seconds: f64) {
+    let device = sensor.device.clone();
+    let cooling = is_on(driver, &device, CCD_COOLER, "COOLER_ON");
+    let target = if cooling { sensor.setpoint } else { AMBIENT };
+    if sensor.temperature == target {
+        return;
+    }
+    let step = sensor.cooling_rate * seconds;
+    let distance = target - sensor.temperature;
+    sensor.temperature = if distance.abs() <= step { target } else { sensor.temperature + step.copysign(distance) };
+
+    let temperature = sensor.temperature;
+    let _ = driver.update(&device, CCD_TEMPERATURE, |v| {
+        v.set_number("CCD_TEMPERATURE_VALUE", temperature);
+        v.state = if !cooling { IndiState::Idle } else if temperature == target { IndiState::Ok } else { IndiState::Busy };
+    });
+}
+
+/// Renders the frame of an exposure of `seconds` and hands it out as `UPLOAD_MODE` says.
+fn read_out(sensor: &Sensor, driver: &DriverHandle, seconds: f64) -> IndiResult<()> {
+    let device = sensor.device.as_str();
+    let setting = |property: &str, element: &str| number(driver, device, property, element);
+    let frame = Frame {
+        x: setting(CCD_FRAME, "X") as u32,
+        y: setting(CCD_FRAME, "Y") as u32,
+        width: setting(CCD_FRAME, "WIDTH") as u32,
+        height: setting(CCD_FRAME, "HEIGHT") as u32,
+        binning: (setting(CCD_BINNING, "HOR_BIN").max(1.0) as u32, setting(CCD_BINNING, "VER_BIN").max(1.0) as u32),
+        kind: ["FRAME_LIGHT", "FRAME_BIAS", "FRAME_DARK", "FRAME_FLAT"]
+            .into_iter()
+            .find(|kind| is_on(driver, device, CCD_FRAME_TYPE, kind))
+            .unwrap_or("FRAME_LIGHT"),
+        seconds,
+        gain: setting(CCD_GAIN, "GAIN"),
+        offset: setting(CCD_OFFSET, "OFFSET"),
+        temperature: sensor.temperature,
+    };
+    let (columns, rows) = frame.size();
+    let pixels = frame.render(sensor.width, sensor.height, sensor.frames);
+    let data = fits::image(columns, rows, &pixels, &[
+        ("EXPTIME", Value::Real(seconds)),
+        ("DATE-OBS", Value::Text(&timestamp())),
+        ("IMAGETYP", Value::Text(&frame.kind["FRAME_".len()..])),
+        ("XBINNING", Value::Integer(frame.binning.0 as i64)),
+        ("YBINNING", Value::Integer(frame.binning.1 as i64)),
+        ("GAIN", Value::Real(frame.gain)),
+        ("OFFSET", Value::Real(frame.offset)),
+        ("CCD-TEMP", Value::Real(frame.temperature)),
+        ("INSTRUME", Value::Text(device)),
+    ]);
+
+    if !is_on(driver, device, UPLOAD_MODE, "UPLOAD_CLIENT") {
+        let prefix = text(driver, device, UPLOAD_SETTINGS, "UPLOAD_PREFIX").replace("XXX", &format!("{:03}", sensor.frames));
+        let path = Path::new(&text(driver, device, UPLOAD_SETTINGS, "UPLOAD_DIR")).join(prefix + ".fits");
+        std::fs::write(&path, &data)?;
+        driver.message(device, &format!("Image saved to {}", path.display()));
+    }
+    if !is_on(driver, device, UPLOAD_MODE, "UPLOAD_LOCAL") {
+        driver.update(device, CCD1, |v| {
+            v.set_blob(CCD1, ".fits", &data);
+            v.state = IndiState::Ok;
+        })?;
+    }
+    Ok(())
+}
+
+/// What an exposure reads out, positions and sizes in unbinned pixels.
+struct Frame {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+    binning: (u32, u32),
+    kind: &'static str,
+    seconds: f64,
+    gain: f64,
+    offset: f64,
+    temperature: f64,
+}
+
+impl Frame {
+    /// Columns and rows of the image.
+    fn size(&self) -> (usize, usize) {
+        ((self.width / self.binning.0).max(1) as usize, (self.height / self.binning.1).max(1) as usize)
+    }
+
+    fn render(&self, sensor_width: u32, sensor_height: u32, seed: u64) -> Vec<u16> {
+        let (columns, rows) = self.size();
+        let (bin_x, bin_y) = (self.binning.0 as f64, self.binning.1 as f64);
+        let gain = 1.0 + self.gain / 100.0;
+        let pedestal = 100.0 + 10.0 * self.offset;
+        let dark = match self.kind {
+            "FRAME_BIAS" => 0.0,
+            _ => 0.5 * 2f64.powf(self.temperature / 6.0) * self.seconds,
+        };
+        let sky = match self.kind {
+            "FRAME_LIGHT" => 20.0 * self.seconds,
+            //flats come out around half full, whatever the exposure
+            "FRAME_FLAT" => 30000.0 / gain / (bin_x * bin_y),
+            _ => 0.0,
+        };
+
+        let mut random = Random::new(seed);
+        let mut signal: Vec<f64> = (0..columns * rows)
+            .map(|_| (dark + sky) * bin_x * bin_y * gain + (random.next() + random.next() - 1.0) * 10.0)
+            .collect();
+
+        if self.kind == "FRAME_LIGHT" {
+            let mut stars = Random::new(STARS);
+            //roughly one star per 10000 pixels of the whole sensor
+            for _ in 0..(sensor_width as u64 * sensor_height as u64 / 10000).max(1) {
+                let (sx, sy) = (stars.next() * sensor_width as f64, stars.next() * sensor_height as f64);
+                let flux = (200.0 + stars.next().powi(4) * 50000.0) * self.seconds * gain;
+                //where the star falls on the image
+                let (cx, cy) = ((sx - self.x as f64) / bin_x, (sy - self.y as f64) / bin_y);
+                let sigma = 1.5 / bin_x.max(bin_y);
+                let reach = (3.0 * sigma).ceil() as i64 + 1;
+                for row in (cy as i64 - reach)..=(cy as i64 + reach) {
+                    for column in (cx as i64 - reach)..=(cx as i64 + reach) {
+                        if row < 0 || column < 0 || row >= rows as i64 || column >= columns as i64 {
+                            continue;
+                        }
+                        let r2 = (column as f64 + 0.5 - cx).powi(2) + (row as f64 + 0.5 - cy).powi(2);
+                        signal[row as usize * columns + column as usize] += flux * (-r2 / (2.0 * sigma * sigma)).exp() / (2.0 * std::f64::consts::PI * sigma * sigma);
+                    }
+                }
+            }
+        }
+        signal.into_iter().map(|s| (pedestal + s).clamp(0.0, 65535.0) as u16).collect()
+    }
+}
+
+/// xorshift64*, plenty for noise.
+struct Random(u64);
+
+impl Random {
+    fn new(seed: u64) -> Random {
+        Random(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1)
+    }
+
+    /// Uniform in [0, 1).
+    fn next(&mut self) -> f64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        (self.0.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use std::time::Duration;
+    use futures::StreamExt;
+    use crate::devices::camera::{Camera, FrameType, Roi, UploadMode};
+    use crate::indi::error::IndiError;
+    use crate::indi::session::Session;
+    use crate::simulator::{spawn, CcdSimulator};
+
+    fn keyword<'a>(fits: &'a [u8], key: &str) -> Option<&'a str> {
+        fits[..2880]
+            .chunks(80)
+            .map(|card| std::str::from_utf8(card).unwrap())
+            .find(|card| card[..8].trim_end() == key)
+            .map(|card| card[10..].trim())
+    }
+
+    #[tokio::test]
+    async fn it_exposes_fits_frames_and_cools() -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut simulator = CcdSimulator::new("CCD Simulator");
+        (simulator.width, simulator.height, simulator.cooling_rate) = (320, 240, 100.0);
+        let (_, spec) = spawn("ccd", simulator).await?;
+        let session = Session::connect(&[spec]).await?;
+        session.get_properties(None, None).await?;
+        session.wait_for_property("CCD Simulator", "CCD1", Duration::from_secs(5)).await?;
+        let camera = Camera::new(&session, "CCD Simulator");
+
+        camera.set_binning(2, 2).await?;
+        let countdown = Box::pin(camera.countdown());
+        let image = camera.expose(0.5).await?;
+        assert_eq!(image.format, ".fits");
+        assert!(image.data.starts_with(b"SIMPLE  =                    T"));
+        assert_eq!((keyword(&image.data, "NAXIS1"), keyword(&image.data, "NAXIS2")), (Some("160"), Some("120")));
+        assert_eq!(image.data.len(), 2880 + (160 * 120 * 2usize).div_ceil(2880) * 2880);
+        let left: Vec<f64> = countdown.take_while(|s| futures::future::ready(*s > 0.0)).collect().await;
+        assert!(left.len() > 2 && left.windows(2).all(|w| w[0] > w[1]));
+
+        assert!(matches!(camera.set_roi(Roi { x: 100, y: 0, width: 320, height: 240 }).await, Err(IndiError::Alert { .. })));
+        camera.set_temperature(-10.0).await?;
+        assert!(camera.is_cooler_on()?);
+        assert_eq!(camera.temperature()?, -10.0);
+
+        let directory = tempfile::tempdir()?;
+        camera.set_upload_settings(&directory.path().to_string_lossy(), "dark_XXX").await?;
+        camera.set_upload_mode(UploadMode::Both).await?;
+        camera.set_frame_type(FrameType::Dark).await?;
+        let dark = camera.expose(0.1).await?;
+        assert_eq!(keyword(&dark.data, "IMAGETYP"), Some("'DARK    '"));
+        assert_eq!(std::fs::read(directory.path().join("dark_002.fits"))?, dark.data);
+
+        let (exposure, abort) = tokio::join!(camera.expose(10.0), async {
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            camera.abort_exposure().await
+        });
+        abort?;
+        assert!(matches!(exposure, Err(IndiError::Alert { .. })));
+        Ok(())
+    }
+}
@@ -0,0 +1,56 @@
+/// Header and data come in blocks of this many bytes.
+const BLOCK: usize = 2880;
+const CARD: usize = 80;
+
+/// A value of a header card.
+pub enum Value<'a> {
+    Logical(bool),
+    Integer(i64),
+    Real(f64),
+    Text(&'a str),
+}
+
+fn card(key: &str, value: &Value) -> String {
+    let value = match value {
+        Value::Logical(value) => format!("{:>20}", if *value { "T" } else { "F" }),
+        Value::Integer(value) => format!("{:>20}", value),
+        Value::Real(value) => format!("{:>20}", format!("{:?}", value).to_uppercase()),
+        //quotes in text are doubled, the text is padded to at least 8 characters
+        Value::Text(value) => format!("'{:<8}'", value.replace('\'', "''")),
+    };
+    let mut card = format!("{:<8}= {}", key, value);
+    card.truncate(CARD);
+    format!("{:<80}", card)
+}
+
+/**
+A primary HDU holding a `width` by `height` image of unsigned 16 bit pixels, row by row from the
+bottom, with `keywords` after the mandatory ones.
+
+Pixels are stored signed with `BZERO` 32768, as FITS has no unsigned integers.
+*/
+pub fn image(width: usize, height: usize, pixels: &[u16], keywords: &[(&str, Value)]) -> Vec<u8> {
+    let mandatory = [
+        ("SIMPLE", Value::Logical(true)),
+        ("BITPIX", Value::Integer(16)),
+        ("NAXIS", Value::Integer(2)),
+        ("NAXIS1", Value::Integer(width as i64)),
+        ("NAXIS2", Value::Integer(height as i64)),
+        ("BZERO", Value::Integer(32768)),
+        ("BSCALE", Value::Integer(1)),
+    ];
+    let mut header = String::new();
+    for (key, value) in mandatory.iter().chain(keywords) {
+        header.push_str(&card(key, value));
+    }
+    header.push_str(&format!("{:<80}", "END"));
+    let mut data = header.into_bytes();
+    data.resize(data.len().div_ceil(BLOCK) * BLOCK, b' ');
+
+    data.reserve(pixels.len() * 2 + BLOCK);
+    for pixel in pixels {
+        data.extend_from_slice(&((*pixel as i32 - 32768) as i16).to_be_bytes());
+    }
+    data.resize(data.len().div_ceil(BLOCK) * BLOCK, 0);
+    data
+}
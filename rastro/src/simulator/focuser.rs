@@ -0,0 +1,228 @@
+use std::sync::{Arc, Mutex};
+use async_trait::async_trait;
+
+use crate::driver::{DriverHandle, IndiDriver, Request};
+use crate::indi::common::{IndiPermission, IndiState};
+use crate::indi::error::{IndiError, IndiResult};
+use crate::indi::registry::{PropertyKind, PropertyValue, PropertyVector};
+use crate::indi::switch::{IndiSwitch, IndiSwitchOptions};
+use crate::simulator::{connection, is_on, number, switches, tick_every};
+
+const ABS_FOCUS_POSITION: &str = "ABS_FOCUS_POSITION";
+const REL_FOCUS_POSITION: &str = "REL_FOCUS_POSITION";
+const FOCUS_MOTION: &str = "FOCUS_MOTION";
+const FOCUS_ABORT_MOTION: &str = "FOCUS_ABORT_MOTION";
+const FOCUS_TEMPERATURE: &str = "FOCUS_TEMPERATURE";
+const FOCUS_BACKLASH_TOGGLE: &str = "FOCUS_BACKLASH_TOGGLE";
+const FOCUS_BACKLASH_STEPS: &str = "FOCUS_BACKLASH_STEPS";
+
+/// A move under way, in steps.
+#[derive(Debug, Clone, Copy)]
+struct Travel {
+    target: f64,
+    /// Steps to take up before the position changes.
+    slack: f64,
+    relative: bool,
+}
+
+struct Drive {
+    device: String,
+    speed: f64,
+    position: f64,
+    travel: Option<Travel>,
+    /// Whether the last move was outward, the gears have slack when reversing.
+    outward: bool,
+}
+
+/**
+A focuser with absolute and relative moves and a temperature probe.
+
+Moves travel at `speed`, keeping the position properties `Busy` until the focuser stops. With
+backlash compensation enabled, reversing first takes up `FOCUS_BACKLASH_STEPS` without the
+position changing, the way gears with slack do.
+*/
+pub struct FocuserSimulator {
+    name: String,
+    /// Steps per second.
+    pub speed: f64,
+    /// Highest absolute position.
+    pub max: u32,
+    /// °C the probe reads.
+    pub temperature: f64,
+    drive: Option<Arc<Mutex<Drive>>>,
+}
+
+impl FocuserSimulator {
+    pub fn new(name: &str) -> FocuserSimulator {
+        FocuserSimulator { name: name.to_string(), speed: 1000.0, max: 100000, temperature: 12.5, drive: None }
+    }
+
+    fn drive(&self) -> IndiResult<&Arc<Mutex<Drive>>> {
+        self.drive.as_ref().ok_or_else(|| IndiError::InvalidRequest(format!("{} is not started", self.name)))
+    }
+}
+
+#[async_trait]
+impl IndiDriver for FocuserSimulator {
+    async fn init(&mut self, driver: &DriverHandle) -> IndiResult<()> {
+        let name = self.name.as_str();
+        let (max, position) = (self.max as f64, (self.max / 2) as f64);
+        driver.define(connection(name));
+        driver.define(PropertyVector::new(name, ABS_FOCUS_POSITION, PropertyKind::Number, "Absolute Position", "Main Control")
+            .with_element("FOCUS_ABSOLUTE_POSITION", "Steps", PropertyValue::number(position, "%.f", 0.0, max, max / 50.0)));
+        driver.define(PropertyVector::new(name, REL_FOCUS_POSITION, PropertyKind::Number, "Relative Position", "Main Control")
+            .with_element("FOCUS_RELATIVE_POSITION", "Steps", PropertyValue::number(0.0, "%.f", 0.0, max / 2.0, max / 100.0)));
+        driver.define(switches(name, FOCUS_MOTION, "Direction", "Main Control", &[("FOCUS_INWARD", "In"), ("FOCUS_OUTWARD", "Out")], "FOCUS_INWARD"));
+        driver.define(switches(name, FOCUS_ABORT_MOTION, "Abort Motion", "Main Control", &[("ABORT", "Abort")], "")
+            .with_rule(IndiSwitchOptions::AtMostOne));
+        driver.define(PropertyVector::new(name, FOCUS_TEMPERATURE, PropertyKind::Number, "Temperature", "Main Control")
+            .with_perm(IndiPermission::RO)
+            .with_element("TEMPERATURE", "Celsius", PropertyValue::number(self.temperature, "%6.2f", -50.0, 70.0, 0.0)));
+        driver.define(switches(name, FOCUS_BACKLASH_TOGGLE, "Backlash", "Options", &[("INDI_ENABLED", "Enabled"), ("INDI_DISABLED", "Disabled")], "INDI_DISABLED"));
+        driver.define(PropertyVector::new(name, FOCUS_BACKLASH_STEPS, PropertyKind::Number, "Backlash", "Options")
+            .with_element("FOCUS_BACKLASH_VALUE", "Steps", PropertyValue::number(100.0, "%.f", 0.0, 1000.0, 1.0)));
+
+        let drive = Arc::new(Mutex::new(Drive { device: name.to_string(), speed: self.speed, position, travel: None, outward: false }));
+        tick_every(&drive, driver, tick);
+        self.drive = Some(drive);
+        Ok(())
+    }
+
+    async fn new_switch(&mut self, driver: &DriverHandle, request: Request<IndiSwitch>) -> IndiResult<()> {
+        if request.property != FOCUS_ABORT_MOTION || request.value("ABORT") != Some(&IndiSwitch::On) {
+            return driver.accept(&request);
+        }
+        let mut drive = self.drive()?.lock().unwrap();
+        if let Some(travel) = drive.travel.take() {
+            let position = drive.position.round();
+            driver.update_with_message(&request.device, ABS_FOCUS_POSITION, Some("move aborted"), |v| {
+                v.set_number("FOCUS_ABSOLUTE_POSITION", position);
+                v.state = IndiState::Alert;
+            })?;
+            if travel.relative {
+                driver.update(&request.device, REL_FOCUS_POSITION, |v| v.state = IndiState::Alert)?;
+            }
+        }
+        driver.update(&request.device, FOCUS_ABORT_MOTION, |v| {
+            v.set_switch("ABORT", IndiSwitch::Off);
+            v.state = IndiState::Ok;
+        })
+    }
+
+    async fn new_number(&mut self, driver: &DriverHandle, request: Request<f64>) -> IndiResult<()> {
+        let device = request.device.as_str();
+        let mut drive = self.drive()?.lock().unwrap();
+        let (target, relative) = match request.property.as_str() {
+            ABS_FOCUS_POSITION => match request.value("FOCUS_ABSOLUTE_POSITION") {
+                Some(target) => (target.round(), false),
+                None => return driver.accept(&request),
+            },
+            REL_FOCUS_POSITION => match request.value("FOCUS_RELATIVE_POSITION") {
+                Some(steps) if is_on(driver, device, FOCUS_MOTION, "FOCUS_OUTWARD") => ((drive.position + steps).round(), true),
+                Some(steps) => ((drive.position - steps).round(), true),
+                None => return driver.accept(&request),
+            },
+            _ => return driver.accept(&request),
+        };
+        if !(0.0..=self.max as f64).contains(&target) {
+            return Err(IndiError::InvalidRequest(format!("{} is beyond the travel of 0 to {}", target, self.max)));
+        }
+        if drive.travel.is_some() {
+            return Err(IndiError::InvalidRequest("the focuser is moving".to_string()));
+        }
+
+        let outward = target > drive.position;
+        let reversing = target != drive.position && outward != drive.outward;
+        let slack = if reversing && is_on(driver, device, FOCUS_BACKLASH_TOGGLE, "INDI_ENABLED") {
+            number(driver, device, FOCUS_BACKLASH_STEPS, "FOCUS_BACKLASH_VALUE")
+        } else {
+            0.0
+        };
+        if target != drive.position {
+            drive.outward = outward;
+        }
+        drive.travel = Some(Travel { target, slack, relative });
+        if relative {
+            driver.update(device, REL_FOCUS_POSITION, |v| {
+                for (element, steps) in &request.values {
+                    v.set_number(element, *steps);
+                }
+                v.state = IndiState::Busy;
+            })?;
+        }
+        driver.update(device, ABS_FOCUS_POSITION, |v| v.state = IndiState::Busy)
+    }
+}
+
+fn tick(drive: &mut Drive, driver: &DriverHandle, seconds: f64) {
+    let mut travel = match drive.travel {
+        Some(travel) => travel,
+        None => return,
+    };
+    let mut step = drive.speed * seconds;
+    let taken_up = travel.slack.min(step);
+    travel.slack -= taken_up;
+    step -= taken_up;
+
+    let distance = travel.target - drive.position;
+    let arrived = travel.slack == 0.0 && distance.abs() <= step;
+    drive.position = if arrived { travel.target } else { drive.position + step.copysign(distance) };
+    drive.travel = if arrived { None } else { Some(travel) };
+
+    let (device, position) = (drive.device.clone(), drive.position.round());
+    let state = if arrived { IndiState::Ok } else { IndiState::Busy };
+    let _ = driver.update(&device, ABS_FOCUS_POSITION, |v| {
+        v.set_number("FOCUS_ABSOLUTE_POSITION", position);
+        v.state = state;
+    });
+    if arrived && travel.relative {
+        let _ = driver.update(&device, REL_FOCUS_POSITION, |v| v.state = IndiState::Ok);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use std::time::{Duration, Instant};
+    use crate::devices::focuser::Focuser;
+    use crate::indi::error::IndiError;
+    use crate::indi::session::Session;
+    use crate::simulator::{spawn, FocuserSimulator};
+
+    #[tokio::test]
+    async fn it_travels_and_takes_up_backlash() -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut simulator = FocuserSimulator::new("Focuser Simulator");
+        simulator.speed = 5000.0;
+        let (_, spec) = spawn("focuser", simulator).await?;
+        let session = Session::connect(&[spec]).await?;
+        session.get_properties(None, None).await?;
+        session.wait_for_property("Focuser Simulator", "FOCUS_BACKLASH_STEPS", Duration::from_secs(5)).await?;
+        let focuser = Focuser::new(&session, "Focuser Simulator");
+        assert_eq!((focuser.position()?, focuser.temperature()?), (50000, 12.5));
+
+        let started = Instant::now();
+        focuser.move_to(52000).await?;
+        assert_eq!(focuser.position()?, 52000);
+        assert!(started.elapsed() >= Duration::from_millis(300));
+        focuser.move_by(-500).await?;
+        assert_eq!(focuser.position()?, 51500);
+        assert!(matches!(focuser.move_to(200000).await, Err(IndiError::Alert { .. })));
+
+        //reversing takes up the slack first, 1500 steps at 5000 a second
+        focuser.set_backlash_enabled(true).await?;
+        focuser.set_backlash(1000).await?;
+        let started = Instant::now();
+        focuser.move_by(500).await?;
+        assert_eq!(focuser.position()?, 52000);
+        assert!(started.elapsed() >= Duration::from_millis(250));
+
+        let (moved, aborted) = tokio::join!(focuser.move_to(0), async {
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            focuser.abort().await
+        });
+        aborted?;
+        assert!(matches!(moved, Err(IndiError::Alert { .. })));
+        assert!(focuser.position()? > 0);
+        Ok(())
+    }
+}
@@ -0,0 +1,98 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::time::{Instant, MissedTickBehavior};
+
+use crate::config_file::ConnectionSpec;
+use crate::driver::{DriverHandle, DriverRuntime, IndiDriver};
+use crate::indi::error::IndiResult;
+use crate::indi::registry::{PropertyKind, PropertyValue, PropertyVector};
+use crate::indi::switch::IndiSwitch;
+
+mod fits;
+pub mod ccd;
+pub mod focuser;
+pub mod telescope;
+
+pub use ccd::CcdSimulator;
+pub use focuser::FocuserSimulator;
+pub use telescope::TelescopeSimulator;
+
+/// How often the simulators move things along, slews, exposures, cooling and focuser travel.
+const TICK: Duration = Duration::from_millis(100);
+
+/**
+Starts `driver` and serves it on a free port of localhost in the background.
+
+Returns the runtime, to look at or change the properties directly, and a spec named `name` to
+connect a `Session` to it.
+*/
+pub async fn spawn<D: IndiDriver>(name: &str, driver: D) -> IndiResult<(DriverRuntime<D>, ConnectionSpec)> {
+    let runtime = DriverRuntime::start(driver).await?;
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let spec = ConnectionSpec::new(name, "127.0.0.1", listener.local_addr()?.port() as usize);
+    let server = runtime.clone();
+    let label = name.to_string();
+    tokio::spawn(async move {
+        if let Err(e) = server.serve_tcp(listener).await {
+            log::warn!("simulator {} stopped: {}", label, e);
+        }
+    });
+    Ok((runtime, spec))
+}
+
+/// Calls `tick` with the seconds since its last call every `TICK`, until `state` is dropped.
+fn tick_every<S: Send + 'static>(state: &Arc<Mutex<S>>, driver: &DriverHandle, tick: fn(&mut S, &DriverHandle, f64)) {
+    let state = Arc::downgrade(state);
+    let driver = driver.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(TICK);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let mut last = Instant::now();
+        loop {
+            interval.tick().await;
+            let state = match state.upgrade() {
+                Some(state) => state,
+                None => return,
+            };
+            let now = Instant::now();
+            tick(&mut state.lock().unwrap(), &driver, (now - last).as_secs_f64());
+            last = now;
+        }
+    });
+}
+
+/// The `CONNECTION` switch, off.
+fn connection(device: &str) -> PropertyVector {
+    PropertyVector::new(device, "CONNECTION", PropertyKind::Switch, "Connection", "Main Control")
+        .with_element("CONNECT", "Connect", PropertyValue::Switch(IndiSwitch::Off))
+        .with_element("DISCONNECT", "Disconnect", PropertyValue::Switch(IndiSwitch::On))
+}
+
+fn switches(device: &str, name: &str, label: &str, group: &str, elements: &[(&str, &str)], on: &str) -> PropertyVector {
+    elements.iter().fold(PropertyVector::new(device, name, PropertyKind::Switch, label, group), |vector, (element, label)| {
+        let value = if *element == on { IndiSwitch::On } else { IndiSwitch::Off };
+        vector.with_element(element, label, PropertyValue::Switch(value))
+    })
+}
+
+fn number(driver: &DriverHandle, device: &str, property: &str, element: &str) -> f64 {
+    match driver.property(device, property).and_then(|p| p.element(element).map(|e| e.value.clone())) {
+        Some(PropertyValue::Number { value, .. }) => value,
+        _ => 0.0,
+    }
+}
+
+fn is_on(driver: &DriverHandle, device: &str, property: &str, element: &str) -> bool {
+    match driver.property(device, property).and_then(|p| p.element(element).map(|e| e.value.clone())) {
+        Some(PropertyValue::Switch(value)) => value == IndiSwitch::On,
+        _ => false,
+    }
+}
+
+fn text(driver: &DriverHandle, device: &str, property: &str, element: &str) -> String {
+    match driver.property(device, property).and_then(|p| p.element(element).map(|e| e.value.clone())) {
+        Some(PropertyValue::Text(value)) => value,
+        _ => String::new(),
+    }
+}
@@ -0,0 +1,331 @@
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use async_trait::async_trait;
+
+use crate::driver::{DriverHandle, IndiDriver, Request};
+use crate::indi::common::{IndiPermission, IndiState};
+use crate::indi::error::{IndiError, IndiResult};
+use crate::indi::registry::{PropertyKind, PropertyValue, PropertyVector};
+use crate::indi::switch::{IndiSwitch, IndiSwitchOptions};
+use crate::simulator::{connection, is_on, number, switches, tick_every};
+
+const EQUATORIAL_EOD_COORD: &str = "EQUATORIAL_EOD_COORD";
+const ON_COORD_SET: &str = "ON_COORD_SET";
+const TELESCOPE_ABORT_MOTION: &str = "TELESCOPE_ABORT_MOTION";
+const TELESCOPE_PARK: &str = "TELESCOPE_PARK";
+const TELESCOPE_TRACK_STATE: &str = "TELESCOPE_TRACK_STATE";
+const TELESCOPE_TRACK_MODE: &str = "TELESCOPE_TRACK_MODE";
+const TELESCOPE_TRACK_RATE: &str = "TELESCOPE_TRACK_RATE";
+const TELESCOPE_PIER_SIDE: &str = "TELESCOPE_PIER_SIDE";
+
+/// Arcseconds per second the stars move across the sky.
+const SIDEREAL_RATE: f64 = 15.041067;
+const SOLAR_RATE: f64 = 15.0;
+const LUNAR_RATE: f64 = 14.685;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Goto {
+    Track,
+    Slew,
+    Park,
+}
+
+/// Where a slew is headed, in hours and degrees.
+#[derive(Debug, Clone, Copy)]
+struct Slew {
+    ra: f64,
+    dec: f64,
+    goto: Goto,
+}
+
+struct Mount {
+    device: String,
+    slew_rate: f64,
+    ra: f64,
+    dec: f64,
+    slew: Option<Slew>,
+    parked: bool,
+    /// Seconds the drift has not been reported for.
+    unreported: f64,
+}
+
+/**
+A German equatorial mount at longitude 0.
+
+Slews move both axes at `slew_rate` towards the target, reporting the position `Busy` until they
+arrive. Otherwise the position follows the tracking: untracked it drifts with the sky, the solar,
+lunar and custom rates drift relative to sidereal. Parking points the mount at the pole on the
+meridian and turns tracking off.
+*/
+pub struct TelescopeSimulator {
+    name: String,
+    /// Degrees per second on each axis.
+    pub slew_rate: f64,
+    mount: Option<Arc<Mutex<Mount>>>,
+}
+
+impl TelescopeSimulator {
+    pub fn new(name: &str) -> TelescopeSimulator {
+        TelescopeSimulator { name: name.to_string(), slew_rate: 3.0, mount: None }
+    }
+
+    fn mount(&self) -> IndiResult<&Arc<Mutex<Mount>>> {
+        self.mount.as_ref().ok_or_else(|| IndiError::InvalidRequest(format!("{} is not started", self.name)))
+    }
+}
+
+#[async_trait]
+impl IndiDriver for TelescopeSimulator {
+    async fn init(&mut self, driver: &DriverHandle) -> IndiResult<()> {
+        let name = self.name.as_str();
+        let (ra, dec) = park_position();
+        driver.define(connection(name));
+        driver.define(PropertyVector::new(name, EQUATORIAL_EOD_COORD, PropertyKind::Number, "Eq. Coordinates", "Main Control")
+            .with_element("RA", "RA (hh:mm:ss)", PropertyValue::number(ra, "%010.6m", 0.0, 24.0, 0.0))
+            .with_element("DEC", "DEC (dd:mm:ss)", PropertyValue::number(dec, "%010.6m", -90.0, 90.0, 0.0)));
+        driver.define(switches(name, ON_COORD_SET, "On Set", "Main Control", &[("TRACK", "Track"), ("SLEW", "Slew"), ("SYNC", "Sync")], "TRACK"));
+        driver.define(switches(name, TELESCOPE_ABORT_MOTION, "Abort Motion", "Main Control", &[("ABORT", "Abort")], "")
+            .with_rule(IndiSwitchOptions::AtMostOne));
+        driver.define(switches(name, TELESCOPE_PARK, "Parking", "Main Control", &[("PARK", "Park(ed)"), ("UNPARK", "UnPark(ed)")], "UNPARK"));
+        driver.define(switches(name, TELESCOPE_TRACK_STATE, "Tracking", "Main Control", &[("TRACK_ON", "On"), ("TRACK_OFF", "Off")], "TRACK_OFF"));
+        driver.define(switches(
+            name, TELESCOPE_TRACK_MODE, "Track Mode", "Main Control",
+            &[("TRACK_SIDEREAL", "Sidereal"), ("TRACK_SOLAR", "Solar"), ("TRACK_LUNAR", "Lunar"), ("TRACK_CUSTOM", "Custom")],
+            "TRACK_SIDEREAL",
+        ));
+        driver.define(PropertyVector::new(name, TELESCOPE_TRACK_RATE, PropertyKind::Number, "Track Rates", "Main Control")
+            .with_element("TRACK_RATE_RA", "RA (arcsecs/s)", PropertyValue::number(SIDEREAL_RATE, "%.6f", -16384.0, 16384.0, 0.000001))
+            .with_element("TRACK_RATE_DE", "DE (arcsecs/s)", PropertyValue::number(0.0, "%.6f", -16384.0, 16384.0, 0.000001)));
+        driver.define(switches(name, TELESCOPE_PIER_SIDE, "Pier Side", "Main Control", &[("PIER_WEST", "West (pointing east)"), ("PIER_EAST", "East (pointing west)")], pier_side(ra))
+            .with_perm(IndiPermission::RO));
+
+        let mount = Arc::new(Mutex::new(Mount {
+            device: name.to_string(),
+            slew_rate: self.slew_rate,
+            ra,
+            dec,
+            slew: None,
+            parked: false,
+            unreported: 0.0,
+        }));
+        tick_every(&mount, driver, tick);
+        self.mount = Some(mount);
+        Ok(())
+    }
+
+    async fn new_switch(&mut self, driver: &DriverHandle, request: Request<IndiSwitch>) -> IndiResult<()> {
+        let on = |element: &str| request.value(element) == Some(&IndiSwitch::On);
+        let mut mount = self.mount()?.lock().unwrap();
+        match request.property.as_str() {
+            TELESCOPE_ABORT_MOTION if on("ABORT") => {
+                if let Some(slew) = mount.slew.take() {
+                    let property = if slew.goto == Goto::Park { TELESCOPE_PARK } else { EQUATORIAL_EOD_COORD };
+                    driver.update_with_message(&request.device, property, Some("slew aborted"), |v| v.state = IndiState::Alert)?;
+                }
+                driver.update(&request.device, TELESCOPE_ABORT_MOTION, |v| {
+                    v.set_switch("ABORT", IndiSwitch::Off);
+                    v.state = IndiState::Ok;
+                })
+            },
+            TELESCOPE_PARK if on("PARK") => {
+                let (ra, dec) = park_position();
+                mount.slew = Some(Slew { ra, dec, goto: Goto::Park });
+                set_tracking(driver, &request.device, false)?;
+                driver.update(&request.device, TELESCOPE_PARK, |v| {
+                    v.set_switch("PARK", IndiSwitch::On);
+                    v.state = IndiState::Busy;
+                })
+            },
+            TELESCOPE_PARK if on("UNPARK") => {
+                if mount.slew.is_some_and(|slew| slew.goto == Goto::Park) {
+                    return Err(IndiError::InvalidRequest("the mount is parking".to_string()));
+                }
+                mount.parked = false;
+                driver.accept(&request)
+            },
+            TELESCOPE_TRACK_STATE if on("TRACK_ON") && mount.parked => Err(IndiError::InvalidRequest("the mount is parked".to_string())),
+            _ => driver.accept(&request),
+        }
+    }
+
+    async fn new_number(&mut self, driver: &DriverHandle, request: Request<f64>) -> IndiResult<()> {
+        if request.property != EQUATORIAL_EOD_COORD {
+            return driver.accept(&request);
+        }
+        let mut mount = self.mount()?.lock().unwrap();
+        let ra = request.value("RA").copied().unwrap_or(mount.ra);
+        let dec = request.value("DEC").copied().unwrap_or(mount.dec);
+        if !(0.0..=24.0).contains(&ra) || !(-90.0..=90.0).contains(&dec) {
+            return Err(IndiError::InvalidRequest(format!("RA {} DEC {} is not in the sky", ra, dec)));
+        }
+        if mount.parked {
+            return Err(IndiError::InvalidRequest("the mount is parked".to_string()));
+        }
+
+        let device = &request.device;
+        if is_on(driver, device, ON_COORD_SET, "SYNC") {
+            mount.slew = None;
+            mount.ra = ra % 24.0;
+            mount.dec = dec;
+            return driver.update(device, EQUATORIAL_EOD_COORD, |v| {
+                v.set_number("RA", ra % 24.0);
+                v.set_number("DEC", dec);
+                v.state = IndiState::Ok;
+            });
+        }
+        let goto = if is_on(driver, device, ON_COORD_SET, "SLEW") { Goto::Slew } else { Goto::Track };
+        mount.slew = Some(Slew { ra: ra % 24.0, dec, goto });
+        driver.update(device, EQUATORIAL_EOD_COORD, |v| v.state = IndiState::Busy)
+    }
+}
+
+fn tick(mount: &mut Mount, driver: &DriverHandle, seconds: f64) {
+    let device = mount.device.clone();
+    let slew = match mount.slew {
+        Some(slew) => slew,
+        None => {
+            drift(mount, driver, seconds);
+            return;
+        },
+    };
+
+    let step = mount.slew_rate * seconds;
+    //RA the short way round, in degrees
+    let ra_distance = ((slew.ra - mount.ra) * 15.0 + 180.0).rem_euclid(360.0) - 180.0;
+    let dec_distance = slew.dec - mount.dec;
+    mount.ra = (mount.ra + ra_distance.clamp(-step, step) / 15.0).rem_euclid(24.0);
+    mount.dec += dec_distance.clamp(-step, step);
+    let arrived = ra_distance.abs() <= step && dec_distance.abs() <= step;
+    if arrived {
+        mount.ra = slew.ra;
+        mount.dec = slew.dec;
+        mount.slew = None;
+        mount.unreported = 0.0;
+    }
+
+    let (ra, dec) = (mount.ra, mount.dec);
+    //the rest of the mount settles first, clients waiting on the position see it done
+    if arrived {
+        let _ = driver.update(&device, TELESCOPE_PIER_SIDE, |v| {
+            v.set_switch(pier_side(ra), IndiSwitch::On);
+            v.state = IndiState::Ok;
+        });
+        if slew.goto != Goto::Park {
+            let _ = set_tracking(driver, &device, slew.goto == Goto::Track);
+        }
+    }
+    let _ = driver.update(&device, EQUATORIAL_EOD_COORD, |v| {
+        v.set_number("RA", ra);
+        v.set_number("DEC", dec);
+        v.state = if !arrived { IndiState::Busy } else if slew.goto == Goto::Park { IndiState::Idle } else { IndiState::Ok };
+    });
+    if arrived && slew.goto == Goto::Park {
+        mount.parked = true;
+        let _ = driver.update(&device, TELESCOPE_PARK, |v| v.state = IndiState::Ok);
+    }
+}
+
+/// Moves the position the way the sky moves past the mount, reports it once a second.
+fn drift(mount: &mut Mount, driver: &DriverHandle, seconds: f64) {
+    let device = mount.device.clone();
+    let (ra_rate, dec_rate) = if !is_on(driver, &device, TELESCOPE_TRACK_STATE, "TRACK_ON") {
+        (0.0, 0.0)
+    } else if is_on(driver, &device, TELESCOPE_TRACK_MODE, "TRACK_SOLAR") {
+        (SOLAR_RATE, 0.0)
+    } else if is_on(driver, &device, TELESCOPE_TRACK_MODE, "TRACK_LUNAR") {
+        (LUNAR_RATE, 0.0)
+    } else if is_on(driver, &device, TELESCOPE_TRACK_MODE, "TRACK_CUSTOM") {
+        (number(driver, &device, TELESCOPE_TRACK_RATE, "TRACK_RATE_RA"), number(driver, &device, TELESCOPE_TRACK_RATE, "TRACK_RATE_DE"))
+    } else {
+        (SIDEREAL_RATE, 0.0)
+    };
+    if ra_rate == SIDEREAL_RATE && dec_rate == 0.0 {
+        return;
+    }
+
+    //what the mount falls behind the sky, in seconds of RA
+    mount.ra = (mount.ra + (SIDEREAL_RATE - ra_rate) * seconds / 15.0 / 3600.0).rem_euclid(24.0);
+    mount.dec = (mount.dec + dec_rate * seconds / 3600.0).clamp(-90.0, 90.0);
+    mount.unreported += seconds;
+    if mount.unreported < 1.0 {
+        return;
+    }
+    mount.unreported = 0.0;
+    let (ra, dec) = (mount.ra, mount.dec);
+    let _ = driver.update(&device, EQUATORIAL_EOD_COORD, |v| {
+        v.set_number("RA", ra);
+        v.set_number("DEC", dec);
+    });
+}
+
+fn set_tracking(driver: &DriverHandle, device: &str, on: bool) -> IndiResult<()> {
+    driver.update(device, TELESCOPE_TRACK_STATE, |v| {
+        v.set_switch(if on { "TRACK_ON" } else { "TRACK_OFF" }, IndiSwitch::On);
+        v.state = if on { IndiState::Ok } else { IndiState::Idle };
+    })
+}
+
+/// The pole, on the meridian.
+fn park_position() -> (f64, f64) {
+    (sidereal_time(), 90.0)
+}
+
+/// Pier side of a mount pointing at `ra`, east when pointing west of the meridian.
+fn pier_side(ra: f64) -> &'static str {
+    let hour_angle = (sidereal_time() - ra + 12.0).rem_euclid(24.0) - 12.0;
+    if hour_angle >= 0.0 { "PIER_EAST" } else { "PIER_WEST" }
+}
+
+/// Greenwich mean sidereal time in hours, the local one at longitude 0.
+fn sidereal_time() -> f64 {
+    let seconds = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs_f64()).unwrap_or(0.0);
+    //days since J2000, 2000-01-01T12:00:00 UTC
+    let days = (seconds - 946_728_000.0) / 86400.0;
+    (18.697374558 + 24.06570982441908 * days).rem_euclid(24.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use std::time::Duration;
+    use futures::StreamExt;
+    use crate::devices::telescope::{EquatorialCoords, Telescope, TrackMode};
+    use crate::indi::session::Session;
+    use crate::simulator::{spawn, TelescopeSimulator};
+
+    #[tokio::test]
+    async fn it_slews_tracks_and_parks() -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut simulator = TelescopeSimulator::new("Telescope Simulator");
+        simulator.slew_rate = 120.0;
+        let (_, spec) = spawn("mount", simulator).await?;
+        let session = Session::connect(&[spec]).await?;
+        session.get_properties(None, None).await?;
+        session.wait_for_property("Telescope Simulator", "TELESCOPE_PIER_SIDE", Duration::from_secs(5)).await?;
+        let mount = Telescope::new(&session, "Telescope Simulator");
+        assert!(!mount.is_parked()? && !mount.is_tracking()?);
+
+        let positions = Box::pin(mount.positions());
+        let target = EquatorialCoords::new(5.5, -5.4);
+        mount.slew_to(target).await?;
+        assert_eq!(mount.coordinates()?, target);
+        assert!(mount.is_tracking()? && mount.pier_side()?.is_some());
+        //at 120° a second the slew from the pole takes over a second
+        let seen: Vec<_> = positions.take_while(|p| futures::future::ready(*p != target)).collect().await;
+        assert!(seen.len() > 5);
+
+        //tracking at the sidereal rate holds the position, the lunar one falls behind
+        mount.set_track_mode(TrackMode::Lunar).await?;
+        tokio::time::sleep(Duration::from_millis(1300)).await;
+        assert!(mount.coordinates()?.ra > target.ra);
+        mount.sync(target).await?;
+        assert_eq!(mount.coordinates()?, target);
+
+        mount.park().await?;
+        assert!(mount.is_parked()? && !mount.is_tracking()?);
+        assert_eq!(mount.coordinates()?.dec, 90.0);
+        assert!(mount.slew_to(target).await.is_err());
+        mount.unpark().await?;
+        assert!(!mount.is_parked()?);
+        Ok(())
+    }
+}